@@ -1,11 +1,17 @@
 import { Route, Router, useNavigate } from '@solidjs/router';
+import { getCurrentWindow } from '@tauri-apps/api/window';
+import { getCurrent, onOpenUrl } from '@tauri-apps/plugin-deep-link';
+import { message } from '@tauri-apps/plugin-dialog';
 import { isPermissionGranted, requestPermission } from '@tauri-apps/plugin-notification';
-import { onMount } from 'solid-js';
+import { onCleanup, onMount } from 'solid-js';
+import { connectFromUri } from './api/connectFromUri';
 import { getLocalIp } from './api/getLocalIp';
 import Home from './routes/home';
 import Login from './routes/login';
 import Setup from './routes/setup';
 import { globalStore, setGlobalStore } from './store/GlobalStore';
+import { handledConnectUri, setHandledConnectUri, setPendingHost } from './store/PersistData';
+import { parseConnectUri } from './utils/connectUri';
 import { getDeviceName } from './utils/getDeviceName';
 
 import './App.css';
@@ -13,8 +19,42 @@ import { isMobile } from './utils/PlatformUtils';
 import { getAuthStatus } from '@sureshot/api/src';
 import { startBackgroundService } from 'tauri-plugin-carbine-notifications';
 
+// QRコードから開かれた接続用URIを処理する
+// ログインできた場合はログイン画面の定期確認でhomeに移り、できなかった場合はホストを選択済みにしてパスワードを待つ
+const openConnectUris = async (urls: string[] | null) => {
+  const url = urls?.find((url) => parseConnectUri(url) !== null);
+  if (!url) return;
+  const uri = parseConnectUri(url)!;
+  // ペアリングコードは一度しか使えないため、他のウィンドウで処理済みなら何もしない
+  if (uri.pairingCode) {
+    if (url === handledConnectUri()) return;
+    setHandledConnectUri(url);
+  }
+
+  try {
+    const { host, isAuthenticated } = await connectFromUri(uri);
+    if (!isAuthenticated) {
+      setPendingHost(host);
+      if (uri.pairingCode) {
+        await message('The pairing code is invalid or expired. Please enter the password.', { title: 'Login Failed', kind: 'error' });
+      }
+    }
+  } catch (error) {
+    const errorMsg = error instanceof Error ? error.message : String(error);
+    await message(`${errorMsg}`, { title: 'Connection Failed', kind: 'error' });
+  }
+};
+
 const App = () => {
+  const unlistenOpenUrl = onOpenUrl(openConnectUris);
+  onCleanup(() => unlistenOpenUrl.then((unlisten) => unlisten()));
+
   onMount(async () => {
+    // 接続用URIから起動された場合（後から開いたウィンドウでは処理しない）
+    if (getCurrentWindow().label === 'setup') {
+      openConnectUris(await getCurrent());
+    }
+
     const localIp = await getLocalIp();
     if (globalStore.localIp !== localIp) {
       setGlobalStore({ localIp });
@@ -0,0 +1,44 @@
+import { HostInfo, login, PongResponse } from '@sureshot/api/src';
+import { globalStore } from '~/store/GlobalStore';
+import { ConnectUri } from '~/utils/connectUri';
+
+/**
+ * 接続用URIのサーバーを確認し、ペアリングコードがあればそれでログインする
+ * @returns ホスト情報と、ログインできたかどうか
+ */
+export const connectFromUri = async (
+  uri: ConnectUri,
+  debugFn?: (message: string) => void
+): Promise<{ host: HostInfo; isAuthenticated: boolean }> => {
+  const log = debugFn || console.log;
+
+  const response = await fetch(`http://${uri.ip}:${uri.port}/ping`, {
+    method: 'GET',
+    signal: AbortSignal.timeout(5000),
+  });
+  if (!response.ok) {
+    throw new Error(`Server responded with status: ${response.status}`);
+  }
+  const pong: PongResponse = await response.json();
+
+  // 同じアドレスで別のサーバーが動いている場合は接続しない
+  if (pong.server_id && pong.server_id !== uri.serverId) {
+    throw new Error(`${uri.ip}:${uri.port} is not the server shown in the QR code`);
+  }
+
+  const host: HostInfo = {
+    ip: uri.ip,
+    port: uri.port,
+    name: pong.name,
+    status: response.status.toString(),
+    message: pong.message,
+    is_self: uri.ip === globalStore.localIp,
+  };
+  if (!uri.pairingCode) {
+    return { host, isAuthenticated: false };
+  }
+
+  log(`Logging in to ${uri.ip}:${uri.port} with a pairing code`);
+  const authStatus = await login(host, '', log, undefined, uri.pairingCode);
+  return { host, isAuthenticated: authStatus.isAuthenticated };
+};
@@ -16,9 +16,23 @@ interface Props {
 const MessageList: Component<Props> = (props) => {
   let scrollList: HTMLDivElement | undefined;
   const [messages, setMessages] = createSignal<ReceivedMessage[] | undefined>(undefined);
+  const replaceMessage = (message: ReceivedMessage) => {
+    setMessages((prev) => prev?.map((m) => (m.id === message.id ? message : m)));
+  };
   const { error, isConnected } = useEventsSource({
+    onEdit: replaceMessage,
+    onPin: replaceMessage,
+    onDelete: ({ id }) => {
+      setMessages((prev) => prev?.filter((m) => m.id !== id));
+    },
+    onResync: () => loadPastMessages(),
     onMessage: (message: ReceivedMessage) => {
       console.log('Received message:', message);
+      // 再接続時の再送で既に表示しているメッセージが届くことがある
+      if (messages()?.some((m) => m.id === message.id)) {
+        replaceMessage(message);
+        return;
+      }
       setMessages((prev) => [...(prev || []), message]);
 
       if (scrollList) {
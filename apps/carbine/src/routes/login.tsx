@@ -1,11 +1,12 @@
 import { HostInfo } from '@sureshot/api/src';
-import { Component, createSignal, onMount, Show } from 'solid-js';
+import { Component, createEffect, createSignal, onMount, Show } from 'solid-js';
 import AppLayout from '~/components/layout/AppLayout';
 import HostSetup from '~/components/setup/HostSetup';
 import LoginForm from '~/components/setup/LoginForm';
 
 import '@styles/main.css';
 import { CarbineAnimLogo } from '@sureshot/ui/src';
+import { pendingHost, setPendingHost } from '~/store/PersistData';
 import { isMobile } from '~/utils/PlatformUtils';
 import { useAuthRedirect } from '~/utils/useAuthRedirect';
 
@@ -27,6 +28,15 @@ const Login: Component = () => {
     setSelectedHost(null);
   });
 
+  // 接続用URIで受け取ったホストはパスワードの入力から始める
+  createEffect(() => {
+    const host = pendingHost();
+    if (!host) return;
+    setSelectedHost(host);
+    setStep(LoginStep.Login);
+    setPendingHost(null);
+  });
+
   const canBack = (toStep: LoginStep) => toStep >= 0 && step() > toStep;
 
   return (
@@ -1,5 +1,6 @@
 import { makePersisted, storageSync } from '@solid-primitives/storage';
 import { tauriStorage } from '@solid-primitives/storage/tauri';
+import { HostInfo } from '@sureshot/api/src';
 import { createSignal } from 'solid-js';
 
 const storage = window.__TAURI_OS_PLUGIN_INTERNALS__ ? tauriStorage() : localStorage;
@@ -9,3 +10,16 @@ export const [nickName, setNickName] = makePersisted(createSignal<string>('new d
   storage: localStorage,
   sync: storageSync,
 });
+
+// 接続用URIで受け取った、パスワードの入力を待っているホスト（ログイン画面で選択済みにする）
+export const [pendingHost, setPendingHost] = makePersisted(createSignal<HostInfo | null>(null), {
+  name: 'pendingHost',
+  storage: localStorage,
+  sync: storageSync,
+});
+
+// 処理済みのペアリングコード付きの接続用URI（複数のウィンドウで同じコードを使わないようにする）
+export const [handledConnectUri, setHandledConnectUri] = makePersisted(createSignal<string | null>(null), {
+  name: 'handledConnectUri',
+  storage: localStorage,
+});
@@ -0,0 +1,39 @@
+// magazineのTUIが表示するQRコードの接続用URI
+// sure-shot://connect?ip=192.168.1.10&port=8000&id=<server_id>[&fp=<TLS fingerprint>][&code=<pairing code>]
+export interface ConnectUri {
+  ip: string;
+  port: number;
+  serverId: string;
+  fingerprint?: string;
+  pairingCode?: string;
+}
+
+const CONNECT_SCHEME = 'sure-shot:';
+
+/**
+ * 接続用URIを解釈する（形式が違う場合はnull）
+ */
+export function parseConnectUri(uri: string): ConnectUri | null {
+  let url: URL;
+  try {
+    url = new URL(uri);
+  } catch {
+    return null;
+  }
+  // sure-shot://connect?... はホスト部分、sure-shot:connect?... はパス部分にconnectが入る
+  const action = url.host || url.pathname.replace(/^\/+/, '');
+  if (url.protocol !== CONNECT_SCHEME || action !== 'connect') return null;
+
+  const ip = url.searchParams.get('ip');
+  const port = Number(url.searchParams.get('port'));
+  const serverId = url.searchParams.get('id');
+  if (!ip || !serverId || !Number.isInteger(port) || port <= 0 || port > 65535) return null;
+
+  return {
+    ip,
+    port,
+    serverId,
+    fingerprint: url.searchParams.get('fp') ?? undefined,
+    pairingCode: url.searchParams.get('code') ?? undefined,
+  };
+}
@@ -0,0 +1,54 @@
+import { AuthManager } from '../../auth/AuthManager';
+import { ApiError, ErrorCode, ImportResponse } from '../../types/generated/api-types';
+import { readApiError } from '../errors';
+
+// 書き出す期間（YYYY-MM-DDかRFC 3339。省略した側は制限なし）
+export interface ExportRange {
+  from?: string;
+  to?: string;
+}
+
+const failed = async (response: Response, action: string): Promise<ApiError> =>
+  (await readApiError(response)) ?? {
+    code: ErrorCode.Internal,
+    message: `Failed to ${action} with status: ${response.status}`,
+  };
+
+// メッセージを添付ファイルごとtarファイルとして受け取る
+export const exportArchive = async (range: ExportRange = {}): Promise<Blob | ApiError> => {
+  const authManager = AuthManager.getInstance();
+  const params = new URLSearchParams();
+  if (range.from) {
+    params.set('from', range.from);
+  }
+  if (range.to) {
+    params.set('to', range.to);
+  }
+
+  const response = await fetch(`${authManager.getBaseUrl()}/admin/export?${params.toString()}`, {
+    headers: authManager.getAuthHeaders(),
+  });
+  return response.ok ? await response.blob() : await failed(response, 'export messages');
+};
+
+// 書き出したアーカイブを読み込む（同じIDのメッセージが既にあれば飛ばされる）
+export const importArchive = async (archive: Blob): Promise<ImportResponse | ApiError> => {
+  const authManager = AuthManager.getInstance();
+
+  const response = await fetch(`${authManager.getBaseUrl()}/admin/import`, {
+    method: 'POST',
+    headers: { ...authManager.getAuthHeaders(), 'Content-Type': 'application/octet-stream' },
+    body: archive,
+  });
+  return response.ok ? await response.json() : await failed(response, 'import messages');
+};
+
+// サーバーを止めずに取ったデータベースのバックアップを受け取る
+export const downloadBackup = async (): Promise<Blob | ApiError> => {
+  const authManager = AuthManager.getInstance();
+
+  const response = await fetch(`${authManager.getBaseUrl()}/admin/backup`, {
+    headers: authManager.getAuthHeaders(),
+  });
+  return response.ok ? await response.blob() : await failed(response, 'download backup');
+};
@@ -1,7 +1,16 @@
 import { AuthManager, AuthStatus } from '../../auth/AuthManager';
-import { HostInfo } from '../../types/generated/api-types';
+import { AuthRequest, ErrorCode, HostInfo } from '../../types/generated/api-types';
+import { readApiError } from '../errors';
 
-export async function login(hostInfo: HostInfo, password: string, debugFn?: (message: string) => void): Promise<AuthStatus> {
+// deviceを渡すと、サーバーの接続中クライアント一覧に端末名で表示される
+// pairingCodeを渡すとパスワードの代わりに使う（接続用QRコードに含まれる一度だけ使えるコード）
+export async function login(
+  hostInfo: HostInfo,
+  password: string,
+  debugFn?: (message: string) => void,
+  device?: { id: string; name: string },
+  pairingCode?: string
+): Promise<AuthStatus> {
   const log = debugFn || console.log;
   const authManager = AuthManager.getInstance();
 
@@ -20,8 +29,11 @@ export async function login(hostInfo: HostInfo, password: string, debugFn?: (mes
     const url = `http://${hostInfo.ip}:${hostInfo.port}/auth/login`;
     // log(`Making fetch request to: ${url}`);
 
-    const requestBody = {
+    const requestBody: AuthRequest = {
       password,
+      pairing_code: pairingCode,
+      device_id: device?.id,
+      device_name: device?.name,
     };
     // log(`Request body: ${JSON.stringify(requestBody)}`);
 
@@ -33,26 +45,21 @@ export async function login(hostInfo: HostInfo, password: string, debugFn?: (mes
 
     // log(`Fetch response received: status=${response.status}, statusText=${response.statusText}, ok=${response.ok}`);
 
-    // HTTPステータスコードベースでエラーハンドリング
-    if (response.status === 401) {
-      log('Authentication failed: Invalid password');
-      authManager.setAuthStatus({
-        ...failedStatus,
-        lastError: {
-          type: 'auth',
-          message: 'Invalid password',
-        },
-      });
-      return failedStatus;
-    }
-
+    // エラーコードで判定する
     if (!response.ok) {
-      log(`Authentication failed with status: ${response.status}`);
+      const error = await readApiError(response);
+      const message =
+        error?.code === ErrorCode.InvalidPassword
+          ? 'Invalid password'
+          : error?.code === ErrorCode.InvalidPairingCode
+            ? 'Invalid or expired pairing code'
+            : (error?.message ?? `Authentication failed with status: ${response.status}`);
+      log(`Authentication failed: ${message}`);
       authManager.setAuthStatus({
         ...failedStatus,
         lastError: {
           type: 'auth',
-          message: `Authentication failed with status: ${response.status}`,
+          message,
         },
       });
       return failedStatus;
@@ -0,0 +1,21 @@
+import { AuthManager } from '../../auth/AuthManager';
+import { ClientInfo } from '../../types/generated/api-types';
+
+export const getClients = async (): Promise<ClientInfo[] | undefined> => {
+  try {
+    const authManager = AuthManager.getInstance();
+
+    const response = await fetch(`${authManager.getBaseUrl()}/clients`, {
+      headers: authManager.getAuthHeaders(),
+    });
+
+    if (response.ok) {
+      const clients: ClientInfo[] = await response.json();
+      return clients;
+    }
+  } catch (error) {
+    console.error('Failed to load clients:', error);
+  }
+
+  return undefined;
+};
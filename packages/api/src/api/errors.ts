@@ -0,0 +1,18 @@
+import { ApiError, ErrorCode } from '../types/generated/api-types';
+
+// エラーレスポンスの本体を読む（ApiError形式でなければundefined）
+export const readApiError = async (response: Response): Promise<ApiError | undefined> => {
+  try {
+    const body = await response.json();
+    if (body && typeof body.code === 'string' && typeof body.message === 'string') {
+      return body as ApiError;
+    }
+  } catch {
+    // 本体がJSONでない
+  }
+  return undefined;
+};
+
+// トークンの再取得が必要なエラーか
+export const isAuthError = (error: ApiError | undefined): boolean =>
+  error?.code === ErrorCode.TokenRequired || error?.code === ErrorCode.InvalidToken;
@@ -0,0 +1,96 @@
+import { AuthManager } from '../../auth/AuthManager';
+import {
+  ClientFrame,
+  FrameError,
+  SendAck,
+  SendMessageRequest,
+  ServerFrame,
+  StreamEvent,
+} from '../../types/generated/api-types';
+
+interface Handlers {
+  onEvent: (event: StreamEvent) => void;
+  onError?: (error: FrameError) => void;
+  onClose?: () => void;
+}
+
+export interface EventSocket {
+  // 送信してサーバーのackを待つ（/sendと同じ経路で保存・配信される）
+  send: (message: SendMessageRequest) => Promise<SendAck>;
+  setTyping: (fromName: string, typing: boolean) => void;
+  close: () => void;
+}
+
+// /wsに接続する（SSEの代わりに双方向で使う場合）
+export const connectEventSocket = (handlers: Handlers, lastEventId?: number): EventSocket => {
+  const authManager = AuthManager.getInstance();
+
+  // WebSocketはヘッダーを付けられないため、トークンと最後のイベントIDはクエリで渡す
+  const params = new URLSearchParams();
+  const token = authManager.getToken();
+  if (token) {
+    params.set('token', token);
+  }
+  if (lastEventId !== undefined) {
+    params.set('last_event_id', String(lastEventId));
+  }
+  const url = `${authManager.getBaseUrl().replace(/^http/, 'ws')}/ws?${params.toString()}`;
+  const socket = new WebSocket(url);
+
+  const pendingAcks = new Map<string, { resolve: (ack: SendAck) => void; reject: (error: Error) => void }>();
+  const queued: string[] = [];
+
+  const sendFrame = (frame: ClientFrame) => {
+    const data = JSON.stringify(frame);
+    if (socket.readyState === WebSocket.OPEN) {
+      socket.send(data);
+    } else {
+      queued.push(data);
+    }
+  };
+
+  socket.addEventListener('open', () => {
+    queued.splice(0).forEach((data) => socket.send(data));
+  });
+  socket.addEventListener('message', (event: MessageEvent) => {
+    try {
+      const frame: ServerFrame = JSON.parse(event.data);
+      switch (frame.type) {
+        case 'event':
+          handlers.onEvent(frame.data);
+          break;
+        case 'ack':
+          pendingAcks.get(frame.data.request_id)?.resolve(frame.data);
+          pendingAcks.delete(frame.data.request_id);
+          break;
+        case 'error':
+          if (frame.data.request_id) {
+            pendingAcks.get(frame.data.request_id)?.reject(new Error(frame.data.message));
+            pendingAcks.delete(frame.data.request_id);
+          }
+          handlers.onError?.(frame.data);
+          break;
+        case 'pong':
+          break;
+      }
+    } catch (error) {
+      console.error('Failed to parse WebSocket frame:', error);
+    }
+  });
+  socket.addEventListener('close', () => {
+    pendingAcks.forEach(({ reject }) => reject(new Error('Connection closed')));
+    pendingAcks.clear();
+    handlers.onClose?.();
+  });
+
+  return {
+    send: (message) =>
+      new Promise((resolve, reject) => {
+        const requestId = crypto.randomUUID();
+        pendingAcks.set(requestId, { resolve, reject });
+        sendFrame({ type: 'send', data: { request_id: requestId, message } });
+      }),
+    setTyping: (fromName, typing) => sendFrame({ type: 'typing', data: { from_name: fromName, typing } }),
+    close: () => socket.close(),
+  };
+};
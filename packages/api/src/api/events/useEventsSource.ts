@@ -1,9 +1,27 @@
 import { Accessor, createSignal, onCleanup, onMount } from 'solid-js';
 import { AuthManager } from '../../auth/AuthManager';
-import { ReceivedMessage } from '../../types/generated/api-types';
+import {
+  LaggedEvent,
+  MessageDeleted,
+  PresenceEvent,
+  ReceivedMessage,
+  ServerShuttingDownEvent,
+  ServerStatusEvent,
+  TypingEvent,
+} from '../../types/generated/api-types';
 
 interface Props {
   onMessage: (message: ReceivedMessage) => void;
+  onEdit?: (message: ReceivedMessage) => void;
+  onDelete?: (deleted: MessageDeleted) => void;
+  onPin?: (message: ReceivedMessage) => void;
+  onServerStatus?: (status: ServerStatusEvent) => void;
+  onTyping?: (typing: TypingEvent) => void;
+  onPresence?: (presence: PresenceEvent) => void;
+  // サーバーが停止する直前に呼ばれる（この後接続は閉じられる）
+  onServerShuttingDown?: (shutdown: ServerShuttingDownEvent) => void;
+  // 再送できないほど取りこぼした場合に呼ばれる（/messagesから取り直す）
+  onResync?: () => void;
 }
 
 export function useEventsSource(props: Props): {
@@ -11,6 +29,8 @@ export function useEventsSource(props: Props): {
   isConnected: Accessor<boolean | undefined>;
 } {
   let eventSource: EventSource | null = null;
+  // 再接続時にサーバーへ伝える最後のイベントID
+  let lastEventId: string | undefined = undefined;
   const [connectionError, setConnectionError] = createSignal<string | undefined>(undefined);
   const [isConnected, setIsConnected] = createSignal<boolean | undefined>(undefined);
 
@@ -18,7 +38,13 @@ export function useEventsSource(props: Props): {
     setIsConnected(true);
     setConnectionError(undefined);
   };
+  const trackEventId = (event: MessageEvent) => {
+    if (event.lastEventId) {
+      lastEventId = event.lastEventId;
+    }
+  };
   const onEventSourceMessage = (event: MessageEvent) => {
+    trackEventId(event);
     try {
       const message: ReceivedMessage = JSON.parse(event.data);
       props.onMessage(message);
@@ -26,6 +52,33 @@ export function useEventsSource(props: Props): {
       console.error('Failed to parse SSE message:', error);
     }
   };
+  const parseEvent =
+    <T>(handler?: (data: T) => void) =>
+    (event: MessageEvent) => {
+      trackEventId(event);
+      try {
+        handler?.(JSON.parse(event.data));
+      } catch (error) {
+        console.error('Failed to parse SSE event:', error);
+      }
+    };
+  const onEventSourceEdit = parseEvent(props.onEdit);
+  const onEventSourceDelete = parseEvent(props.onDelete);
+  const onEventSourcePin = parseEvent(props.onPin);
+  const onEventSourceServerStatus = parseEvent(props.onServerStatus);
+  const onEventSourceTyping = parseEvent(props.onTyping);
+  const onEventSourcePresence = parseEvent(props.onPresence);
+  const onEventSourceServerShuttingDown = parseEvent<ServerShuttingDownEvent>((shutdown) => {
+    setIsConnected(false);
+    setConnectionError('Server is shutting down.');
+    props.onServerShuttingDown?.(shutdown);
+  });
+  const onEventSourceLagged = parseEvent<LaggedEvent>((lagged) => {
+    console.warn('SSE lagged:', lagged);
+    if (lagged.resync) {
+      props.onResync?.();
+    }
+  });
   const onEventSourceError = (error: Event) => {
     console.error('SSE error:', error);
     setIsConnected(false);
@@ -39,6 +92,14 @@ export function useEventsSource(props: Props): {
       if (eventSource) {
         eventSource.removeEventListener('open', onEventSourceOpen);
         eventSource.removeEventListener('message', onEventSourceMessage);
+        eventSource.removeEventListener('edit', onEventSourceEdit);
+        eventSource.removeEventListener('delete', onEventSourceDelete);
+        eventSource.removeEventListener('pin', onEventSourcePin);
+        eventSource.removeEventListener('server-status', onEventSourceServerStatus);
+        eventSource.removeEventListener('lagged', onEventSourceLagged);
+        eventSource.removeEventListener('typing', onEventSourceTyping);
+        eventSource.removeEventListener('presence', onEventSourcePresence);
+        eventSource.removeEventListener('server-shutting-down', onEventSourceServerShuttingDown);
         eventSource.removeEventListener('error', onEventSourceError);
         eventSource.close();
       }
@@ -48,12 +109,29 @@ export function useEventsSource(props: Props): {
         throw new Error('Not authenticated');
       }
 
-      const eventsUrl = `${authManager.getBaseUrl()}/events`;
+      // EventSourceはヘッダーを付けられないため、トークンと最後のイベントIDはクエリで渡す
+      const params = new URLSearchParams();
+      const token = authManager.getToken();
+      if (token) {
+        params.set('token', token);
+      }
+      if (lastEventId) {
+        params.set('last_event_id', lastEventId);
+      }
+      const eventsUrl = `${authManager.getBaseUrl()}/events?${params.toString()}`;
       eventSource = new EventSource(eventsUrl);
 
       eventSource.addEventListener('open', onEventSourceOpen);
 
       eventSource.addEventListener('message', onEventSourceMessage);
+      eventSource.addEventListener('edit', onEventSourceEdit);
+      eventSource.addEventListener('delete', onEventSourceDelete);
+      eventSource.addEventListener('pin', onEventSourcePin);
+      eventSource.addEventListener('server-status', onEventSourceServerStatus);
+      eventSource.addEventListener('lagged', onEventSourceLagged);
+      eventSource.addEventListener('typing', onEventSourceTyping);
+      eventSource.addEventListener('presence', onEventSourcePresence);
+      eventSource.addEventListener('server-shutting-down', onEventSourceServerShuttingDown);
 
       eventSource.addEventListener('error', onEventSourceError);
     } catch (error) {
@@ -0,0 +1,62 @@
+import { AuthManager } from '../../auth/AuthManager';
+import { MessageEdit, ReceivedMessage } from '../../types/generated/api-types';
+
+const messageUrl = (id: string) => `${AuthManager.getInstance().getBaseUrl()}/messages/${encodeURIComponent(id)}`;
+
+export const deleteMessage = async (id: string): Promise<boolean> => {
+  try {
+    const response = await fetch(messageUrl(id), {
+      method: 'DELETE',
+      headers: AuthManager.getInstance().getAuthHeaders(),
+    });
+    return response.ok;
+  } catch (error) {
+    console.error('Failed to delete message:', error);
+    return false;
+  }
+};
+
+export const editMessage = async (id: string, message: string): Promise<ReceivedMessage | undefined> => {
+  try {
+    const response = await fetch(messageUrl(id), {
+      method: 'PATCH',
+      headers: AuthManager.getInstance().getAuthHeaders(),
+      body: JSON.stringify({ message }),
+    });
+    if (response.ok) {
+      return await response.json();
+    }
+  } catch (error) {
+    console.error('Failed to edit message:', error);
+  }
+  return undefined;
+};
+
+export const setMessagePinned = async (id: string, pinned: boolean): Promise<ReceivedMessage | undefined> => {
+  try {
+    const response = await fetch(`${messageUrl(id)}/pin`, {
+      method: pinned ? 'POST' : 'DELETE',
+      headers: AuthManager.getInstance().getAuthHeaders(),
+    });
+    if (response.ok) {
+      return await response.json();
+    }
+  } catch (error) {
+    console.error('Failed to update pin:', error);
+  }
+  return undefined;
+};
+
+export const getEditHistory = async (id: string): Promise<MessageEdit[] | undefined> => {
+  try {
+    const response = await fetch(`${messageUrl(id)}/history`, {
+      headers: AuthManager.getInstance().getAuthHeaders(),
+    });
+    if (response.ok) {
+      return await response.json();
+    }
+  } catch (error) {
+    console.error('Failed to load edit history:', error);
+  }
+  return undefined;
+};
@@ -0,0 +1,43 @@
+import { AuthManager } from '../../auth/AuthManager';
+import { ApiError, ErrorCode, ServerSettings, SettingsUpdate } from '../../types/generated/api-types';
+import { readApiError } from '../errors';
+
+export const getSettings = async (): Promise<ServerSettings | undefined> => {
+  try {
+    const authManager = AuthManager.getInstance();
+
+    const response = await fetch(`${authManager.getBaseUrl()}/settings`, {
+      headers: authManager.getAuthHeaders(),
+    });
+
+    if (response.ok) {
+      return await response.json();
+    }
+  } catch (error) {
+    console.error('Failed to load settings:', error);
+  }
+
+  return undefined;
+};
+
+// パスワードを変更する場合はcurrent_passwordも必要
+export const updateSettings = async (update: SettingsUpdate): Promise<ServerSettings | ApiError> => {
+  const authManager = AuthManager.getInstance();
+
+  const response = await fetch(`${authManager.getBaseUrl()}/settings`, {
+    method: 'PATCH',
+    headers: authManager.getAuthHeaders(),
+    body: JSON.stringify(update),
+  });
+
+  if (response.ok) {
+    return await response.json();
+  }
+
+  return (
+    (await readApiError(response)) ?? {
+      code: ErrorCode.Internal,
+      message: `Failed to update settings with status: ${response.status}`,
+    }
+  );
+};
@@ -1,9 +1,24 @@
 // Message APIs
 export { getMessages } from './api/messages/get';
 export { sendMessage } from './api/messages/send';
+export { deleteMessage, editMessage, getEditHistory, setMessagePinned } from './api/messages/manage';
+
+// Settings APIs
+export { getSettings, updateSettings } from './api/settings/manage';
+
+// Admin APIs
+export { downloadBackup, exportArchive, importArchive, type ExportRange } from './api/admin/archive';
+
+// Presence APIs
+export { getClients } from './api/clients/get';
 
 // Event Streaming APIs
 export { useEventsSource } from './api/events/useEventsSource';
+export { connectEventSocket, type EventSocket } from './api/events/socket';
+
+// Error handling
+export { isAuthError, readApiError } from './api/errors';
+export { ErrorCode } from './types/generated/api-types';
 
 // Authentication APIs
 export { getAuthStatus, login, logout } from './api/auth/login';
@@ -2,6 +2,25 @@
  Generated by typeshare 1.13.3
 */
 
+export enum ErrorCode {
+	TokenRequired = "token_required",
+	InvalidToken = "invalid_token",
+	InvalidPassword = "invalid_password",
+	InvalidPairingCode = "invalid_pairing_code",
+	Forbidden = "forbidden",
+	NotFound = "not_found",
+	BadRequest = "bad_request",
+	PayloadTooLarge = "payload_too_large",
+	Database = "database",
+	Internal = "internal",
+}
+
+export interface ApiError {
+	code: ErrorCode;
+	message: string;
+	details?: string;
+}
+
 export interface Attachment {
 	id: string;
 	filename: string;
@@ -12,7 +31,10 @@ export interface Attachment {
 }
 
 export interface AuthRequest {
-	password: string;
+	password?: string;
+	pairing_code?: string;
+	device_id?: string;
+	device_name?: string;
 }
 
 export interface AuthResponse {
@@ -21,6 +43,30 @@ export interface AuthResponse {
 	token?: string;
 }
 
+export interface ClientInfo {
+	device_id: string;
+	device_name: string;
+	ip: string;
+	transports: string[];
+	connected_since: string;
+	last_activity: string;
+}
+
+export interface EditMessageRequest {
+	message: string;
+}
+
+export interface FrameError {
+	request_id?: string;
+	message: string;
+}
+
+export interface HealthResponse {
+	ok: boolean;
+	database: boolean;
+	listener: boolean;
+}
+
 export interface HostInfo {
 	ip: string;
 	port: number;
@@ -30,13 +76,40 @@ export interface HostInfo {
 	is_self: boolean;
 }
 
+export interface ImportResponse {
+	imported: number;
+	skipped: number;
+}
+
+export interface LaggedEvent {
+	missed: number;
+	resync: boolean;
+}
+
+export interface MessageDeleted {
+	id: string;
+}
+
+export interface MessageEdit {
+	message_id: string;
+	previous_message: string;
+	edited_at: string;
+}
+
 export interface PongResponse {
 	message: string;
 	name: string;
 	is_self: boolean;
+	server_id?: string;
+}
+
+export interface PresenceEvent {
+	client: ClientInfo;
+	online: boolean;
 }
 
 export interface ReceivedMessage {
+	id?: string;
 	from: string;
 	from_name: string;
 	message: string;
@@ -44,6 +117,20 @@ export interface ReceivedMessage {
 	timestamp: string;
 	is_self: boolean;
 	attachments: Attachment[];
+	pinned?: boolean;
+	edited_at?: string;
+}
+
+export interface SendMessageResponse {
+	success: boolean;
+	message: string;
+	timestamp: string;
+}
+
+export interface SendAck {
+	request_id: string;
+	message_id: string;
+	response: SendMessageResponse;
 }
 
 export interface SendMessageRequest {
@@ -54,9 +141,66 @@ export interface SendMessageRequest {
 	from_ip: string;
 }
 
-export interface SendMessageResponse {
-	success: boolean;
-	message: string;
-	timestamp: string;
+export interface ServerSettings {
+	nickname: string;
+	show_requests: boolean;
+	show_responses: boolean;
+	quiet_endpoints: string[];
+}
+
+export interface ServerShuttingDownEvent {
+	name: string;
+}
+
+export interface ServerStatusEvent {
+	name: string;
+	state: string;
+}
+
+export interface SettingsUpdate {
+	nickname?: string;
+	password?: string;
+	current_password?: string;
+	revoke_tokens?: boolean;
+	show_requests?: boolean;
+	show_responses?: boolean;
+	quiet_endpoints?: string[];
+}
+
+export type ServerEvent = 
+	| { type: "message", data: ReceivedMessage }
+	| { type: "edit", data: ReceivedMessage }
+	| { type: "delete", data: MessageDeleted }
+	| { type: "pin", data: ReceivedMessage }
+	| { type: "server_status", data: ServerStatusEvent }
+	| { type: "lagged", data: LaggedEvent }
+	| { type: "typing", data: TypingEvent }
+	| { type: "presence", data: PresenceEvent }
+	| { type: "server_shutting_down", data: ServerShuttingDownEvent };
+
+export interface StreamEvent {
+	id?: number;
+	event: ServerEvent;
 }
 
+export interface TypingEvent {
+	from_name: string;
+	typing: boolean;
+}
+
+export interface WsSendRequest {
+	request_id: string;
+	message: SendMessageRequest;
+}
+
+export type ClientFrame = 
+	| { type: "send", data: WsSendRequest }
+	| { type: "typing", data: TypingEvent }
+	| { type: "ping", data?: undefined };
+
+export type ServerFrame = 
+	| { type: "event", data: StreamEvent }
+	| { type: "ack", data: SendAck }
+	| { type: "error", data: FrameError }
+	| { type: "pong", data?: undefined };
+
@@ -135,6 +135,8 @@ class BackgroundNotificationService : Service() {
         }
     }
     
+    private var currentEvent = "message"
+
     private suspend fun connectToSSE() {
         try {
             val url = URL("$serverUrl/events")
@@ -173,8 +175,19 @@ class BackgroundNotificationService : Service() {
     
     private fun processSSELine(line: String) {
         Log.d(TAG, "Received SSE line: $line")
-        
-        if (line.startsWith("data: ")) {
+
+        // イベント名を記録（空行でイベントが区切られる）
+        if (line.startsWith("event:")) {
+            currentEvent = line.substringAfter(":").trim()
+            return
+        }
+        if (line.isEmpty()) {
+            currentEvent = "message"
+            return
+        }
+
+        // 新着メッセージ以外（編集・削除・ステータスなど）は通知しない
+        if (line.startsWith("data: ") && currentEvent == "message") {
             try {
                 val jsonData = line.substring(6) // "data: " を除去
                 Log.d(TAG, "Processing message data: $jsonData")
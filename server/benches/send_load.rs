@@ -0,0 +1,182 @@
+// /sendと/messagesに並行して負荷をかけ、スループットとレイテンシを計測する
+//
+//   cargo bench --bench send_load -- [同時接続数] [秒数] [添付ファイルのKB]
+//
+// 一時ディレクトリのDBを使うため、実際のデータには影響しない
+use server::{
+    AppState, AuthResponse, SendMessageRequest, ServerConfig, external::create_external_router,
+    message_store::MessageStore, metrics::Metrics, presence::PresenceRegistry,
+    repository::CachedRepository,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, broadcast};
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let clients: usize = args.first().and_then(|v| v.parse().ok()).unwrap_or(16);
+    let seconds: u64 = args.get(1).and_then(|v| v.parse().ok()).unwrap_or(10);
+    let attachment_kb: usize = args.get(2).and_then(|v| v.parse().ok()).unwrap_or(32);
+
+    let db_path = std::env::temp_dir().join(format!("sure-shot-bench-{}.db", std::process::id()));
+    let addr = start_server(db_path.clone()).await;
+    let base_url = format!("http://{}", addr);
+
+    let http = reqwest::Client::new();
+    let token = http
+        .post(format!("{}/auth/login", base_url))
+        .json(&serde_json::json!({ "password": "admin" }))
+        .send()
+        .await
+        .expect("login failed")
+        .json::<AuthResponse>()
+        .await
+        .expect("invalid login response")
+        .token
+        .expect("no token");
+
+    println!(
+        "clients: {} ({} senders / {} readers), duration: {}s, attachment: {}KB",
+        clients,
+        clients.div_ceil(2),
+        clients / 2,
+        seconds,
+        attachment_kb
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let mut tasks = Vec::new();
+    for index in 0..clients {
+        let http = http.clone();
+        let base_url = base_url.clone();
+        let token = token.clone();
+        // 半分は送信、半分は一覧取得
+        let sending = index % 2 == 0;
+        tasks.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            let mut errors = 0usize;
+            while Instant::now() < deadline {
+                let start = Instant::now();
+                let request = if sending {
+                    http.post(format!("{}/send", base_url))
+                        .json(&message(index, attachment_kb))
+                } else {
+                    http.get(format!("{}/messages", base_url))
+                };
+                match request.bearer_auth(&token).send().await {
+                    Ok(response) if response.status().is_success() => {
+                        let _ = response.bytes().await;
+                        latencies.push(start.elapsed());
+                    }
+                    _ => errors += 1,
+                }
+            }
+            (sending, latencies, errors)
+        }));
+    }
+
+    let mut send = Stats::default();
+    let mut read = Stats::default();
+    for task in tasks {
+        let (sending, latencies, errors) = task.await.expect("client task panicked");
+        let stats = if sending { &mut send } else { &mut read };
+        stats.latencies.extend(latencies);
+        stats.errors += errors;
+    }
+
+    send.print("POST /send", seconds);
+    read.print("GET /messages", seconds);
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", db_path.display(), suffix));
+    }
+}
+
+async fn start_server(db_path: std::path::PathBuf) -> SocketAddr {
+    let (message_broadcaster, _) = broadcast::channel(100);
+    let store = Arc::new(MessageStore::new(Some(db_path)).expect("failed to open store"));
+    let app_state = AppState {
+        messages: Arc::new(CachedRepository::new(store.clone(), 100)),
+        message_broadcaster,
+        config: Arc::new(Mutex::new(ServerConfig::default())),
+        log_sender: None,
+        message_store: store,
+        publish_lock: Arc::new(Mutex::new(())),
+        presence: Arc::new(PresenceRegistry::new()),
+        metrics: Arc::new(Metrics::new()),
+        listening: Arc::new(AtomicBool::new(true)),
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind");
+    let addr = listener.local_addr().expect("no local address");
+    let app = create_external_router(app_state);
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .expect("server error");
+    });
+    addr
+}
+
+fn message(index: usize, attachment_kb: usize) -> SendMessageRequest {
+    let attachments = if attachment_kb > 0 {
+        vec![server::Attachment {
+            id: uuid::Uuid::new_v4().to_string(),
+            filename: "bench.bin".to_string(),
+            mime_type: "application/octet-stream".to_string(),
+            size: (attachment_kb * 1024) as u64,
+            data: "A".repeat(attachment_kb * 1024 * 4 / 3),
+            thumbnail: None,
+        }]
+    } else {
+        Vec::new()
+    };
+
+    SendMessageRequest {
+        message: format!("benchmark message from client {}", index),
+        message_type: "text".to_string(),
+        attachments,
+        from_name: format!("bench-{}", index),
+        from_ip: "127.0.0.1".to_string(),
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+impl Stats {
+    fn print(&mut self, label: &str, seconds: u64) {
+        self.latencies.sort();
+        let percentile = |p: f64| {
+            self.latencies
+                .get(
+                    ((self.latencies.len() as f64 * p) as usize)
+                        .min(self.latencies.len().saturating_sub(1)),
+                )
+                .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+        };
+        println!(
+            "{:<14} {:>8.1} req/s  p50 {:>7.2}ms  p95 {:>7.2}ms  p99 {:>7.2}ms  errors {}",
+            label,
+            self.latencies.len() as f64 / seconds as f64,
+            percentile(0.50),
+            percentile(0.95),
+            percentile(0.99),
+            self.errors
+        );
+    }
+}
@@ -0,0 +1,530 @@
+use crate::{
+    Attachment, ImportResponse, ReceivedMessage,
+    error::{ServerError, ServerResult},
+    message_store::MessageStore,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+// アーカイブの形式のバージョン（新しい形式のアーカイブは読み込まない）
+pub const ARCHIVE_VERSION: u32 = 1;
+
+// アーカイブ内のファイル
+// manifest.json            書き出した日時や件数
+// messages.jsonl           1行1メッセージ（添付ファイルのデータは空にしてある）
+// attachments/<id>/<n>     メッセージ<id>のn番目の添付ファイルの中身
+const MANIFEST_PATH: &str = "manifest.json";
+const MESSAGES_PATH: &str = "messages.jsonl";
+const ATTACHMENTS_DIR: &str = "attachments";
+
+// 期間を指定しない場合の範囲（保存している日時はUTCのRFC 3339なので文字列で比較できる）
+const RANGE_START: &str = "";
+const RANGE_END: &str = "9999-12-31T23:59:59.999999999+00:00";
+
+// tarのブロックサイズ（先頭のヘッダーで形式を見分ける）
+const BLOCK_SIZE: usize = 512;
+
+// アーカイブの内容の説明
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveManifest {
+    pub version: u32,
+    pub server_name: String,
+    pub exported_at: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub messages: usize,
+    pub attachments: usize,
+}
+
+// 書き出す期間（両端を含む。省略した側は制限なし）
+#[derive(Clone, Debug, Default)]
+pub struct DateRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    // "2025-01-31"のような日付（その日のローカル時刻の始まりか終わり）か、RFC 3339の日時を受け付ける
+    pub fn parse(from: Option<&str>, to: Option<&str>) -> ServerResult<Self> {
+        let range = Self {
+            from: from.map(|value| parse_bound(value, false)).transpose()?,
+            to: to.map(|value| parse_bound(value, true)).transpose()?,
+        };
+        if let (Some(from), Some(to)) = (range.from, range.to)
+            && from > to
+        {
+            return Err(ServerError::BadRequest(
+                "The start of the range is after the end".to_string(),
+            ));
+        }
+        Ok(range)
+    }
+}
+
+fn parse_bound(value: &str, end: bool) -> ServerResult<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let invalid = || {
+        ServerError::BadRequest(format!(
+            "Invalid date: {} (expected YYYY-MM-DD or RFC 3339)",
+            value
+        ))
+    };
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+    let time: NaiveDateTime = match end {
+        true => date.and_hms_nano_opt(23, 59, 59, 999_999_999),
+        false => date.and_hms_opt(0, 0, 0),
+    }
+    .ok_or_else(invalid)?;
+    time.and_local_timezone(Local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(invalid)
+}
+
+// 指定した期間のメッセージ（期間の指定がなければ全てを保存順に）
+pub async fn messages_in_range(
+    store: &MessageStore,
+    range: &DateRange,
+) -> ServerResult<Vec<ReceivedMessage>> {
+    match (range.from, range.to) {
+        (None, None) => store.get_all_messages().await,
+        (from, to) => {
+            let start = from.map_or(RANGE_START.to_string(), |time| time.to_rfc3339());
+            let end = to.map_or(RANGE_END.to_string(), |time| time.to_rfc3339());
+            store.get_messages_by_date_range(&start, &end, None).await
+        }
+    }
+}
+
+// 指定した期間のメッセージを添付ファイルごと1つのtarファイルに書き出す
+// 添付ファイルを含むと大きくなるため、メモリではなくdirの一時ファイルに書く（破棄すると消える）
+pub async fn export_archive(
+    store: &MessageStore,
+    server_name: &str,
+    range: &DateRange,
+    dir: PathBuf,
+) -> ServerResult<(NamedTempFile, ArchiveManifest)> {
+    let messages = messages_in_range(store, range).await?;
+
+    let manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        server_name: server_name.to_string(),
+        exported_at: Utc::now().to_rfc3339(),
+        from: range.from.map(|time| time.to_rfc3339()),
+        to: range.to.map(|time| time.to_rfc3339()),
+        messages: messages.len(),
+        attachments: 0,
+    };
+    // 添付ファイルのデコードとtarへの書き込みは重いためランタイムの外で行う
+    tokio::task::spawn_blocking(move || write_archive(manifest, messages, &dir)).await?
+}
+
+fn write_archive(
+    mut manifest: ArchiveManifest,
+    messages: Vec<ReceivedMessage>,
+    dir: &Path,
+) -> ServerResult<(NamedTempFile, ArchiveManifest)> {
+    // 件数を入れたmanifestを先頭に置くため、本文と添付ファイルの中身はいったん別の一時ファイルに書く
+    let mut lines = BufWriter::new(tempfile::tempfile_in(dir)?);
+    let mut blobs = BufWriter::new(tempfile::tempfile_in(dir)?);
+    let mut blob_entries = Vec::new();
+    for mut message in messages {
+        for (index, attachment) in message.attachments.iter_mut().enumerate() {
+            // 保持ポリシーで中身を削除した添付ファイルはそのまま
+            if attachment.data.is_empty() {
+                continue;
+            }
+            // Base64として読めないデータはJSONの中にそのまま残す
+            let Ok(bytes) = BASE64.decode(&attachment.data) else {
+                continue;
+            };
+            blobs.write_all(&bytes)?;
+            blob_entries.push((attachment_path(&message.id, index), bytes.len() as u64));
+            attachment.data.clear();
+        }
+        serde_json::to_writer(&mut lines, &message)?;
+        lines.write_all(b"\n")?;
+    }
+    manifest.attachments = blob_entries.len();
+    let mut lines = rewind(lines)?;
+    let mut blobs = rewind(blobs)?;
+
+    let archive = tempfile::Builder::new()
+        .prefix("export-")
+        .suffix(".tar")
+        .tempfile_in(dir)?;
+    let mtime = Utc::now().timestamp().max(0) as u64;
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+    let mut tar = tar::Builder::new(BufWriter::new(archive.as_file()));
+    append_file(
+        &mut tar,
+        MANIFEST_PATH,
+        manifest_json.len() as u64,
+        manifest_json.as_slice(),
+        mtime,
+    )?;
+    let lines_size = lines.metadata()?.len();
+    append_file(&mut tar, MESSAGES_PATH, lines_size, &mut lines, mtime)?;
+    for (path, size) in &blob_entries {
+        append_file(&mut tar, path, *size, (&mut blobs).take(*size), mtime)?;
+    }
+    tar.into_inner()?.flush()?;
+    Ok((archive, manifest))
+}
+
+// 書き終えた一時ファイルを先頭から読めるようにする
+fn rewind(writer: BufWriter<File>) -> ServerResult<File> {
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+fn append_file(
+    tar: &mut tar::Builder<impl Write>,
+    path: &str,
+    size: u64,
+    data: impl Read,
+    mtime: u64,
+) -> ServerResult<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_entry_type(tar::EntryType::Regular);
+    tar.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+// アーカイブ（またはJSON Linesで書き出したファイル）のメッセージを保存する
+// 既にあるメッセージ（IDが同じもの）は上書きせずに飛ばす
+pub async fn import_archive(store: &MessageStore, path: PathBuf) -> ServerResult<ImportResponse> {
+    let mut messages = tokio::task::spawn_blocking(move || read_messages(&path)).await??;
+
+    // IDのないメッセージ（古い形式で書き出したもの）には新しいIDを振る
+    for message in &mut messages {
+        if message.id.trim().is_empty() {
+            message.id = uuid::Uuid::new_v4().to_string();
+        }
+    }
+
+    // 保存順が時系列順になるようにする
+    messages.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let total = messages.len();
+    let imported = store.import_messages(messages).await?;
+    Ok(ImportResponse {
+        imported: imported as u64,
+        skipped: (total - imported) as u64,
+    })
+}
+
+fn read_messages(path: &Path) -> ServerResult<Vec<ReceivedMessage>> {
+    let mut file = File::open(path)?;
+
+    // 先頭のブロックがtarのヘッダーでなければ、`messages export --format jsonl` で書き出したファイル
+    // （添付ファイルは埋め込まれている）
+    let mut header = Vec::with_capacity(BLOCK_SIZE);
+    (&mut file)
+        .take(BLOCK_SIZE as u64)
+        .read_to_end(&mut header)?;
+    file.rewind()?;
+    if header.get(257..262) != Some(b"ustar") {
+        return parse_lines(BufReader::new(file));
+    }
+
+    // tarを作り直した場合はファイルの順番が変わるため、メッセージを読んでから添付ファイルを読み直す
+    let mut messages = None;
+    read_entries(path, |entry_path, entry| {
+        match entry_path {
+            MANIFEST_PATH => {
+                let manifest: ArchiveManifest = serde_json::from_reader(entry)
+                    .map_err(|e| invalid_archive(format!("{}: {}", MANIFEST_PATH, e)))?;
+                if manifest.version > ARCHIVE_VERSION {
+                    return Err(invalid_archive(format!(
+                        "unsupported version {} (this server reads up to {})",
+                        manifest.version, ARCHIVE_VERSION
+                    )));
+                }
+            }
+            MESSAGES_PATH => messages = Some(parse_lines(BufReader::new(entry))?),
+            _ => {}
+        }
+        Ok(())
+    })?;
+    let mut messages =
+        messages.ok_or_else(|| invalid_archive(format!("{} is missing", MESSAGES_PATH)))?;
+
+    let mut attachments: HashMap<String, &mut Attachment> = messages
+        .iter_mut()
+        .flat_map(|message| {
+            message
+                .attachments
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, attachment)| attachment.data.is_empty())
+                .map(|(index, attachment)| (attachment_path(&message.id, index), attachment))
+        })
+        .collect();
+    if !attachments.is_empty() {
+        read_entries(path, |entry_path, entry| {
+            if let Some(attachment) = attachments.get_mut(entry_path) {
+                let mut blob = Vec::new();
+                entry.read_to_end(&mut blob)?;
+                attachment.data = BASE64.encode(blob);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(messages)
+}
+
+// アーカイブに含まれるファイルを順に渡す（ディレクトリやリンク、知らないファイルは飛ばす）
+// ヘッダーに書かれたサイズは信用せず、アーカイブより大きいものや途中で切れているものは壊れているとみなす
+fn read_entries(
+    path: &Path,
+    mut visit: impl FnMut(&str, &mut dyn Read) -> ServerResult<()>,
+) -> ServerResult<()> {
+    let file = File::open(path)?;
+    let archive_size = file.metadata()?.len();
+    let mut archive = tar::Archive::new(BufReader::new(file));
+    for entry in archive.entries().map_err(tar_error)? {
+        let entry = entry.map_err(tar_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        // tarコマンドで作り直した場合の「./」は付いていないものとして扱う
+        let entry_path = entry.path().map_err(tar_error)?;
+        let entry_path = entry_path.to_string_lossy();
+        let entry_path = entry_path.trim_start_matches("./").to_string();
+        if entry_path != MANIFEST_PATH
+            && entry_path != MESSAGES_PATH
+            && !entry_path.starts_with(&format!("{}/", ATTACHMENTS_DIR))
+        {
+            continue;
+        }
+        if entry.size() > archive_size {
+            return Err(invalid_archive(format!(
+                "{} is larger than the archive",
+                entry_path
+            )));
+        }
+
+        let mut entry = EntryReader {
+            remaining: entry.size(),
+            inner: entry,
+        };
+        visit(&entry_path, &mut entry).map_err(|e| match e {
+            ServerError::Io(e) => tar_error(e),
+            e => e,
+        })?;
+    }
+    Ok(())
+}
+
+// エントリーの中身（tarクレートは途中で切れていても短く読めるだけなので、ヘッダーのサイズに足りなければエラーにする）
+struct EntryReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for EntryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read == 0 && self.remaining > 0 && !buf.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "archive is truncated",
+            ));
+        }
+        self.remaining = self.remaining.saturating_sub(read as u64);
+        Ok(read)
+    }
+}
+
+fn tar_error(e: std::io::Error) -> ServerError {
+    invalid_archive(e.to_string())
+}
+
+fn parse_lines(reader: impl BufRead) -> ServerResult<Vec<ReceivedMessage>> {
+    let mut messages = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidData => {
+                invalid_archive(format!("line {}: not UTF-8", number + 1))
+            }
+            _ => tar_error(e),
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = serde_json::from_str(&line)
+            .map_err(|e| invalid_archive(format!("line {}: {}", number + 1, e)))?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+fn attachment_path(message_id: &str, index: usize) -> String {
+    format!("{}/{}/{}", ATTACHMENTS_DIR, message_id, index)
+}
+
+fn invalid_archive(reason: String) -> ServerError {
+    ServerError::BadRequest(format!("Invalid archive: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, timestamp: &str) -> ReceivedMessage {
+        ReceivedMessage {
+            id: id.to_string(),
+            from: "127.0.0.1".to_string(),
+            from_name: "tester".to_string(),
+            message: format!("message {}", id),
+            message_type: "text".to_string(),
+            timestamp: timestamp.to_string(),
+            is_self: false,
+            attachments: Vec::new(),
+            pinned: false,
+            edited_at: None,
+        }
+    }
+
+    fn attachment(contents: &[u8]) -> Attachment {
+        Attachment {
+            id: "a1".to_string(),
+            filename: "note.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            size: contents.len() as u64,
+            data: BASE64.encode(contents),
+            thumbnail: None,
+        }
+    }
+
+    fn store(dir: &Path, name: &str) -> MessageStore {
+        MessageStore::new(Some(dir.join(name))).unwrap()
+    }
+
+    fn invalid_reason(result: ServerResult<ImportResponse>) -> String {
+        match result {
+            Err(ServerError::BadRequest(reason)) => reason,
+            other => panic!("expected an invalid archive, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip_keeps_range_timestamps_and_attachments() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = store(dir.path(), "source.db");
+        let mut with_file = message("m2", "2026-01-02T09:30:00+09:00");
+        with_file.attachments.push(attachment(b"hello"));
+        for message in [
+            message("m1", "2026-01-01T12:00:00Z"),
+            with_file.clone(),
+            message("m3", "2026-01-03T12:00:00Z"),
+        ] {
+            source.save_message(&message).await.unwrap();
+        }
+
+        let range = DateRange::parse(Some("2026-01-02T00:00:00Z"), None).unwrap();
+        let (archive, manifest) = export_archive(&source, "source", &range, dir.path().into())
+            .await
+            .unwrap();
+        assert_eq!((manifest.messages, manifest.attachments), (2, 1));
+
+        let target = store(dir.path(), "target.db");
+        target
+            .save_message(&message("m3", "2026-01-03T12:00:00Z"))
+            .await
+            .unwrap();
+        let report = import_archive(&target, archive.path().into())
+            .await
+            .unwrap();
+        // m3は既にあるため飛ばす
+        assert_eq!((report.imported, report.skipped), (1, 1));
+
+        let imported = target.get_message("m2").await.unwrap().unwrap();
+        assert_eq!(imported.timestamp, with_file.timestamp);
+        assert_eq!(imported.attachments[0].data, with_file.attachments[0].data);
+        assert!(target.get_message("m1").await.unwrap().is_none());
+
+        let again = import_archive(&target, archive.path().into())
+            .await
+            .unwrap();
+        assert_eq!((again.imported, again.skipped), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn jsonl_export_is_imported_with_embedded_attachments() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut with_file = message("", "2026-01-01T00:00:00Z");
+        with_file.attachments.push(attachment(b"embedded"));
+        let path = dir.path().join("messages.jsonl");
+        let mut lines = serde_json::to_string(&with_file).unwrap();
+        lines.push_str("\n\n");
+        lines.push_str(&serde_json::to_string(&message("m2", "2026-01-02T00:00:00Z")).unwrap());
+        std::fs::write(&path, lines).unwrap();
+
+        let target = store(dir.path(), "target.db");
+        let report = import_archive(&target, path).await.unwrap();
+        assert_eq!((report.imported, report.skipped), (2, 0));
+
+        // IDのないメッセージには新しいIDが振られる
+        let all = target.get_all_messages().await.unwrap();
+        assert!(!all[0].id.is_empty());
+        assert_eq!(all[0].attachments[0].data, with_file.attachments[0].data);
+    }
+
+    #[tokio::test]
+    async fn truncated_archive_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = store(dir.path(), "source.db");
+        source
+            .save_message(&message("m1", "2026-01-01T00:00:00Z"))
+            .await
+            .unwrap();
+        let (archive, _) =
+            export_archive(&source, "source", &DateRange::default(), dir.path().into())
+                .await
+                .unwrap();
+
+        // manifest.jsonの途中で切る
+        let truncated = dir.path().join("truncated.tar");
+        let bytes = std::fs::read(archive.path()).unwrap();
+        std::fs::write(&truncated, &bytes[..BLOCK_SIZE + 16]).unwrap();
+
+        let target = store(dir.path(), "target.db");
+        let reason = invalid_reason(import_archive(&target, truncated).await);
+        assert!(reason.contains("truncated"), "{}", reason);
+        assert_eq!(target.get_message_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn entry_larger_than_archive_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_path(MESSAGES_PATH).unwrap();
+        header.set_size(8 << 30);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        let path = dir.path().join("oversized.tar");
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(&[0; BLOCK_SIZE]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let target = store(dir.path(), "target.db");
+        let reason = invalid_reason(import_archive(&target, path).await);
+        assert!(reason.contains("larger than the archive"), "{}", reason);
+    }
+}
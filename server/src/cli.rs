@@ -0,0 +1,821 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use server::{
+    PongResponse, ServerConfig, ServerMessage, ServerState,
+    archive::{self, DateRange},
+    encryption::{self, KeySource, StoreKey, UnlockState},
+    error::{ServerError, ServerResult},
+    find_local_ip,
+    logging::{self, LogOutput},
+    message_store::MessageStore,
+    parse_toml_value, paths,
+};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::server_manager::ServerManager;
+
+// 終了コード（使い方の誤りはclapが2で終了する）
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_NOT_RUNNING: u8 = 3; // statusでサーバーが応答しない（systemctl statusに合わせる）
+pub const EXIT_NOT_CONFIGURED: u8 = 78; // 設定ファイルがない（sysexitsのEX_CONFIG）
+
+// 端末からパスフレーズを入力させる回数
+const UNLOCK_ATTEMPTS: u32 = 3;
+
+// 設定ファイル内で直接読み書きさせない項目
+const SECRET_KEYS: [&str; 2] = ["password_hash", "salt"];
+
+#[derive(Parser, Debug)]
+#[command(name = "server", version, about = "Sure-Shot magazine server")]
+pub struct Cli {
+    /// 設定・メッセージ・ログを置くディレクトリ（環境変数 SURE_SHOT_DATA_DIR でも指定できる）
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// 実行ファイルと同じ場所の sure-shot-data/ にデータを置く
+    #[arg(long, global = true, conflicts_with = "data_dir")]
+    pub portable: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// サーバーを起動する（サブコマンド省略時はTUIで起動）
+    Serve {
+        /// TUIを使わずに起動し、ログを標準エラー出力に書く
+        #[arg(long)]
+        headless: bool,
+    },
+    /// 初期設定を行う（オプションを省略した項目は対話的に入力する）
+    Setup {
+        /// サーバー名
+        #[arg(long)]
+        nickname: Option<String>,
+        /// パスワードを標準入力の1行目から読む
+        #[arg(long)]
+        password_stdin: bool,
+        /// 既存の設定を上書きする
+        #[arg(long)]
+        force: bool,
+    },
+    /// パスワードを変更する
+    Passwd {
+        /// パスワードを標準入力の1行目から読む
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// 設定を表示・変更する
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// 保存されたメッセージを操作する
+    Messages {
+        #[command(subcommand)]
+        command: MessagesCommand,
+    },
+    /// 保存するメッセージの暗号化を設定する
+    Encryption {
+        #[command(subcommand)]
+        command: EncryptionCommand,
+    },
+    /// 設定とサーバーの稼働状況を表示する
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// 設定値を表示する（キー省略時は全体）
+    Get {
+        /// "log_config.show_requests" のようなドット区切りのキー
+        key: Option<String>,
+    },
+    /// 設定値を変更する
+    Set {
+        /// "log_config.show_requests" のようなドット区切りのキー
+        key: String,
+        /// TOMLの値として解釈する（文字列の項目はそのまま）
+        value: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MessagesCommand {
+    /// メッセージを書き出す（期間の指定がなければ全て）
+    Export {
+        /// 出力先のファイル（省略時は標準出力）
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+        /// この日時以降のメッセージ（YYYY-MM-DDかRFC 3339）
+        #[arg(long, value_name = "DATE")]
+        from: Option<String>,
+        /// この日時までのメッセージ（YYYY-MM-DDならその日の終わりまで）
+        #[arg(long, value_name = "DATE")]
+        to: Option<String>,
+    },
+    /// 書き出したメッセージを読み込む（同じIDのメッセージが既にあれば飛ばす）
+    Import {
+        /// `export --format archive` のアーカイブか `--format jsonl` のファイル
+        input: PathBuf,
+    },
+    /// サーバーを止めずにデータベースをファイルに写す
+    Backup {
+        /// 出力先のファイル（既にあれば置き換える）
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum EncryptionCommand {
+    /// 暗号化しているかどうかを表示する
+    Status,
+    /// 保存済みのメッセージを暗号化し、以降も暗号化して保存する
+    Enable {
+        #[command(flatten)]
+        key: NewKeyArgs,
+    },
+    /// 別のパスフレーズか鍵ファイルで暗号化し直す
+    Rekey {
+        #[command(flatten)]
+        key: NewKeyArgs,
+    },
+    /// 暗号化をやめ、保存済みのメッセージを平文に戻す
+    Disable,
+}
+
+// 新しい鍵の指定（省略時はパスフレーズを入力させる）
+#[derive(Args, Debug)]
+pub struct NewKeyArgs {
+    /// パスフレーズの代わりに鍵ファイルを使う（パス省略時は設定の encryption.key_file かデータディレクトリの messages.key）
+    #[arg(long, value_name = "PATH", num_args = 0..=1, conflicts_with = "passphrase_stdin")]
+    key_file: Option<Option<PathBuf>>,
+    /// 新しいパスフレーズを標準入力の1行目から読む
+    #[arg(long)]
+    passphrase_stdin: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Jsonl,   // 1行1メッセージ
+    Json,    // 配列
+    Archive, // 添付ファイルを別のファイルにしたtar（importで読み込める）
+}
+
+// エラーを表示して終了コードに変換する
+pub fn report(result: ServerResult<ExitCode>) -> ExitCode {
+    match result {
+        Ok(code) => code,
+        Err(ServerError::ConfigNotFound) => {
+            eprintln!(
+                "error: 設定ファイルがありません。`server setup` で初期設定を行ってください。"
+            );
+            ExitCode::from(EXIT_NOT_CONFIGURED)
+        }
+        Err(e @ (ServerError::DataDirNotFound | ServerError::DataDir(..))) => {
+            eprintln!("error: {}", e);
+            eprintln!(
+                "hint: `--data-dir <DIR>` か環境変数 {} で別のディレクトリを指定できます。",
+                paths::DATA_DIR_ENV
+            );
+            ExitCode::from(EXIT_FAILURE)
+        }
+        Err(e @ ServerError::StoreLocked) => {
+            eprintln!("error: {}", e);
+            eprintln!(
+                "hint: 端末から実行するか、環境変数 {} でパスフレーズを渡してください。",
+                encryption::PASSPHRASE_ENV
+            );
+            ExitCode::from(EXIT_FAILURE)
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+// 暗号化されたデータベースを開けるよう解錠する（鍵ファイル、環境変数、端末からの入力の順に試す）
+pub fn unlock(key_file: Option<&Path>) -> ServerResult<()> {
+    let info = match encryption::unlock_without_prompt(key_file)? {
+        UnlockState::Plaintext | UnlockState::Unlocked => return Ok(()),
+        UnlockState::NeedsPassphrase(info) => info,
+    };
+    if !io::stdin().is_terminal() {
+        return Err(ServerError::StoreLocked);
+    }
+
+    for attempt in 1..=UNLOCK_ATTEMPTS {
+        eprint!("パスフレーズを入力してください: ");
+        io::stderr().flush()?;
+        let passphrase = rpassword::read_password()?;
+        match encryption::unlock_with_passphrase(&info, &passphrase) {
+            Err(ServerError::InvalidKey) if attempt < UNLOCK_ATTEMPTS => {
+                eprintln!("パスフレーズが違います。");
+            }
+            result => return result,
+        }
+    }
+    Err(ServerError::InvalidKey)
+}
+
+// 設定ファイルの鍵ファイルの場所（設定がなくてもデータベースは扱えるようにする）
+fn configured_key_file() -> Option<PathBuf> {
+    ServerConfig::load_unchecked()
+        .ok()
+        .and_then(|config| config.encryption.key_file)
+}
+
+// TUIを使わずにサーバーを起動し、SIGTERM/SIGINTで停止する
+pub async fn serve_headless() -> ServerResult<ExitCode> {
+    let config = ServerConfig::load_or_create()?;
+    let _logging = logging::init(&config.log_config, LogOutput::Stderr)?;
+    unlock(config.encryption.key_file.as_deref())?;
+
+    let (message_sender, mut message_receiver) = mpsc::unbounded_channel();
+    let server_manager = Arc::new(ServerManager::new(message_sender));
+    server_manager
+        .start_server()
+        .await
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    // サーバーが自分で止まった場合は異常終了として扱う（systemdに再起動させるため）
+    let code = loop {
+        tokio::select! {
+            message = message_receiver.recv() => match message {
+                Some(ServerMessage::StatusUpdate(status)) => match status.state {
+                    ServerState::Error(e) => {
+                        tracing::error!("Server failed: {}", e);
+                        break ExitCode::from(EXIT_FAILURE);
+                    }
+                    ServerState::Stopped | ServerState::Aborted => {
+                        break ExitCode::from(EXIT_FAILURE);
+                    }
+                    // 落ちたサーバーは監視タスクが再起動する（諦めた場合はErrorかAbortedになる）
+                    ServerState::Starting
+                    | ServerState::Running
+                    | ServerState::Restarting { .. }
+                    | ServerState::Stopping => {}
+                },
+                Some(_) => {}
+                None => break ExitCode::from(EXIT_FAILURE),
+            },
+            _ = &mut shutdown => {
+                tracing::info!("Shutdown signal received");
+                break ExitCode::SUCCESS;
+            }
+        }
+    };
+
+    server_manager
+        .stop_server()
+        .await
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+    Ok(code)
+}
+
+// SIGINT（Ctrl+C）またはSIGTERMを待つ
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+pub fn setup(
+    nickname: Option<String>,
+    password_stdin: bool,
+    force: bool,
+) -> ServerResult<ExitCode> {
+    if !force && ServerConfig::get_config_path()?.exists() {
+        return Err(ServerError::Setup(
+            "設定ファイルが既に存在します（上書きする場合は --force）".to_string(),
+        ));
+    }
+
+    // 指定されなかった項目は端末から入力させる
+    let config = match (nickname, password_stdin) {
+        (None, false) if io::stdin().is_terminal() => ServerConfig::create_with_setup()?,
+        (Some(nickname), false) if io::stdin().is_terminal() => {
+            let password = ServerConfig::prompt_password()?;
+            let config = ServerConfig::new(nickname, &password)?;
+            config.save()?;
+            config
+        }
+        (nickname, true) => {
+            let password = read_password_line()?;
+            let nickname = nickname.unwrap_or_else(|| ServerConfig::default().nickname);
+            let config = ServerConfig::new(nickname, &password)?;
+            config.save()?;
+            config
+        }
+        (_, false) => {
+            return Err(ServerError::Setup(
+                "端末がない場合は --password-stdin でパスワードを渡してください".to_string(),
+            ));
+        }
+    };
+
+    println!(
+        "設定を保存しました: {} ({})",
+        config.nickname,
+        ServerConfig::get_config_path()?.display()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn passwd(password_stdin: bool) -> ServerResult<ExitCode> {
+    let mut config = ServerConfig::load_or_create()?;
+
+    let password = if password_stdin {
+        read_password_line()?
+    } else {
+        ServerConfig::prompt_password()?
+    };
+
+    config.set_password(&password)?;
+    config.save()?;
+    println!("パスワードを変更しました。起動中のサーバーにも自動で反映されます。");
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn config(command: ConfigCommand) -> ServerResult<ExitCode> {
+    let config = ServerConfig::load_unchecked()?;
+    let mut value = toml::Value::try_from(&config)?;
+
+    match command {
+        ConfigCommand::Get { key: None } => {
+            if let Some(table) = value.as_table_mut() {
+                for key in SECRET_KEYS {
+                    table.remove(key);
+                }
+            }
+            print!("{}", toml::to_string_pretty(&value)?);
+        }
+        ConfigCommand::Get { key: Some(key) } => {
+            reject_secret(&key)?;
+            match lookup(&mut value, &key) {
+                Some(toml::Value::String(text)) => println!("{}", text),
+                Some(toml::Value::Table(table)) => print!("{}", toml::to_string_pretty(table)?),
+                Some(other) => println!("{}", other),
+                None => return Err(ServerError::NotFound("Config key")),
+            }
+        }
+        ConfigCommand::Set { key, value: raw } => {
+            reject_secret(&key)?;
+            let (parent, field) = match key.rsplit_once('.') {
+                Some((parent, field)) => (Some(parent), field),
+                None => (None, key.as_str()),
+            };
+            let table = match parent {
+                Some(parent) => lookup(&mut value, parent),
+                None => Some(&mut value),
+            }
+            .and_then(|v| v.as_table_mut())
+            .ok_or(ServerError::NotFound("Config key"))?;
+
+            // 文字列の項目には入力をそのまま入れ、それ以外はTOMLの値として解釈する
+            let new_value = match table.get(field) {
+                Some(toml::Value::String(_)) => toml::Value::String(raw),
+                _ => parse_toml_value(&raw),
+            };
+            table.insert(field.to_string(), new_value);
+
+            // 型が合わない値や範囲外の値は保存しない
+            let updated: ServerConfig = value
+                .try_into()
+                .map_err(|e| ServerError::InvalidConfig(format!("{}: {}", key, e)))?;
+            // 存在しない項目はserdeに無視されて結果に残らない（設定した項目は未設定のOptionでも残る）
+            let mut applied = toml::Value::try_from(&updated)?;
+            if lookup(&mut applied, &key).is_none() {
+                return Err(ServerError::NotFound("Config key"));
+            }
+            updated.check()?;
+            let changes = config.changes(&updated);
+            updated.save()?;
+            if changes.restart_required.is_empty() {
+                println!("{} を更新しました。", key);
+            } else {
+                println!("{} を更新しました。サーバーの再起動後に反映されます。", key);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn messages(command: MessagesCommand) -> ServerResult<ExitCode> {
+    unlock(configured_key_file().as_deref())?;
+
+    match command {
+        MessagesCommand::Export {
+            output,
+            format,
+            from,
+            to,
+        } => {
+            let range = DateRange::parse(from.as_deref(), to.as_deref())?;
+            let store = MessageStore::new(None)?;
+
+            let mut writer: Box<dyn Write> = match &output {
+                Some(path) => Box::new(io::BufWriter::new(std::fs::File::create(path)?)),
+                None => Box::new(io::BufWriter::new(io::stdout().lock())),
+            };
+
+            let count = match format {
+                ExportFormat::Archive => {
+                    // 設定がなくてもデータベースがあれば書き出せるようにする
+                    let name = ServerConfig::load_unchecked()
+                        .map(|config| config.nickname)
+                        .unwrap_or_default();
+                    let (archive, manifest) =
+                        archive::export_archive(&store, &name, &range, paths::data_dir()?).await?;
+                    io::copy(&mut archive.reopen()?, &mut writer)?;
+                    manifest.messages
+                }
+                ExportFormat::Jsonl => {
+                    let messages = archive::messages_in_range(&store, &range).await?;
+                    for message in &messages {
+                        serde_json::to_writer(&mut writer, message)?;
+                        writeln!(writer)?;
+                    }
+                    messages.len()
+                }
+                ExportFormat::Json => {
+                    let messages = archive::messages_in_range(&store, &range).await?;
+                    serde_json::to_writer_pretty(&mut writer, &messages)?;
+                    writeln!(writer)?;
+                    messages.len()
+                }
+            };
+            writer.flush()?;
+
+            if let Some(path) = output {
+                eprintln!(
+                    "{} 件のメッセージを書き出しました: {}",
+                    count,
+                    path.display()
+                );
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        MessagesCommand::Import { input } => {
+            let store = MessageStore::new(None)?;
+            let report = archive::import_archive(&store, input).await?;
+
+            println!(
+                "{} 件のメッセージを読み込みました（{} 件は既にあるため飛ばしました）",
+                report.imported, report.skipped
+            );
+            if report.imported > 0 {
+                println!("起動中のサーバーの一覧には、サーバーを再起動すると表示されます。");
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        MessagesCommand::Backup { output } => {
+            let store = MessageStore::new(None)?;
+            store.backup(output.clone()).await?;
+            println!("データベースをバックアップしました: {}", output.display());
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+pub async fn status() -> ServerResult<ExitCode> {
+    let config = ServerConfig::load_or_create()?;
+
+    // 件数とサイズは鍵がなくても読めるため、解錠はしない
+    let (count, size) = MessageStore::summary(None)?;
+    println!("nickname:  {}", config.nickname);
+    let (data_dir, source) = paths::resolve_data_dir()?;
+    println!("data dir:  {} ({})", data_dir.display(), source);
+    println!("config:    {}", ServerConfig::get_config_path()?.display());
+    println!("messages:  {} ({} KB)", count, size / 1024);
+    println!(
+        "encrypted: {}",
+        match MessageStore::encryption_info(None)? {
+            Some(info) => format!("yes ({})", info.source),
+            None => "no".to_string(),
+        }
+    );
+
+    let Some(url) = server_url(&config) else {
+        println!("server:    not running (no local IP found)");
+        return Ok(ExitCode::from(EXIT_NOT_RUNNING));
+    };
+
+    match ping(&url).await? {
+        Some(pong) => {
+            println!("server:    running at {} ({})", url, pong.name);
+            Ok(ExitCode::SUCCESS)
+        }
+        None => {
+            println!("server:    not running ({})", url);
+            Ok(ExitCode::from(EXIT_NOT_RUNNING))
+        }
+    }
+}
+
+pub async fn encryption(command: EncryptionCommand) -> ServerResult<ExitCode> {
+    let key_file = configured_key_file();
+    let info = MessageStore::encryption_info(None)?;
+
+    let (new_key, message) = match command {
+        EncryptionCommand::Status => {
+            match info {
+                None => println!("encryption: off"),
+                Some(info) if info.source == KeySource::Passphrase => println!(
+                    "encryption: on (passphrase, PBKDF2-HMAC-SHA256 {} iterations)",
+                    info.iterations
+                ),
+                Some(_) => println!(
+                    "encryption: on (key file {})",
+                    encryption::key_file_path(key_file.as_deref())?.display()
+                ),
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        EncryptionCommand::Enable { key } => {
+            if info.is_some() {
+                return Err(ServerError::BadRequest(
+                    "既に暗号化されています（鍵を変える場合は `server encryption rekey`）"
+                        .to_string(),
+                ));
+            }
+            ensure_server_stopped().await?;
+            (
+                Some(new_key(&key, key_file.as_deref(), true)?),
+                "暗号化しました",
+            )
+        }
+        EncryptionCommand::Rekey { key } => {
+            if info.is_none() {
+                return Err(ServerError::BadRequest(
+                    "暗号化されていません（`server encryption enable` で暗号化できます）"
+                        .to_string(),
+                ));
+            }
+            ensure_server_stopped().await?;
+            unlock(key_file.as_deref())?;
+            (
+                Some(new_key(&key, key_file.as_deref(), false)?),
+                "新しい鍵で暗号化し直しました",
+            )
+        }
+        EncryptionCommand::Disable => {
+            if info.is_none() {
+                return Err(ServerError::BadRequest("暗号化されていません".to_string()));
+            }
+            ensure_server_stopped().await?;
+            unlock(key_file.as_deref())?;
+            (None, "平文に戻しました")
+        }
+    };
+
+    // 鍵ファイルをやめた場合や別の場所に変えた場合は、古い鍵ファイルが残る
+    let old_key_file = match &info {
+        Some(info) if info.source == KeySource::KeyFile => {
+            Some(encryption::key_file_path(key_file.as_deref())?)
+        }
+        _ => None,
+    };
+
+    let store = MessageStore::new(None)?;
+    let key = new_key.as_ref().map(|new_key| new_key.key.clone());
+    let count = match store.change_encryption(key).await {
+        Ok(count) => count,
+        Err(e) => {
+            if let Some(new_key) = new_key {
+                new_key.discard();
+            }
+            return Err(e);
+        }
+    };
+    if let Some(new_key) = &new_key {
+        new_key.commit()?;
+    }
+    println!("{} 件のメッセージを{}。", count, message);
+
+    match &new_key {
+        Some(NewKey {
+            key_file: Some(path),
+            ..
+        }) => println!(
+            "鍵ファイル: {}\n鍵ファイルを失うとメッセージを読めなくなるため、別の場所にも保管してください。",
+            path.display()
+        ),
+        Some(_) => println!(
+            "サーバーの起動時にパスフレーズを入力してください（TUIを使わない場合は環境変数 {}）。",
+            encryption::PASSPHRASE_ENV
+        ),
+        None => {}
+    }
+    if let Some(path) = old_key_file
+        && new_key
+            .as_ref()
+            .and_then(|new_key| new_key.key_file.as_ref())
+            != Some(&path)
+    {
+        println!("古い鍵ファイルは不要になりました: {}", path.display());
+    }
+    println!("以前のバックアップや書き出したファイルは、この変更の影響を受けません。");
+    Ok(ExitCode::SUCCESS)
+}
+
+// 新しい鍵（鍵ファイルは変換が終わってから置き換える）
+struct NewKey {
+    key: Arc<StoreKey>,
+    key_file: Option<PathBuf>,       // 鍵ファイルを使う場合の場所
+    staged: Option<PathBuf>,         // 書き込み済みで、まだ置き換えていない鍵ファイル
+    configure: Option<ServerConfig>, // 鍵ファイルの場所を変えた設定（変換後に保存する）
+}
+
+impl NewKey {
+    // 一時ファイルを鍵ファイルに置き換え、設定を保存する
+    fn commit(&self) -> ServerResult<()> {
+        if let (Some(staged), Some(path)) = (&self.staged, &self.key_file) {
+            std::fs::rename(staged, path)?;
+        }
+        if let Some(config) = &self.configure {
+            config.save()?;
+        }
+        Ok(())
+    }
+
+    fn discard(self) {
+        if let Some(staged) = self.staged {
+            let _ = std::fs::remove_file(staged);
+        }
+    }
+}
+
+// 指定に従って新しい鍵を作る（reuse_existingなら既存の鍵ファイルをそのまま使う）
+fn new_key(
+    args: &NewKeyArgs,
+    configured: Option<&Path>,
+    reuse_existing: bool,
+) -> ServerResult<NewKey> {
+    let Some(path) = &args.key_file else {
+        let passphrase = if args.passphrase_stdin {
+            read_password_line()?
+        } else {
+            prompt_new_passphrase()?
+        };
+        return Ok(NewKey {
+            key: Arc::new(StoreKey::new_passphrase(&passphrase)?),
+            key_file: None,
+            staged: None,
+            configure: None,
+        });
+    };
+
+    // 場所を指定された場合は設定にも保存し、起動時に読めるようにする
+    let configure = match path {
+        Some(path) => {
+            let path = std::path::absolute(path)?;
+            let mut config = ServerConfig::load_unchecked()?;
+            if config.encryption.key_file.as_ref() == Some(&path) {
+                None
+            } else {
+                config.encryption.key_file = Some(path);
+                Some(config)
+            }
+        }
+        None => None,
+    };
+    let path = encryption::key_file_path(
+        configure
+            .as_ref()
+            .and_then(|config| config.encryption.key_file.as_deref())
+            .or(configured),
+    )?;
+
+    if reuse_existing && path.exists() {
+        return Ok(NewKey {
+            key: Arc::new(StoreKey::from_key_file(&path)?),
+            key_file: Some(path),
+            staged: None,
+            configure,
+        });
+    }
+
+    // 変換に失敗しても今の鍵ファイルが残るよう、別のファイルに書いておく
+    let mut staged = path.clone().into_os_string();
+    staged.push(".new");
+    let staged = PathBuf::from(staged);
+    Ok(NewKey {
+        key: Arc::new(StoreKey::generate_key_file(&staged)?),
+        key_file: Some(path),
+        staged: Some(staged),
+        configure,
+    })
+}
+
+// 新しいパスフレーズを確認付きで端末から入力させる
+fn prompt_new_passphrase() -> ServerResult<String> {
+    if !io::stdin().is_terminal() {
+        return Err(ServerError::Setup(
+            "端末がない場合は --passphrase-stdin でパスフレーズを渡してください".to_string(),
+        ));
+    }
+
+    print!("新しいパスフレーズを入力してください: ");
+    io::stdout().flush()?;
+    let passphrase = rpassword::read_password()?;
+
+    print!("確認用にもう一度パスフレーズを入力してください: ");
+    io::stdout().flush()?;
+    if passphrase != rpassword::read_password()? {
+        return Err(ServerError::Setup("パスフレーズが一致しません".to_string()));
+    }
+    Ok(passphrase)
+}
+
+// 起動中のサーバーは変換前の鍵で書き込み続けるため、止めてから変換させる
+async fn ensure_server_stopped() -> ServerResult<()> {
+    let Ok(config) = ServerConfig::load_unchecked() else {
+        return Ok(());
+    };
+    if let Some(url) = server_url(&config)
+        && ping(&url).await?.is_some()
+    {
+        return Err(ServerError::BadRequest(format!(
+            "サーバーが起動しています（{}）。停止してから実行してください",
+            url
+        )));
+    }
+    Ok(())
+}
+
+// 設定の待ち受けアドレス（未設定の場合は検出したローカルIP）のURL
+fn server_url(config: &ServerConfig) -> Option<String> {
+    let ip = match config.bind.address.as_deref().map(str::parse::<IpAddr>) {
+        Some(Ok(ip)) => ip,
+        Some(Err(_)) | None => find_local_ip()?,
+    };
+    Some(format!("http://{}", SocketAddr::new(ip, config.bind.port)))
+}
+
+// /pingに応答すればその内容を返す
+async fn ping(url: &str) -> ServerResult<Option<PongResponse>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+    match client.get(format!("{}/ping", url)).send().await {
+        Ok(response) => Ok(response.json::<PongResponse>().await.ok()),
+        Err(_) => Ok(None),
+    }
+}
+
+// 標準入力の1行目をパスワードとして読む
+fn read_password_line() -> ServerResult<String> {
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn reject_secret(key: &str) -> ServerResult<()> {
+    if SECRET_KEYS.contains(&key) {
+        return Err(ServerError::BadRequest(
+            "パスワードは `server passwd` で変更してください".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// ドット区切りのキーで値を探す
+fn lookup<'a>(value: &'a mut toml::Value, key: &str) -> Option<&'a mut toml::Value> {
+    let mut current = value;
+    for part in key.split('.') {
+        current = current.as_table_mut()?.get_mut(part)?;
+    }
+    Some(current)
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+
+// 接続用URIのスキーム（carbineがディープリンクとして受け取る）
+pub const CONNECT_SCHEME: &str = "sure-shot";
+
+// QRコードで端末に渡す接続情報
+// sure-shot://connect?ip=192.168.1.10&port=8000&id=<server_id>[&fp=<TLS fingerprint>][&code=<pairing code>]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectUri {
+    pub ip: String,
+    pub port: u16,
+    pub server_id: String,
+    pub fingerprint: Option<String>, // TLS証明書のSHA-256（TLSで待ち受けていない場合はなし）
+    pub pairing_code: Option<String>, // パスワードの代わりに一度だけ使えるコード
+}
+
+impl fmt::Display for ConnectUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}://connect?ip={}&port={}&id={}",
+            CONNECT_SCHEME,
+            encode(&self.ip),
+            self.port,
+            encode(&self.server_id)
+        )?;
+        if let Some(ref fingerprint) = self.fingerprint {
+            write!(f, "&fp={}", encode(fingerprint))?;
+        }
+        if let Some(ref code) = self.pairing_code {
+            write!(f, "&code={}", encode(code))?;
+        }
+        Ok(())
+    }
+}
+
+// クエリの値として使えない文字をパーセントエンコードする（IPv6の「:」などはそのまま）
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,401 @@
+use crate::error::{ServerError, ServerResult};
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+// パスフレーズを渡す環境変数（TUIを使わずに起動する場合など）
+pub const PASSPHRASE_ENV: &str = "SURE_SHOT_PASSPHRASE";
+
+// 鍵ファイルを設定していない場合にデータディレクトリに作るファイル
+const DEFAULT_KEY_FILE: &str = "messages.key";
+
+// 暗号化したデータの先頭に付ける形式の番号（その後にnonce、暗号文、認証タグが続く）
+const FORMAT_VERSION: u8 = 1;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+// PBKDF2-HMAC-SHA256の反復回数（OWASPの推奨値）
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+// 鍵が正しいか確かめるため、この値を暗号化してデータベースに残す
+const CHECK_PLAINTEXT: &[u8] = b"sure-shot";
+const CHECK_AAD: &[u8] = b"encryption.check_value";
+
+// 起動時に解錠した鍵（データベースを開くたびに使う）
+static UNLOCKED: RwLock<Option<Arc<StoreKey>>> = RwLock::new(None);
+
+// 鍵の作り方
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySource {
+    Passphrase, // パスフレーズからPBKDF2で導出する
+    KeyFile,    // 鍵ファイルに保存したランダムな鍵
+}
+
+impl KeySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeySource::Passphrase => "pbkdf2-sha256",
+            KeySource::KeyFile => "key-file",
+        }
+    }
+
+    pub fn parse(value: &str) -> ServerResult<Self> {
+        match value {
+            "pbkdf2-sha256" => Ok(KeySource::Passphrase),
+            "key-file" => Ok(KeySource::KeyFile),
+            other => Err(ServerError::Internal(format!(
+                "Unknown key derivation: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySource::Passphrase => write!(f, "passphrase"),
+            KeySource::KeyFile => write!(f, "key file"),
+        }
+    }
+}
+
+// データベースに記録する暗号化の情報（鍵そのものは含まない）
+#[derive(Clone, Debug)]
+pub struct EncryptionInfo {
+    pub source: KeySource,
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub check: Vec<u8>, // CHECK_PLAINTEXTを暗号化したもの
+}
+
+// メッセージの暗号化に使う鍵（ChaCha20-Poly1305）
+pub struct StoreKey {
+    key: LessSafeKey,
+    source: KeySource,
+    salt: Vec<u8>,
+    iterations: u32,
+}
+
+impl std::fmt::Debug for StoreKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoreKey")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StoreKey {
+    fn from_bytes(
+        bytes: &[u8],
+        source: KeySource,
+        salt: Vec<u8>,
+        iterations: u32,
+    ) -> ServerResult<Self> {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, bytes)
+            .map_err(|_| ServerError::Internal("Invalid encryption key length".to_string()))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            source,
+            salt,
+            iterations,
+        })
+    }
+
+    // 新しいソルトでパスフレーズから鍵を作る
+    pub fn new_passphrase(passphrase: &str) -> ServerResult<Self> {
+        if passphrase.is_empty() {
+            return Err(ServerError::Setup(
+                "パスフレーズは空にできません".to_string(),
+            ));
+        }
+        let salt = random_bytes(SALT_LEN)?;
+        Self::derive(passphrase, salt, PBKDF2_ITERATIONS)
+    }
+
+    // データベースに記録したソルトでパスフレーズから鍵を作る
+    pub fn from_passphrase(passphrase: &str, info: &EncryptionInfo) -> ServerResult<Self> {
+        Self::derive(passphrase, info.salt.clone(), info.iterations)
+    }
+
+    fn derive(passphrase: &str, salt: Vec<u8>, iterations: u32) -> ServerResult<Self> {
+        let rounds = NonZeroU32::new(iterations)
+            .ok_or_else(|| ServerError::Internal("Invalid PBKDF2 iterations".to_string()))?;
+        let mut bytes = [0u8; KEY_LEN];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            rounds,
+            &salt,
+            passphrase.as_bytes(),
+            &mut bytes,
+        );
+        Self::from_bytes(&bytes, KeySource::Passphrase, salt, iterations)
+    }
+
+    // 鍵ファイル（32バイトの16進数）から読み込む
+    pub fn from_key_file(path: &Path) -> ServerResult<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            ServerError::Internal(format!("Cannot read key file {}: {}", path.display(), e))
+        })?;
+        let bytes = hex::decode(content.trim())
+            .ok()
+            .filter(|bytes| bytes.len() == KEY_LEN)
+            .ok_or_else(|| {
+                ServerError::Internal(format!(
+                    "Key file {} must contain {} hex-encoded bytes",
+                    path.display(),
+                    KEY_LEN
+                ))
+            })?;
+        Self::from_bytes(&bytes, KeySource::KeyFile, Vec::new(), 0)
+    }
+
+    // ランダムな鍵を作って鍵ファイルに書き込む（本人だけが読めるようにする）
+    pub fn generate_key_file(path: &Path) -> ServerResult<Self> {
+        let bytes = random_bytes(KEY_LEN)?;
+        write_private_file(path, format!("{}\n", hex::encode(&bytes)).as_bytes()).map_err(|e| {
+            ServerError::Internal(format!("Cannot write key file {}: {}", path.display(), e))
+        })?;
+        Self::from_bytes(&bytes, KeySource::KeyFile, Vec::new(), 0)
+    }
+
+    pub fn source(&self) -> KeySource {
+        self.source
+    }
+
+    // データベースに記録する情報を作る
+    pub fn info(&self) -> ServerResult<EncryptionInfo> {
+        Ok(EncryptionInfo {
+            source: self.source,
+            salt: self.salt.clone(),
+            iterations: self.iterations,
+            check: self.seal(CHECK_PLAINTEXT, CHECK_AAD)?,
+        })
+    }
+
+    // データベースに記録した確認用の値を復号できれば正しい鍵
+    pub fn verify(&self, info: &EncryptionInfo) -> bool {
+        self.open(&info.check, CHECK_AAD)
+            .is_ok_and(|plaintext| plaintext == CHECK_PLAINTEXT)
+    }
+
+    // 形式の番号、ランダムなnonce、暗号文と認証タグの順に並べる
+    // aadには保存先（カラムと行）を渡し、別の場所に移された暗号文を復号できないようにする
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> ServerResult<Vec<u8>> {
+        let nonce_bytes = random_bytes(NONCE_LEN)?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)
+            .map_err(|_| ServerError::Internal("Invalid nonce".to_string()))?;
+
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| ServerError::Internal("Encryption failed".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(1 + NONCE_LEN + in_out.len());
+        sealed.push(FORMAT_VERSION);
+        sealed.extend_from_slice(&nonce_bytes);
+        sealed.extend_from_slice(&in_out);
+        Ok(sealed)
+    }
+
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> ServerResult<Vec<u8>> {
+        let (nonce_bytes, ciphertext) = match sealed.split_first() {
+            Some((&FORMAT_VERSION, rest)) if rest.len() >= NONCE_LEN => rest.split_at(NONCE_LEN),
+            _ => {
+                return Err(ServerError::Internal(
+                    "Unsupported encrypted data".to_string(),
+                ));
+            }
+        };
+        let nonce = Nonce::try_assume_unique_for_key(nonce_bytes)
+            .map_err(|_| ServerError::Internal("Invalid nonce".to_string()))?;
+
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| ServerError::InvalidKey)?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+// 解錠した鍵を登録する（以降に開くデータベースで使う）
+pub fn set_unlocked_key(key: Option<Arc<StoreKey>>) {
+    *UNLOCKED.write().unwrap_or_else(|e| e.into_inner()) = key;
+}
+
+pub fn unlocked_key() -> Option<Arc<StoreKey>> {
+    UNLOCKED.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// 鍵ファイルの場所（設定がなければデータディレクトリのmessages.key）
+pub fn key_file_path(configured: Option<&Path>) -> ServerResult<PathBuf> {
+    match configured {
+        Some(path) => Ok(path.to_path_buf()),
+        None => Ok(crate::paths::data_dir()?.join(DEFAULT_KEY_FILE)),
+    }
+}
+
+// パスフレーズを環境変数から読む（設定されていなければNone）
+pub fn passphrase_from_env() -> Option<String> {
+    std::env::var(PASSPHRASE_ENV)
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+// 解錠の結果
+#[derive(Debug)]
+pub enum UnlockState {
+    Plaintext,                       // 暗号化されていない
+    Unlocked,                        // 鍵ファイルか環境変数で解錠した
+    NeedsPassphrase(EncryptionInfo), // パスフレーズを入力させる必要がある
+}
+
+// 入力なしで解錠できるものは解錠する（鍵ファイルか環境変数のパスフレーズ）
+pub fn unlock_without_prompt(key_file: Option<&Path>) -> ServerResult<UnlockState> {
+    let Some(info) = crate::message_store::MessageStore::encryption_info(None)? else {
+        return Ok(UnlockState::Plaintext);
+    };
+
+    match info.source {
+        KeySource::KeyFile => {
+            let key = StoreKey::from_key_file(&key_file_path(key_file)?)?;
+            if !key.verify(&info) {
+                return Err(ServerError::InvalidKey);
+            }
+            set_unlocked_key(Some(Arc::new(key)));
+            Ok(UnlockState::Unlocked)
+        }
+        KeySource::Passphrase => match passphrase_from_env() {
+            Some(passphrase) => {
+                unlock_with_passphrase(&info, &passphrase)?;
+                Ok(UnlockState::Unlocked)
+            }
+            None => Ok(UnlockState::NeedsPassphrase(info)),
+        },
+    }
+}
+
+// パスフレーズで解錠する（誤っていればInvalidKey）
+pub fn unlock_with_passphrase(info: &EncryptionInfo, passphrase: &str) -> ServerResult<()> {
+    let key = StoreKey::from_passphrase(passphrase, info)?;
+    if !key.verify(info) {
+        return Err(ServerError::InvalidKey);
+    }
+    set_unlocked_key(Some(Arc::new(key)));
+    Ok(())
+}
+
+fn random_bytes(len: usize) -> ServerResult<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| ServerError::Internal("Failed to generate random bytes".to_string()))?;
+    Ok(bytes)
+}
+
+fn write_private_file(path: &Path, content: &[u8]) -> ServerResult<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストではPBKDF2の反復回数を減らす
+    const TEST_ITERATIONS: u32 = 1_000;
+
+    fn passphrase_key(passphrase: &str) -> StoreKey {
+        StoreKey::derive(passphrase, random_bytes(SALT_LEN).unwrap(), TEST_ITERATIONS).unwrap()
+    }
+
+    #[test]
+    fn sealed_data_opens_with_the_same_aad() {
+        let key = passphrase_key("secret");
+        let sealed = key.seal(b"hello", b"messages.data:m1").unwrap();
+
+        assert_eq!(sealed[0], FORMAT_VERSION);
+        assert!(!sealed.windows(5).any(|window| window == b"hello"));
+        assert_eq!(key.open(&sealed, b"messages.data:m1").unwrap(), b"hello");
+        // 同じ内容でもnonceが違うため暗号文は毎回変わる
+        assert_ne!(sealed, key.seal(b"hello", b"messages.data:m1").unwrap());
+    }
+
+    #[test]
+    fn sealed_data_does_not_open_with_another_aad() {
+        let key = passphrase_key("secret");
+        let sealed = key.seal(b"hello", b"messages.data:m1").unwrap();
+
+        for aad in [
+            &b"messages.data:m2"[..],
+            b"message_edits.previous_message:m1",
+        ] {
+            assert!(matches!(
+                key.open(&sealed, aad),
+                Err(ServerError::InvalidKey)
+            ));
+        }
+    }
+
+    #[test]
+    fn tampered_or_unknown_data_is_rejected() {
+        let key = passphrase_key("secret");
+        let mut sealed = key.seal(b"hello", b"aad").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(matches!(
+            key.open(&sealed, b"aad"),
+            Err(ServerError::InvalidKey)
+        ));
+
+        sealed[0] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            key.open(&sealed, b"aad"),
+            Err(ServerError::Internal(_))
+        ));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_an_invalid_key() {
+        let info = passphrase_key("secret").info().unwrap();
+
+        let right = StoreKey::from_passphrase("secret", &info).unwrap();
+        assert!(right.verify(&info));
+        let wrong = StoreKey::from_passphrase("Secret", &info).unwrap();
+        assert!(!wrong.verify(&info));
+        assert!(matches!(
+            unlock_with_passphrase(&info, "Secret"),
+            Err(ServerError::InvalidKey)
+        ));
+    }
+
+    #[test]
+    fn key_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_KEY_FILE);
+        let key = StoreKey::generate_key_file(&path).unwrap();
+        let sealed = key.seal(b"hello", b"aad").unwrap();
+
+        let loaded = StoreKey::from_key_file(&path).unwrap();
+        assert!(loaded.verify(&key.info().unwrap()));
+        assert_eq!(loaded.open(&sealed, b"aad").unwrap(), b"hello");
+
+        let other = StoreKey::generate_key_file(&dir.path().join("other.key")).unwrap();
+        assert!(!other.verify(&key.info().unwrap()));
+    }
+}
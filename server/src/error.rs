@@ -0,0 +1,180 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+// サーバー全体で使うエラー
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("Token required")]
+    TokenRequired,
+    #[error("Invalid or expired token")]
+    InvalidToken,
+    #[error("Invalid password")]
+    InvalidPassword,
+    #[error("Invalid or expired pairing code")]
+    InvalidPairingCode,
+    #[error("Access denied: {0}")]
+    Forbidden(&'static str),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+    #[error("Request body is larger than {0} MB")]
+    PayloadTooLarge(u64),
+
+    #[error("Config file not found. Please run initial setup.")]
+    ConfigNotFound,
+    #[error("Could not find data directory")]
+    DataDirNotFound,
+    #[error("Cannot use data directory {path}: {source}", path = .0.display(), source = .1)]
+    DataDir(std::path::PathBuf, #[source] std::io::Error),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("Setup failed: {0}")]
+    Setup(String),
+    #[error("Message database is encrypted and has not been unlocked")]
+    StoreLocked,
+    #[error("Wrong passphrase or key for the message database")]
+    InvalidKey,
+
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Config parse error: {0}")]
+    ConfigParse(#[from] toml::de::Error),
+    #[error("Config write error: {0}")]
+    ConfigWrite(#[from] toml::ser::Error),
+    #[error("Background task failed: {0}")]
+    Task(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+pub type ServerResult<T> = Result<T, ServerError>;
+
+// エラー時のレスポンス本体（クライアントはcodeで判定する）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[typeshare]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    TokenRequired,
+    InvalidToken,
+    InvalidPassword,
+    InvalidPairingCode,
+    Forbidden,
+    NotFound,
+    BadRequest,
+    PayloadTooLarge,
+    Database,
+    Internal,
+}
+
+// ログミドルウェアが内部エラーの詳細を記録できるよう、レスポンスに添付する
+#[derive(Clone, Debug)]
+pub struct LoggedError(pub String);
+
+impl ServerError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServerError::TokenRequired => ErrorCode::TokenRequired,
+            ServerError::InvalidToken => ErrorCode::InvalidToken,
+            ServerError::InvalidPassword => ErrorCode::InvalidPassword,
+            ServerError::InvalidPairingCode => ErrorCode::InvalidPairingCode,
+            ServerError::Forbidden(_) => ErrorCode::Forbidden,
+            ServerError::NotFound(_) => ErrorCode::NotFound,
+            ServerError::BadRequest(_) => ErrorCode::BadRequest,
+            ServerError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            ServerError::Database(_) => ErrorCode::Database,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code() {
+            ErrorCode::TokenRequired
+            | ErrorCode::InvalidToken
+            | ErrorCode::InvalidPassword
+            | ErrorCode::InvalidPairingCode => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::Database | ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn to_api_error(&self) -> ApiError {
+        let status = self.status();
+        if status.is_server_error() {
+            // 内部エラーの詳細はクライアントに返さずログに残す
+            let message = match self.code() {
+                ErrorCode::Database => "Database error",
+                _ => "Internal server error",
+            };
+            ApiError {
+                code: self.code(),
+                message: message.to_string(),
+                details: None,
+            }
+        } else {
+            // 入力の誤りなどは原因をdetailsに載せる
+            let (message, details) = match self {
+                ServerError::BadRequest(reason) => {
+                    ("Invalid request".to_string(), Some(reason.clone()))
+                }
+                _ => (self.to_string(), None),
+            };
+            ApiError {
+                code: self.code(),
+                message,
+                details,
+            }
+        }
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), Json(self.to_api_error())).into_response();
+        if self.status().is_server_error() {
+            response
+                .extensions_mut()
+                .insert(LoggedError(self.to_string()));
+        }
+        response
+    }
+}
+
+// リクエスト本体のJSONが不正な場合もApiError形式で返す
+impl From<axum::extract::rejection::JsonRejection> for ServerError {
+    fn from(e: axum::extract::rejection::JsonRejection) -> Self {
+        ServerError::BadRequest(e.body_text())
+    }
+}
+
+impl From<tokio::task::JoinError> for ServerError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        ServerError::Task(e.to_string())
+    }
+}
+
+impl From<tokio::sync::AcquireError> for ServerError {
+    fn from(e: tokio::sync::AcquireError) -> Self {
+        ServerError::Task(e.to_string())
+    }
+}
@@ -0,0 +1,263 @@
+use super::{monitoring::is_local_address, require_token};
+use crate::{
+    AdminAccess, AppState, ImportResponse, LaggedEvent, ServerEvent, StreamEvent,
+    archive::{self, DateRange},
+    error::{ServerError, ServerResult},
+    paths,
+};
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Query},
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::net::SocketAddr;
+use tempfile::NamedTempFile;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+// 書き出したファイルを読んで送る単位
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    from: Option<String>, // YYYY-MM-DDかRFC 3339
+    to: Option<String>,
+}
+
+// メッセージの書き出し・読み込みとデータベースのバックアップ
+// トークンに加えて、既定では同じマシンからの接続に限る（admin.access）
+pub fn external_admin(router: routing::Router, app_state: AppState) -> routing::Router {
+    let router = router.route("/admin/export", {
+        let state = app_state.clone();
+        routing::get(
+            move |ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                  headers: HeaderMap,
+                  Query(query): Query<ExportQuery>| {
+                let state = state.clone();
+                async move { export_handler(state, addr, headers, query).await }
+            },
+        )
+    });
+
+    let router = router.route("/admin/import", {
+        let state = app_state.clone();
+        routing::post(
+            move |ConnectInfo(addr): ConnectInfo<SocketAddr>, headers: HeaderMap, body: Body| {
+                let state = state.clone();
+                async move { import_handler(state, addr, headers, body).await }
+            },
+        )
+    });
+
+    router.route("/admin/backup", {
+        let state = app_state.clone();
+        routing::get(
+            move |ConnectInfo(addr): ConnectInfo<SocketAddr>, headers: HeaderMap| {
+                let state = state.clone();
+                async move { backup_handler(state, addr, headers).await }
+            },
+        )
+    })
+}
+
+// QRコードでペアリングしたデバイスにもトークンは発行されるため、管理用の操作は接続元でも制限する
+async fn require_admin(
+    state: &AppState,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> ServerResult<()> {
+    require_token(headers).await?;
+    let access = state.config.lock().await.admin.access;
+    if access == AdminAccess::Localhost && !is_local_address(addr.ip()).await {
+        return Err(ServerError::Forbidden(
+            "admin endpoints are only served to localhost",
+        ));
+    }
+    Ok(())
+}
+
+async fn export_handler(
+    state: AppState,
+    addr: SocketAddr,
+    headers: HeaderMap,
+    query: ExportQuery,
+) -> ServerResult<impl IntoResponse> {
+    require_admin(&state, addr, &headers).await?;
+    let range = DateRange::parse(query.from.as_deref(), query.to.as_deref())?;
+
+    let name = state.config.lock().await.nickname.clone();
+    let (archive, manifest) =
+        archive::export_archive(&state.message_store, &name, &range, paths::data_dir()?).await?;
+    let (mut file, path) = archive.into_parts();
+    file.rewind()?;
+    let size = file.metadata()?.len();
+    tracing::info!(
+        "Exporting {} messages and {} attachments ({} KB)",
+        manifest.messages,
+        manifest.attachments,
+        size / 1024
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                attachment_name("export", "tar"),
+            ),
+        ],
+        stream_file(file, path),
+    ))
+}
+
+async fn import_handler(
+    state: AppState,
+    addr: SocketAddr,
+    headers: HeaderMap,
+    body: Body,
+) -> ServerResult<Json<ImportResponse>> {
+    require_admin(&state, addr, &headers).await?;
+
+    // アーカイブは添付ファイルを含んで大きくなるため、メモリに溜めずに一時ファイルに書く
+    let max_upload_mb = state.config.lock().await.admin.max_upload_mb;
+    let upload = receive_upload(&headers, body, max_upload_mb).await?;
+    let response =
+        archive::import_archive(&state.message_store, upload.path().to_path_buf()).await?;
+    tracing::info!(
+        "Imported {} messages ({} already stored)",
+        response.imported,
+        response.skipped
+    );
+
+    if response.imported > 0 {
+        // ストアを直接変更したためキャッシュを読み直させ、クライアントにも一覧を取り直させる
+        state.messages.invalidate().await;
+        let _ = state.message_broadcaster.send(StreamEvent {
+            id: None,
+            event: ServerEvent::Lagged(LaggedEvent {
+                missed: response.imported,
+                resync: true,
+            }),
+        });
+    }
+    Ok(Json(response))
+}
+
+// 受け取った内容をデータディレクトリの一時ファイルに書く（戻り値を破棄するとファイルも消える）
+// 上限を超えた時点で受け取るのをやめ、書きかけのファイルも消す
+async fn receive_upload(
+    headers: &HeaderMap,
+    body: Body,
+    max_upload_mb: u64,
+) -> ServerResult<NamedTempFile> {
+    let limit = max_upload_mb.saturating_mul(1024 * 1024);
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return Err(ServerError::PayloadTooLarge(max_upload_mb));
+    }
+
+    let file = tempfile::Builder::new()
+        .prefix("import-")
+        .tempfile_in(paths::data_dir()?)?;
+
+    // ファイルへの書き込みはランタイムの外で行う
+    let (sender, mut receiver) = mpsc::channel::<Bytes>(16);
+    let writer = tokio::task::spawn_blocking(move || -> ServerResult<NamedTempFile> {
+        let mut file = file;
+        while let Some(chunk) = receiver.blocking_recv() {
+            file.write_all(&chunk)?;
+        }
+        file.flush()?;
+        Ok(file)
+    });
+
+    let mut received = 0u64;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk =
+            chunk.map_err(|e| ServerError::BadRequest(format!("Failed to read upload: {}", e)))?;
+        received += chunk.len() as u64;
+        if received > limit {
+            drop(sender);
+            // 書きかけのファイルは書き込み側が終わってから破棄する
+            let _ = writer.await;
+            return Err(ServerError::PayloadTooLarge(max_upload_mb));
+        }
+        if sender.send(chunk).await.is_err() {
+            // 書き込みに失敗した（理由は書き込み側の結果で返す）
+            break;
+        }
+    }
+    drop(sender);
+    writer.await?
+}
+
+async fn backup_handler(
+    state: AppState,
+    addr: SocketAddr,
+    headers: HeaderMap,
+) -> ServerResult<impl IntoResponse> {
+    require_admin(&state, addr, &headers).await?;
+
+    // データディレクトリの一時ファイルに写して送り、送り終えたら消す
+    let path = tempfile::Builder::new()
+        .prefix("backup-")
+        .suffix(".db")
+        .tempfile_in(paths::data_dir()?)?
+        .into_temp_path();
+    state.message_store.backup(path.to_path_buf()).await?;
+    let file = File::open(&path)?;
+    let size = file.metadata()?.len();
+    tracing::info!("Sending a database backup ({} KB)", size / 1024);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (header::CONTENT_DISPOSITION, attachment_name("backup", "db")),
+        ],
+        stream_file(file, path),
+    ))
+}
+
+// ファイルを少しずつ読んでレスポンスの本体にする
+// 読み終えるか接続が切れたら、一時ファイル（keep）も破棄する
+fn stream_file<T: Send + 'static>(mut file: File, keep: T) -> Body {
+    let (sender, receiver) = mpsc::channel::<std::io::Result<Bytes>>(16);
+    tokio::task::spawn_blocking(move || {
+        let _keep = keep;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let chunk = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => Ok(Bytes::copy_from_slice(&buffer[..read])),
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if sender.blocking_send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+    Body::from_stream(ReceiverStream::new(receiver))
+}
+
+// ダウンロードしたファイルの名前（sure-shot-export-20250131-120000.tarなど）
+fn attachment_name(kind: &str, extension: &str) -> String {
+    format!(
+        "attachment; filename=\"sure-shot-{}-{}.{}\"",
+        kind,
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        extension
+    )
+}
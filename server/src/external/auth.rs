@@ -1,107 +1,176 @@
-use crate::{AppState, AuthRequest, AuthResponse};
-use axum::{Json, http::HeaderMap, http::StatusCode, response::IntoResponse, routing};
+use crate::{
+    AppState, AuthRequest, AuthResponse,
+    error::{ServerError, ServerResult},
+    presence::DeviceIdentity,
+};
+use axum::{Json, extract::rejection::JsonRejection, http::HeaderMap, routing};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+// 発行したトークンと、ログインしたデバイス
+#[derive(Debug, Clone)]
+struct IssuedToken {
+    token: String,
+    device: DeviceIdentity,
+}
+
+// 接続用QRコードに含めるペアリングコード
+#[derive(Debug, Clone)]
+pub struct PairingCode {
+    pub code: String,
+    pub expires_at: Instant,
+}
+
+// ペアリングコードの有効期間
+pub const PAIRING_CODE_TTL: Duration = Duration::from_secs(5 * 60);
+
 // 認証トークンの管理
 lazy_static::lazy_static! {
-    static ref AUTH_TOKENS: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    static ref AUTH_TOKENS: Arc<Mutex<Vec<IssuedToken>>> = Arc::new(Mutex::new(Vec::new()));
+    static ref PAIRING_CODES: Arc<Mutex<Vec<PairingCode>>> = Arc::new(Mutex::new(Vec::new()));
 }
 
 pub fn external_auth(router: routing::Router, app_state: AppState) -> routing::Router {
     let router = router.route("/auth/login", {
         let state = app_state.clone();
-        routing::post(move |Json(request): Json<AuthRequest>| {
+        routing::post(move |request: Result<Json<AuthRequest>, JsonRejection>| {
             let state = state.clone();
-            async move { login_handler(state, request).await }
+            async move {
+                let Json(request) = request?;
+                login_handler(state, request).await
+            }
         })
     });
 
-    let router = router.route("/auth/verify", {
+    router.route("/auth/verify", {
         routing::get(move |headers: HeaderMap| async move { verify_token_handler(headers).await })
-    });
-
-    router
+    })
 }
 
-async fn login_handler(app_state: AppState, request: AuthRequest) -> impl IntoResponse {
+async fn login_handler(
+    app_state: AppState,
+    request: AuthRequest,
+) -> ServerResult<Json<AuthResponse>> {
     let config = app_state.config.lock().await;
 
-    if !config.verify_password(&request.password) {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(AuthResponse {
-                success: false,
-                message: "Invalid password".to_string(),
-                token: None,
-            }),
-        );
+    // ペアリングコードが送られてきた場合はパスワードの代わりに使う
+    match request.pairing_code {
+        Some(ref code) => {
+            if !redeem_pairing_code(code).await {
+                app_state.metrics.record_login_failure("pairing_code");
+                return Err(ServerError::InvalidPairingCode);
+            }
+        }
+        None => {
+            if !config.verify_password(&request.password) {
+                app_state.metrics.record_login_failure("password");
+                return Err(ServerError::InvalidPassword);
+            }
+        }
     }
 
     // 認証トークンを生成
     let token = Uuid::new_v4().to_string();
+    // デバイスIDが送られてこない古いクライアントはトークンごとに別デバイスとして扱う
+    let device = DeviceIdentity {
+        device_id: request.device_id.unwrap_or_else(|| token.clone()),
+        device_name: request
+            .device_name
+            .unwrap_or_else(|| "Unknown device".to_string()),
+    };
     let mut tokens = AUTH_TOKENS.lock().await;
-    tokens.push(token.clone());
-
-    (
-        StatusCode::OK,
-        Json(AuthResponse {
-            success: true,
-            message: "Login successful".to_string(),
-            token: Some(token),
-        }),
-    )
+    tokens.push(IssuedToken {
+        token: token.clone(),
+        device,
+    });
+
+    Ok(Json(AuthResponse {
+        success: true,
+        message: "Login successful".to_string(),
+        token: Some(token),
+    }))
 }
 
 // 認証ミドルウェア用の関数
 pub async fn verify_token(token: &str) -> Option<String> {
     let tokens = AUTH_TOKENS.lock().await;
-    tokens.iter().cloned().find(|t| t == token)
+    tokens
+        .iter()
+        .find(|t| t.token == token)
+        .map(|t| t.token.clone())
 }
 
-async fn verify_token_handler(headers: HeaderMap) -> impl IntoResponse {
-    // Authorizationヘッダーからトークンを取得
-    let token = headers
-        .get("authorization")
-        .and_then(|header| header.to_str().ok())
-        .and_then(|auth_str| {
-            if auth_str.starts_with("Bearer ") {
-                Some(&auth_str[7..])
-            } else {
-                None
-            }
-        });
-
-    match token {
-        Some(token) => {
-            // トークンの検証
-            match verify_token(token).await {
-                Some(_) => (
-                    StatusCode::OK,
-                    Json(AuthResponse {
-                        success: true,
-                        message: "Token is valid".to_string(),
-                        token: None,
-                    }),
-                ),
-                None => (
-                    StatusCode::UNAUTHORIZED,
-                    Json(AuthResponse {
-                        success: false,
-                        message: "Invalid token".to_string(),
-                        token: None,
-                    }),
-                ),
-            }
+// 発行済みのトークンを全て無効にする（無効にした数を返す）
+// 未使用のペアリングコードも合わせて無効にする
+pub async fn revoke_all_tokens() -> usize {
+    PAIRING_CODES.lock().await.clear();
+    let mut tokens = AUTH_TOKENS.lock().await;
+    let count = tokens.len();
+    tokens.clear();
+    count
+}
+
+// 一度だけ使えるペアリングコードを発行する
+pub async fn issue_pairing_code() -> PairingCode {
+    let pairing = PairingCode {
+        code: Uuid::new_v4().simple().to_string(),
+        expires_at: Instant::now() + PAIRING_CODE_TTL,
+    };
+    let mut codes = PAIRING_CODES.lock().await;
+    codes.retain(|c| c.expires_at > Instant::now());
+    codes.push(pairing.clone());
+    pairing
+}
+
+// ペアリングコードを検証し、有効なら使用済みにする
+async fn redeem_pairing_code(code: &str) -> bool {
+    let mut codes = PAIRING_CODES.lock().await;
+    codes.retain(|c| c.expires_at > Instant::now());
+    match codes.iter().position(|c| c.code == code) {
+        Some(index) => {
+            codes.remove(index);
+            true
         }
-        None => (
-            StatusCode::UNAUTHORIZED,
-            Json(AuthResponse {
-                success: false,
-                message: "No token provided".to_string(),
-                token: None,
-            }),
-        ),
+        None => false,
     }
 }
+
+// トークンからログインしたデバイスを取得する
+pub async fn identify_token(token: &str) -> Option<DeviceIdentity> {
+    let tokens = AUTH_TOKENS.lock().await;
+    tokens
+        .iter()
+        .find(|t| t.token == token)
+        .map(|t| t.device.clone())
+}
+
+// Authorizationヘッダーのトークンを取り出す
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+}
+
+// Authorizationヘッダーのトークンを検証する
+pub async fn require_token(headers: &HeaderMap) -> ServerResult<String> {
+    let token = bearer_token(headers).ok_or(ServerError::TokenRequired)?;
+    verify_token(token).await.ok_or(ServerError::InvalidToken)
+}
+
+// Authorizationヘッダーのトークンを検証し、ログインしたデバイスを返す
+pub async fn require_device(headers: &HeaderMap) -> ServerResult<DeviceIdentity> {
+    let token = bearer_token(headers).ok_or(ServerError::TokenRequired)?;
+    identify_token(token).await.ok_or(ServerError::InvalidToken)
+}
+
+async fn verify_token_handler(headers: HeaderMap) -> ServerResult<Json<AuthResponse>> {
+    require_token(&headers).await?;
+    Ok(Json(AuthResponse {
+        success: true,
+        message: "Token is valid".to_string(),
+        token: None,
+    }))
+}
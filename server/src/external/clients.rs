@@ -0,0 +1,18 @@
+use super::require_token;
+use crate::AppState;
+use crate::error::ServerResult;
+use axum::{Json, http::HeaderMap, routing};
+
+pub fn external_clients(router: routing::Router, app_state: AppState) -> routing::Router {
+    router.route("/clients", {
+        let state = app_state.clone();
+        routing::get(move |headers: HeaderMap| {
+            let state = state.clone();
+            async move {
+                // 接続元のIPを含むため認証済みのクライアントのみ
+                require_token(&headers).await?;
+                ServerResult::Ok(Json(state.presence.list().await))
+            }
+        })
+    })
+}
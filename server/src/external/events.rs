@@ -1,31 +1,370 @@
-use crate::AppState;
+use super::identify_token;
+use crate::{
+    AppState, LaggedEvent, ServerEvent, ServerStatusEvent, StreamEvent, presence::DeviceIdentity,
+};
 use axum::{
+    extract::{ConnectInfo, Query},
+    http::HeaderMap,
     response::Sse,
     response::sse::{Event, KeepAlive},
     routing,
 };
-use tokio_stream::{StreamExt, wrappers::BroadcastStream};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+
+// 再接続時に一度に再送するイベント数の上限（超えた場合は取り直しを求める）
+const REPLAY_LIMIT: usize = 500;
+
+// フィードを受け取る認証済みクライアント（接続状況の記録に使う）
+pub(crate) struct FeedClient {
+    pub device: DeviceIdentity,
+    pub ip: String,
+    pub transport: &'static str,
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    token: Option<String>,
+    last_event_id: Option<i64>,
+}
 
 pub fn external_events(router: routing::Router, app_state: AppState) -> routing::Router {
     router.route("/events", {
         let state = app_state.clone();
-        routing::get({
-            let state = state.clone();
-            || async move {
-                let receiver = state.message_broadcaster.subscribe();
-                let stream = BroadcastStream::new(receiver).filter_map(|msg| match msg {
-                    Ok(message) => {
-                        let json =
-                            serde_json::to_string(&message).unwrap_or_else(|_| "{}".to_string());
-                        Some(Ok::<Event, std::convert::Infallible>(
-                            Event::default().data(json),
-                        ))
-                    }
-                    Err(_) => None,
-                });
+        routing::get(
+            move |ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                  headers: HeaderMap,
+                  Query(query): Query<EventsQuery>| {
+                let state = state.clone();
+                async move {
+                    // EventSourceはヘッダーを付けられないため、クエリのトークンも受け付ける
+                    let token = query.token.as_deref().or_else(|| {
+                        headers
+                            .get("authorization")
+                            .and_then(|header| header.to_str().ok())
+                            .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+                    });
+                    let client = match token {
+                        Some(token) => identify_token(token).await.map(|device| FeedClient {
+                            device,
+                            ip: addr.ip().to_string(),
+                            transport: "sse",
+                        }),
+                        None => None,
+                    };
 
-                Sse::new(stream).keep_alive(KeepAlive::default())
-            }
-        })
+                    // ブラウザの自動再接続ではLast-Event-IDヘッダーが付く
+                    let last_event_id = headers
+                        .get("last-event-id")
+                        .and_then(|header| header.to_str().ok())
+                        .and_then(|id| id.parse::<i64>().ok())
+                        .or(query.last_event_id);
+
+                    // ストリームが破棄される（切断される）までSSEの購読者として数える
+                    let subscriber = state.metrics.subscribe_sse();
+                    let feed = spawn_event_feed(state, client, last_event_id).await;
+                    let stream = ReceiverStream::new(feed).map(move |event| {
+                        let _ = &subscriber;
+                        Ok::<Event, Infallible>(to_sse_event(&event))
+                    });
+                    Sse::new(stream).keep_alive(KeepAlive::default())
+                }
+            },
+        )
     })
 }
+
+// 1接続分のイベント配信を開始する（SSEとWebSocketで共通）
+// 再送と接続状況の記録・配信は認証済みのクライアントのみ。起点は購読より前に決めておき、
+// 購読後に再送することで間のイベントを取りこぼさないようにする
+pub(crate) async fn spawn_event_feed(
+    state: AppState,
+    client: Option<FeedClient>,
+    last_event_id: Option<i64>,
+) -> mpsc::Receiver<StreamEvent> {
+    let replay_from = match (&client, last_event_id) {
+        (None, _) => None,
+        (Some(_), Some(id)) => Some(id),
+        (Some(_), None) => state.message_store.latest_event_id().await.ok(),
+    };
+    let receiver = state.message_broadcaster.subscribe();
+
+    let (sender, feed) = mpsc::channel::<StreamEvent>(64);
+    tokio::spawn(async move {
+        if let Some(ref client) = client {
+            state
+                .client_connected(&client.device, &client.ip, client.transport)
+                .await;
+        }
+
+        let authenticated = client.is_some();
+        run_event_feed(state.clone(), receiver, sender, replay_from, authenticated).await;
+
+        if let Some(ref client) = client {
+            state
+                .client_disconnected(&client.device.device_id, client.transport)
+                .await;
+        }
+    });
+    feed
+}
+
+// クライアントが切断するか、サーバーが停止するまで続く
+async fn run_event_feed(
+    state: AppState,
+    mut receiver: broadcast::Receiver<StreamEvent>,
+    sender: mpsc::Sender<StreamEvent>,
+    replay_from: Option<i64>,
+    authenticated: bool,
+) {
+    let name = state.config.lock().await.nickname.clone();
+    let status = ServerEvent::ServerStatus(ServerStatusEvent {
+        name,
+        state: "running".to_string(),
+    });
+    if sender.send(unnumbered(status)).await.is_err() {
+        return;
+    }
+
+    let mut last_sent = match replay_from {
+        Some(id) => replay(&state, &sender, id).await,
+        None => None,
+    };
+
+    loop {
+        // 受信側（SSEのストリームやWebSocket）が閉じたら即座に終了する
+        let received = tokio::select! {
+            received = receiver.recv() => received,
+            _ = sender.closed() => return,
+        };
+        let event = match received {
+            Ok(event) => {
+                // 再送済みのイベントは飛ばす
+                if let (Some(id), Some(last)) = (event.id, last_sent)
+                    && id <= last
+                {
+                    continue;
+                }
+                if event.id.is_some() {
+                    last_sent = event.id;
+                }
+                // 接続状況にはIPアドレスやデバイス名が含まれるため、認証済みのクライアントにだけ送る
+                if !authenticated && matches!(event.event, ServerEvent::Presence(_)) {
+                    continue;
+                }
+                event
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                let recovered = match last_sent {
+                    Some(last) => {
+                        let notice = ServerEvent::Lagged(LaggedEvent {
+                            missed,
+                            resync: false,
+                        });
+                        if sender.send(unnumbered(notice)).await.is_err() {
+                            return;
+                        }
+                        last_sent = replay(&state, &sender, last).await;
+                        last_sent.is_some()
+                    }
+                    None => false,
+                };
+                if recovered {
+                    continue;
+                }
+                unnumbered(ServerEvent::Lagged(LaggedEvent {
+                    missed,
+                    resync: true,
+                }))
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        // サーバーが停止する場合は、通知を送ったらストリームを閉じる（閉じないと停止を待つことになる）
+        let shutting_down = matches!(event.event, ServerEvent::ServerShuttingDown(_));
+        if sender.send(event).await.is_err() || shutting_down {
+            return;
+        }
+    }
+}
+
+// 指定したID以降のイベントをストアから再送し、最後に送ったIDを返す
+async fn replay(state: &AppState, sender: &mpsc::Sender<StreamEvent>, after: i64) -> Option<i64> {
+    let store = &state.message_store;
+
+    let pending = store.count_events_since(after).await.ok()?;
+    if pending.is_none_or(|pending| pending > REPLAY_LIMIT) {
+        // 多すぎる場合や、削除済み・知らないIDからの場合は再送せず、取り直しを求める
+        // （取り直しで以降の分も揃う）
+        let notice = ServerEvent::Lagged(LaggedEvent {
+            missed: pending.unwrap_or_default() as u64,
+            resync: true,
+        });
+        sender.send(unnumbered(notice)).await.ok()?;
+        return store.latest_event_id().await.ok();
+    }
+
+    let mut last_sent = after;
+    for event in store.get_events_since(after).await.ok()? {
+        last_sent = event.id.unwrap_or(last_sent);
+        sender.send(event).await.ok()?;
+    }
+
+    Some(last_sent)
+}
+
+fn unnumbered(event: ServerEvent) -> StreamEvent {
+    StreamEvent { id: None, event }
+}
+
+fn to_sse_event(StreamEvent { id, event }: &StreamEvent) -> Event {
+    let json = event.data_json().unwrap_or_else(|_| "{}".to_string());
+    let sse_event = Event::default().event(event.event_name()).data(json);
+    match id {
+        Some(id) => sse_event.id(id.to_string()),
+        None => sse_event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ReceivedMessage, ServerConfig, message_store::MessageStore, metrics::Metrics,
+        presence::PresenceRegistry, repository::CachedRepository,
+    };
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+    use tokio::sync::Mutex;
+
+    fn state(dir: &std::path::Path) -> AppState {
+        let store = Arc::new(MessageStore::new(Some(dir.join("messages.db"))).unwrap());
+        let (message_broadcaster, _) = broadcast::channel(16);
+        AppState {
+            messages: Arc::new(CachedRepository::new(store.clone(), 10)),
+            message_broadcaster,
+            config: Arc::new(Mutex::new(ServerConfig::default())),
+            log_sender: None,
+            message_store: store,
+            publish_lock: Arc::new(Mutex::new(())),
+            presence: Arc::new(PresenceRegistry::new()),
+            metrics: Arc::new(Metrics::new()),
+            listening: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn client(device_id: &str) -> FeedClient {
+        FeedClient {
+            device: DeviceIdentity {
+                device_id: device_id.to_string(),
+                device_name: device_id.to_string(),
+            },
+            ip: "127.0.0.1".to_string(),
+            transport: "sse",
+        }
+    }
+
+    async fn publish_message(state: &AppState, id: &str) {
+        let message = ReceivedMessage {
+            id: id.to_string(),
+            from: "127.0.0.1".to_string(),
+            from_name: "tester".to_string(),
+            message: format!("message {}", id),
+            message_type: "text".to_string(),
+            timestamp: "2026-01-01T00:00:00Z".to_string(),
+            is_self: false,
+            attachments: Vec::new(),
+            pinned: false,
+            edited_at: None,
+        };
+        state.message_store.save_message(&message).await.unwrap();
+        state.publish(ServerEvent::Message(message)).await;
+    }
+
+    async fn next(feed: &mut mpsc::Receiver<StreamEvent>) -> StreamEvent {
+        tokio::time::timeout(Duration::from_secs(5), feed.recv())
+            .await
+            .expect("no event within 5s")
+            .expect("feed closed")
+    }
+
+    fn message_id(event: &StreamEvent) -> Option<&str> {
+        match &event.event {
+            ServerEvent::Message(message) => Some(&message.id),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticated_feed_replays_after_last_event_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        for id in ["m1", "m2", "m3"] {
+            publish_message(&state, id).await;
+        }
+
+        let mut feed = spawn_event_feed(state.clone(), Some(client("d1")), Some(1)).await;
+        assert!(matches!(
+            next(&mut feed).await.event,
+            ServerEvent::ServerStatus(_)
+        ));
+        for (id, expected) in [(2, "m2"), (3, "m3")] {
+            let event = next(&mut feed).await;
+            assert_eq!((event.id, message_id(&event)), (Some(id), Some(expected)));
+        }
+        // 自分が接続したことの通知
+        assert!(matches!(
+            next(&mut feed).await.event,
+            ServerEvent::Presence(_)
+        ));
+
+        // 再送済みのイベントは重ねて送らない
+        publish_message(&state, "m4").await;
+        assert_eq!(message_id(&next(&mut feed).await), Some("m4"));
+    }
+
+    #[tokio::test]
+    async fn unknown_or_pruned_last_event_id_asks_for_resync() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        publish_message(&state, "m1").await;
+
+        for last_event_id in [99, -1] {
+            let mut feed =
+                spawn_event_feed(state.clone(), Some(client("d1")), Some(last_event_id)).await;
+            next(&mut feed).await;
+            assert!(matches!(
+                next(&mut feed).await.event,
+                ServerEvent::Lagged(LaggedEvent { resync: true, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn anonymous_feed_gets_neither_replay_nor_presence() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        publish_message(&state, "m1").await;
+
+        let mut anonymous = spawn_event_feed(state.clone(), None, Some(0)).await;
+        assert!(matches!(
+            next(&mut anonymous).await.event,
+            ServerEvent::ServerStatus(_)
+        ));
+
+        // 別のデバイスが接続すると、接続状況の通知が配信される
+        let mut device = spawn_event_feed(state.clone(), Some(client("d1")), None).await;
+        next(&mut device).await;
+        assert!(matches!(
+            next(&mut device).await.event,
+            ServerEvent::Presence(_)
+        ));
+
+        publish_message(&state, "m2").await;
+        assert_eq!(message_id(&next(&mut anonymous).await), Some("m2"));
+    }
+}
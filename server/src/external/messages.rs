@@ -1,9 +1,12 @@
-use super::verify_token;
-use crate::AppState;
+use super::require_token;
+use crate::{
+    AppState, EditMessageRequest, MessageDeleted, MessageEdit, ReceivedMessage, ServerEvent,
+    error::{ServerError, ServerResult},
+};
 use axum::{
     Json,
+    extract::{Path, rejection::JsonRejection},
     http::{HeaderMap, StatusCode},
-    response::IntoResponse,
     routing,
 };
 
@@ -13,50 +16,113 @@ pub fn external_get_messages(router: routing::Router, app_state: AppState) -> ro
         routing::get(move |headers: HeaderMap| {
             let state = state.clone();
             async move {
-                // Authorizationヘッダーからトークンを取得
-                let token = headers
-                    .get("authorization")
-                    .and_then(|header| header.to_str().ok())
-                    .and_then(|auth_str| {
-                        if auth_str.starts_with("Bearer ") {
-                            Some(&auth_str[7..])
-                        } else {
-                            None
-                        }
-                    });
-
-                match token {
-                    Some(token) => {
-                        // トークンの検証
-                        match verify_token(token).await {
-                            Some(_) => {
-                                // 認証成功、メッセージを返却
-                                // データベースから最新のメッセージを取得
-                                match state.message_store.get_recent_messages(100).await {
-                                    Ok(messages) => {
-                                        (StatusCode::OK, Json(messages)).into_response()
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to get messages from database: {}", e);
-                                        // フォールバック: メモリ内のメッセージを返す
-                                        let messages = state.messages.lock().await;
-                                        (StatusCode::OK, Json(messages.clone())).into_response()
-                                    }
-                                }
-                            }
-                            None => {
-                                // 無効なトークン
-                                (StatusCode::UNAUTHORIZED, Json("Invalid or expired token"))
-                                    .into_response()
-                            }
-                        }
-                    }
-                    None => {
-                        // トークンが提供されていない
-                        (StatusCode::UNAUTHORIZED, Json("Token required")).into_response()
-                    }
-                }
+                require_token(&headers).await?;
+                // データベースから最新のメッセージを取得
+                let messages = state.messages.recent(100).await?;
+                ServerResult::Ok(Json(messages))
             }
         })
     })
 }
+
+pub fn external_manage_messages(router: routing::Router, app_state: AppState) -> routing::Router {
+    let router = router.route("/messages/{id}", {
+        let delete_state = app_state.clone();
+        let edit_state = app_state.clone();
+        routing::delete(move |headers: HeaderMap, Path(id): Path<String>| {
+            let state = delete_state.clone();
+            async move { delete_message_handler(state, headers, id).await }
+        })
+        .patch(
+            move |headers: HeaderMap,
+                  Path(id): Path<String>,
+                  request: Result<Json<EditMessageRequest>, JsonRejection>| {
+                let state = edit_state.clone();
+                async move { edit_message_handler(state, headers, id, request).await }
+            },
+        )
+    });
+
+    let router = router.route("/messages/{id}/history", {
+        let state = app_state.clone();
+        routing::get(move |headers: HeaderMap, Path(id): Path<String>| {
+            let state = state.clone();
+            async move { edit_history_handler(state, headers, id).await }
+        })
+    });
+
+    router.route("/messages/{id}/pin", {
+        let pin_state = app_state.clone();
+        let unpin_state = app_state.clone();
+        routing::post(move |headers: HeaderMap, Path(id): Path<String>| {
+            let state = pin_state.clone();
+            async move { pin_handler(state, headers, id, true).await }
+        })
+        .delete(move |headers: HeaderMap, Path(id): Path<String>| {
+            let state = unpin_state.clone();
+            async move { pin_handler(state, headers, id, false).await }
+        })
+    })
+}
+
+async fn delete_message_handler(
+    state: AppState,
+    headers: HeaderMap,
+    id: String,
+) -> ServerResult<StatusCode> {
+    require_token(&headers).await?;
+
+    if !state.messages.delete(&id).await? {
+        return Err(ServerError::NotFound("Message"));
+    }
+    state
+        .publish(ServerEvent::Delete(MessageDeleted { id }))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn edit_message_handler(
+    state: AppState,
+    headers: HeaderMap,
+    id: String,
+    request: Result<Json<EditMessageRequest>, JsonRejection>,
+) -> ServerResult<Json<ReceivedMessage>> {
+    require_token(&headers).await?;
+    let Json(request) = request?;
+
+    let message = state
+        .messages
+        .edit(&id, &request.message)
+        .await?
+        .ok_or(ServerError::NotFound("Message"))?;
+    state.publish(ServerEvent::Edit(message.clone())).await;
+    Ok(Json(message))
+}
+
+async fn edit_history_handler(
+    state: AppState,
+    headers: HeaderMap,
+    id: String,
+) -> ServerResult<Json<Vec<MessageEdit>>> {
+    require_token(&headers).await?;
+
+    let history = state.message_store.get_edit_history(&id).await?;
+    Ok(Json(history))
+}
+
+async fn pin_handler(
+    state: AppState,
+    headers: HeaderMap,
+    id: String,
+    pinned: bool,
+) -> ServerResult<Json<ReceivedMessage>> {
+    require_token(&headers).await?;
+
+    let message = state
+        .messages
+        .set_pinned(&id, pinned)
+        .await?
+        .ok_or(ServerError::NotFound("Message"))?;
+    state.publish(ServerEvent::Pin(message.clone())).await;
+    Ok(Json(message))
+}
@@ -1,31 +1,47 @@
+pub mod admin;
 pub mod auth;
+pub mod clients;
 pub mod events;
 pub mod messages;
+pub mod monitoring;
 pub mod ping;
 pub mod send;
+pub mod settings;
+pub mod ws;
 
 // auth.rsから認証関数を再エクスポート
-pub use auth::verify_token;
+pub use auth::{identify_token, require_device, require_token, verify_token};
 
-use crate::{AppState, ServerMessage};
+use crate::{AppState, error::LoggedError, logging::ACCESS_TARGET};
 use axum::{
     Router,
-    extract::{Request, State},
+    extract::{MatchedPath, Request, State},
     middleware::{self, Next},
     response::Response,
 };
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 use tower_http::cors::CorsLayer;
+use tracing::Instrument;
+
+// ログでリクエストを識別するための連番
+static REQUEST_ID: AtomicU64 = AtomicU64::new(1);
 
 pub fn create_external_router(app_state: AppState) -> Router {
     let router = Router::new();
 
     // 各ルートハンドラーを適用
     let router = ping::external_ping(router, app_state.clone());
+    let router = monitoring::external_monitoring(router, app_state.clone());
     let router = auth::external_auth(router, app_state.clone());
     let router = events::external_events(router, app_state.clone());
+    let router = clients::external_clients(router, app_state.clone());
     let router = messages::external_get_messages(router, app_state.clone());
+    let router = messages::external_manage_messages(router, app_state.clone());
     let router = send::external_send_message(router, app_state.clone());
+    let router = settings::external_settings(router, app_state.clone());
+    let router = ws::external_ws(router, app_state.clone());
+    let router = admin::external_admin(router, app_state.clone());
 
     // APIログミドルウェアを追加
     let router = router.layer(middleware::from_fn_with_state(
@@ -40,17 +56,22 @@ pub fn create_external_router(app_state: AppState) -> Router {
             .allow_methods([
                 axum::http::Method::GET,
                 axum::http::Method::POST,
+                axum::http::Method::PATCH,
+                axum::http::Method::DELETE,
                 axum::http::Method::OPTIONS,
             ])
             .allow_headers([
                 axum::http::header::CONTENT_TYPE,
                 axum::http::header::ACCEPT,
                 axum::http::header::AUTHORIZATION,
-            ]),
+            ])
+            // ブラウザから/admin/exportなどのファイル名を読めるようにする
+            .expose_headers([axum::http::header::CONTENT_DISPOSITION]),
     )
 }
 
 // APIのリクエスト/レスポンスをログ記録するミドルウェア
+// リクエストごとにspanを作り、ハンドラー内のログもそのリクエストに紐づける
 async fn api_logger_middleware(
     State(app_state): State<AppState>,
     request: Request,
@@ -60,6 +81,21 @@ async fn api_logger_middleware(
     let method = request.method().clone();
     let path = request.uri().path().to_string();
     let query = request.uri().query().unwrap_or("").to_string();
+    // 集計はIDなどを含まないルートごとに行う（どのルートにも一致しない場合はまとめる）
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str);
+    let endpoint = format!("{} {}", method, route);
+
+    let span = tracing::info_span!(
+        "request",
+        id = REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        %method,
+        %path,
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    );
 
     // ログ設定を取得
     let config = app_state.config.lock().await;
@@ -67,40 +103,46 @@ async fn api_logger_middleware(
     drop(config); // 早期にロックを解放
 
     // quietエンドポイントのチェック
-    let is_quiet = log_config.quiet_endpoints.contains(&path) || 
-                   query.contains("quiet=true");
+    let is_quiet = log_config.quiet_endpoints.contains(&path) || query.contains("quiet=true");
 
     // リクエストの記録
     if log_config.show_requests && !is_quiet {
-        let request_log = if query.is_empty() {
-            format!("→ {} {}", method, path)
-        } else {
-            format!("→ {} {}?{}", method, path, query)
-        };
-
-        if let Some(ref log_sender) = app_state.log_sender {
-            let _ = log_sender.send(ServerMessage::Log(request_log));
-        }
+        span.in_scope(|| {
+            if query.is_empty() {
+                tracing::info!(target: ACCESS_TARGET, "→ {} {}", method, path);
+            } else {
+                tracing::info!(target: ACCESS_TARGET, "→ {} {}?{}", method, path, query);
+            }
+        });
     }
 
     // 次のハンドラーを実行
-    let response = next.run(request).await;
+    let response = next.run(request).instrument(span.clone()).await;
+
+    let status = response.status();
+    span.record("status", status.as_u16());
+    let latency_ms = start_time.elapsed().as_secs_f64() * 1000.0;
+    span.record("latency_ms", latency_ms);
+    app_state
+        .metrics
+        .record_request(endpoint, status.as_u16(), latency_ms);
+    let _entered = span.enter();
+
+    // 内部エラーの詳細はクライアントには返さないため、ここで必ず記録する
+    if let Some(LoggedError(error)) = response.extensions().get::<LoggedError>() {
+        tracing::error!("✗ {} {}: {}", method, path, error);
+    }
 
     // レスポンスの記録
     if log_config.show_responses && !is_quiet {
-        let duration = start_time.elapsed();
-        let status = response.status();
-        let response_log = format!(
+        tracing::info!(
+            target: ACCESS_TARGET,
             "← {} {} -> {} ({:.2}ms)",
             method,
             path,
             status.as_u16(),
-            duration.as_millis() as f64
+            latency_ms
         );
-
-        if let Some(ref log_sender) = app_state.log_sender {
-            let _ = log_sender.send(ServerMessage::Log(response_log));
-        }
     }
 
     response
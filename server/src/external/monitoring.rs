@@ -0,0 +1,168 @@
+use super::require_token;
+use crate::{
+    AppState, HealthResponse, MetricsAccess,
+    error::{ServerError, ServerResult},
+};
+use axum::{
+    Json,
+    extract::ConnectInfo,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing,
+};
+use local_ip_address::list_afinet_netifas;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+// OpenMetricsのテキスト形式のContent-Type
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// データベース全体の整合性チェックは重いため、この間隔でだけ実行して結果を使い回す
+const INTEGRITY_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// 最後に実行した整合性チェックの結果（初回のチェックが終わるまでは問題なしとみなす）
+static INTEGRITY: Mutex<IntegrityCache> = Mutex::new(IntegrityCache {
+    checked_at: None,
+    ok: true,
+});
+
+struct IntegrityCache {
+    checked_at: Option<Instant>,
+    ok: bool,
+}
+
+pub fn external_monitoring(router: routing::Router, app_state: AppState) -> routing::Router {
+    // 生きているか（データベースが壊れていなければ再起動は不要）
+    let router = router.route("/healthz", {
+        let state = app_state.clone();
+        routing::get(move || {
+            let state = state.clone();
+            async move {
+                let database = database_ok(&state).await;
+                health_response(database, state.listening.load(Ordering::Relaxed), database)
+            }
+        })
+    });
+
+    // リクエストを受け付けられるか（待ち受けを停止している間も受け付けない）
+    let router = router.route("/readyz", {
+        let state = app_state.clone();
+        routing::get(move || {
+            let state = state.clone();
+            async move {
+                let database = database_ok(&state).await;
+                let listener = state.listening.load(Ordering::Relaxed);
+                health_response(database, listener, database && listener)
+            }
+        })
+    });
+
+    router.route("/metrics", {
+        let state = app_state.clone();
+        routing::get(
+            move |ConnectInfo(addr): ConnectInfo<SocketAddr>, headers: HeaderMap| {
+                let state = state.clone();
+                async move { metrics_handler(state, addr, headers).await }
+            },
+        )
+    })
+}
+
+// プローブごとには接続できるかだけを確かめ、整合性チェックは間隔を空けて裏で実行する
+async fn database_ok(state: &AppState) -> bool {
+    if let Err(e) = state.message_store.ping().await {
+        tracing::warn!("Health check failed: {}", e);
+        return false;
+    }
+    integrity_ok(state)
+}
+
+fn integrity_ok(state: &AppState) -> bool {
+    let mut cache = INTEGRITY.lock().unwrap_or_else(|e| e.into_inner());
+    if cache
+        .checked_at
+        .is_none_or(|at| at.elapsed() >= INTEGRITY_CHECK_INTERVAL)
+    {
+        // 実行中に重ねて始めないよう、開始時点で時刻を更新しておく
+        cache.checked_at = Some(Instant::now());
+        let store = state.message_store.clone();
+        tokio::spawn(async move {
+            let ok = match store.verify_integrity().await {
+                Ok(ok) => ok,
+                Err(e) => {
+                    tracing::warn!("Integrity check failed: {}", e);
+                    false
+                }
+            };
+            if !ok {
+                tracing::error!("Database integrity check found problems");
+            }
+            INTEGRITY.lock().unwrap_or_else(|e| e.into_inner()).ok = ok;
+        });
+    }
+    cache.ok
+}
+
+fn health_response(database: bool, listener: bool, ok: bool) -> impl IntoResponse {
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(HealthResponse {
+            ok,
+            database,
+            listener,
+        }),
+    )
+}
+
+async fn metrics_handler(
+    state: AppState,
+    addr: SocketAddr,
+    headers: HeaderMap,
+) -> ServerResult<impl IntoResponse> {
+    let access = state.config.lock().await.monitoring.metrics_access;
+    match access {
+        MetricsAccess::Public => {}
+        MetricsAccess::Localhost => {
+            if !is_local_address(addr.ip()).await {
+                return Err(ServerError::Forbidden(
+                    "metrics are only served to localhost",
+                ));
+            }
+        }
+        MetricsAccess::Authenticated => {
+            if !is_local_address(addr.ip()).await {
+                require_token(&headers).await?;
+            }
+        }
+    }
+
+    let stored_messages = state.message_store.get_message_count().await?;
+    let database_bytes = state.message_store.database_size().await?;
+    let body = state
+        .metrics
+        .encode_openmetrics(stored_messages, database_bytes);
+    Ok(([(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)], body))
+}
+
+// 同じマシンからの接続か（LANのアドレスで待ち受けている場合は自分のアドレスから届く）
+pub(crate) async fn is_local_address(ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    if ip.is_loopback() {
+        return true;
+    }
+    // インターフェースの一覧の取得はブロックするため、ランタイムの外で行う
+    tokio::task::spawn_blocking(move || {
+        list_afinet_netifas()
+            .map(|interfaces| interfaces.iter().any(|(_, local)| *local == ip))
+            .unwrap_or(false)
+    })
+    .await
+    .unwrap_or(false)
+}
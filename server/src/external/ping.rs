@@ -12,6 +12,7 @@ pub fn external_ping(router: routing::Router, app_state: AppState) -> routing::R
                     message: "Pong".to_string(),
                     name: config.nickname.clone(),
                     is_self: true, // 自分自身からのレスポンス
+                    server_id: config.server_id.clone(),
                 };
                 Json(response)
             }
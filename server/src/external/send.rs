@@ -1,121 +1,111 @@
-use super::verify_token;
-use crate::{AppState, ReceivedMessage, SendMessageRequest, SendMessageResponse};
-use axum::{
-    Json,
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    routing,
+use super::require_device;
+use crate::{
+    AppState, Attachment, ReceivedMessage, SendMessageRequest, SendMessageResponse, ServerEvent,
+    detect_local_ip, error::ServerResult,
 };
+use axum::{Json, extract::rejection::JsonRejection, http::HeaderMap, routing};
 
 pub fn external_send_message(router: routing::Router, app_state: AppState) -> routing::Router {
     router.route("/send", {
         let state = app_state.clone();
         routing::post(
-            move |headers: HeaderMap, Json(request): Json<SendMessageRequest>| {
+            move |headers: HeaderMap, request: Result<Json<SendMessageRequest>, JsonRejection>| {
                 let state = state.clone();
                 async move {
-                    // Authorizationヘッダーからトークンを取得
-                    let token = headers
-                        .get("authorization")
-                        .and_then(|header| header.to_str().ok())
-                        .and_then(|auth_str| {
-                            if auth_str.starts_with("Bearer ") {
-                                Some(&auth_str[7..])
-                            } else {
-                                None
-                            }
-                        });
-
-                    match token {
-                        Some(token) => {
-                            // トークンの検証
-                            match verify_token(token).await {
-                                Some(_) => {
-                                    // 認証成功、メッセージ処理を続行
-                                    let config = state.config.lock().await;
-                                    drop(config); // ロックを早期に解放
+                    let device = require_device(&headers).await?;
+                    let Json(request) = request?;
+                    state.client_active(&device.device_id).await;
+                    let (_, response) = deliver_message(&state, request).await;
+                    ServerResult::Ok(Json(response))
+                }
+            },
+        )
+    })
+}
 
-                                    let from_name = request.from_name.clone();
-                                    let from_ip = request.from_ip.clone();
+// /sendとWebSocketで共通の配信処理（保存してから全クライアントに配信し、メッセージIDを返す）
+pub async fn deliver_message(
+    state: &AppState,
+    request: SendMessageRequest,
+) -> (String, SendMessageResponse) {
+    let sent_message = ReceivedMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        from: request.from_ip, // クライアントのIP
+        from_name: request.from_name,
+        message: request.message,
+        message_type: request.message_type,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        is_self: false, // 外部からの送信なのでfalse
+        attachments: request.attachments,
+        pinned: false,
+        edited_at: None,
+    };
+    let message_id = sent_message.id.clone();
+    let bytes = sent_message.message.len() as u64
+        + sent_message
+            .attachments
+            .iter()
+            .map(|attachment| attachment.size)
+            .sum::<u64>();
+    state.metrics.record_message(bytes);
 
-                                    let sent_message = ReceivedMessage {
-                                        from: from_ip.clone(), // クライアントのIP
-                                        from_name: from_name.clone(),
-                                        message: request.message.clone(),
-                                        message_type: request.message_type.clone(),
-                                        timestamp: chrono::Utc::now().to_rfc3339(),
-                                        is_self: false, // 外部からの送信なのでfalse
-                                        attachments: request.attachments.clone(),
-                                    };
+    // データベースに永続化
+    if let Err(e) = state.messages.save(&sent_message).await {
+        // ログには残すが、エラーとしてレスポンスは返さない
+        tracing::error!("Failed to save message to database: {}", e);
+    }
 
-                                    // 自分のメッセージリストに追加
-                                    {
-                                        let mut messages = state.messages.lock().await;
-                                        messages.push(sent_message.clone());
+    // 自分のSSEクライアントにも配信
+    let receivers = state.publish(ServerEvent::Message(sent_message)).await;
 
-                                        // 最新100件のみ保持
-                                        if messages.len() > 100 {
-                                            messages.remove(0);
-                                        }
-                                    }
+    let response = if receivers > 0 {
+        SendMessageResponse {
+            success: true,
+            message: "Message sent successfully".to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    } else {
+        // 受信者がいない場合でも成功とみなす
+        SendMessageResponse {
+            success: true,
+            message: "Message stored (no active receivers)".to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    };
 
-                                    // データベースに永続化
-                                    if let Err(e) = state.message_store.save_message(&sent_message).await {
-                                        eprintln!("Failed to save message to database: {}", e);
-                                        // ログには送信するが、エラーとしてレスポンスは返さない
-                                        if let Some(ref log_sender) = state.log_sender {
-                                            let _ = log_sender.send(crate::ServerMessage::Log(format!(
-                                                "Failed to save message to database: {}",
-                                                e
-                                            )));
-                                        }
-                                    }
+    (message_id, response)
+}
 
-                                    // 自分のSSEクライアントにも配信
-                                    let result = state.message_broadcaster.send(sent_message);
+// サーバー自身（TUI）からの送信。保存に失敗した場合は配信せずにエラーを返す
+pub async fn deliver_local_message(
+    state: &AppState,
+    message: String,
+    attachments: Vec<Attachment>,
+) -> ServerResult<ReceivedMessage> {
+    let config = state.config.lock().await.clone();
+    let from = match config.bind.address {
+        Some(address) => address,
+        None => detect_local_ip()
+            .await
+            .map_or_else(|| "127.0.0.1".to_string(), |ip| ip.to_string()),
+    };
 
-                                    let response = match result {
-                                        Ok(_) => SendMessageResponse {
-                                            success: true,
-                                            message: "Message sent successfully".to_string(),
-                                            timestamp: chrono::Utc::now().to_rfc3339(),
-                                        },
-                                        Err(tokio::sync::broadcast::error::SendError(_)) => {
-                                            // 受信者がいない場合でも成功とみなす
-                                            SendMessageResponse {
-                                                success: true,
-                                                message: "Message stored (no active receivers)"
-                                                    .to_string(),
-                                                timestamp: chrono::Utc::now().to_rfc3339(),
-                                            }
-                                        }
-                                    };
+    let sent_message = ReceivedMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        from,
+        from_name: config.nickname,
+        message,
+        message_type: "text".to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        is_self: true,
+        attachments,
+        pinned: false,
+        edited_at: None,
+    };
 
-                                    (StatusCode::OK, Json(response)).into_response()
-                                }
-                                None => {
-                                    // 無効なトークン
-                                    let response = SendMessageResponse {
-                                        success: false,
-                                        message: "Invalid or expired token".to_string(),
-                                        timestamp: chrono::Utc::now().to_rfc3339(),
-                                    };
-                                    (StatusCode::UNAUTHORIZED, Json(response)).into_response()
-                                }
-                            }
-                        }
-                        None => {
-                            // トークンが提供されていない
-                            let response = SendMessageResponse {
-                                success: false,
-                                message: "Token required".to_string(),
-                                timestamp: chrono::Utc::now().to_rfc3339(),
-                            };
-                            (StatusCode::UNAUTHORIZED, Json(response)).into_response()
-                        }
-                    }
-                }
-            },
-        )
-    })
+    state.messages.save(&sent_message).await?;
+    state
+        .publish(ServerEvent::Message(sent_message.clone()))
+        .await;
+    Ok(sent_message)
 }
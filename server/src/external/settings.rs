@@ -0,0 +1,47 @@
+use super::require_token;
+use crate::{
+    AppState, ServerSettings, SettingsUpdate,
+    error::{ServerError, ServerResult},
+};
+use axum::{Json, extract::rejection::JsonRejection, http::HeaderMap, routing};
+
+pub fn external_settings(router: routing::Router, app_state: AppState) -> routing::Router {
+    router.route("/settings", {
+        let get_state = app_state.clone();
+        let update_state = app_state.clone();
+        routing::get(move |headers: HeaderMap| {
+            let state = get_state.clone();
+            async move {
+                require_token(&headers).await?;
+                let settings = state.config.lock().await.settings();
+                ServerResult::Ok(Json(settings))
+            }
+        })
+        .patch(
+            move |headers: HeaderMap, request: Result<Json<SettingsUpdate>, JsonRejection>| {
+                let state = update_state.clone();
+                async move { update_settings_handler(state, headers, request).await }
+            },
+        )
+    })
+}
+
+async fn update_settings_handler(
+    state: AppState,
+    headers: HeaderMap,
+    request: Result<Json<SettingsUpdate>, JsonRejection>,
+) -> ServerResult<Json<ServerSettings>> {
+    require_token(&headers).await?;
+    let Json(update) = request?;
+
+    // トークンを持っているだけでパスワードを変更できないよう、現在のパスワードを確認する
+    if update.password.is_some() {
+        let current_password = update.current_password.as_deref().unwrap_or_default();
+        if !state.config.lock().await.verify_password(current_password) {
+            return Err(ServerError::InvalidPassword);
+        }
+    }
+
+    let config = state.update_settings(&update).await?;
+    Ok(Json(config.settings()))
+}
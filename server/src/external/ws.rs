@@ -0,0 +1,205 @@
+use super::{
+    events::{FeedClient, spawn_event_feed},
+    identify_token,
+    send::deliver_message,
+};
+use crate::{
+    AppState, ClientFrame, FrameError, SendAck, ServerEvent, ServerFrame, error::ServerError,
+};
+use axum::{
+    extract::{
+        ConnectInfo, Query,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::HeaderMap,
+    response::IntoResponse,
+    routing,
+};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// ハートビートの送信間隔と、無通信で切断するまでの時間
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct WsQuery {
+    token: Option<String>,
+    format: Option<String>,
+    last_event_id: Option<i64>,
+}
+
+// フレームの形式（テキストはJSON、バイナリはMessagePack）
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    MessagePack,
+}
+
+pub fn external_ws(router: routing::Router, app_state: AppState) -> routing::Router {
+    router.route("/ws", {
+        let state = app_state.clone();
+        routing::get(
+            move |ws: WebSocketUpgrade,
+                  ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                  headers: HeaderMap,
+                  Query(query): Query<WsQuery>| {
+                let state = state.clone();
+                async move {
+                    // ブラウザのWebSocketはヘッダーを付けられないため、クエリのトークンも受け付ける
+                    // 認証はアップグレード前に行う
+                    let token = query.token.as_deref().or_else(|| {
+                        headers
+                            .get("authorization")
+                            .and_then(|header| header.to_str().ok())
+                            .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+                    });
+                    let device = match token {
+                        Some(token) => identify_token(token).await,
+                        None => None,
+                    };
+                    let Some(device) = device else {
+                        let error = if token.is_some() {
+                            ServerError::InvalidToken
+                        } else {
+                            ServerError::TokenRequired
+                        };
+                        return error.into_response();
+                    };
+                    let client = FeedClient {
+                        device,
+                        ip: addr.ip().to_string(),
+                        transport: "ws",
+                    };
+
+                    let format = match query.format.as_deref() {
+                        Some("msgpack") => Format::MessagePack,
+                        _ => Format::Json,
+                    };
+                    let last_event_id = headers
+                        .get("last-event-id")
+                        .and_then(|header| header.to_str().ok())
+                        .and_then(|id| id.parse::<i64>().ok())
+                        .or(query.last_event_id);
+
+                    ws.on_upgrade(move |socket| {
+                        handle_socket(socket, state, client, format, last_event_id)
+                    })
+                }
+            },
+        )
+    })
+}
+
+// クライアントが切断するか、無通信がタイムアウトするか、サーバーが停止するまで続く
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    client: FeedClient,
+    format: Format,
+    last_event_id: Option<i64>,
+) {
+    let device_id = client.device.device_id.clone();
+    let mut feed = spawn_event_feed(state.clone(), Some(client), last_event_id).await;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await; // 最初のtickは即座に完了するため読み捨てる
+    let mut last_seen = Instant::now();
+
+    loop {
+        let frame = tokio::select! {
+            incoming = socket.recv() => {
+                let Some(Ok(message)) = incoming else {
+                    return;
+                };
+                last_seen = Instant::now();
+                if matches!(message, Message::Text(_) | Message::Binary(_)) {
+                    state.client_active(&device_id).await;
+                }
+                match message {
+                    Message::Text(text) => {
+                        handle_frame(&state, serde_json::from_str(text.as_str()).map_err(|e| e.to_string())).await
+                    }
+                    Message::Binary(bytes) => {
+                        handle_frame(&state, rmp_serde::from_slice(&bytes).map_err(|e| e.to_string())).await
+                    }
+                    Message::Close(_) => return,
+                    // Pingへの応答はaxumが自動で行う
+                    Message::Ping(_) | Message::Pong(_) => None,
+                }
+            }
+            event = feed.recv() => {
+                let Some(event) = event else {
+                    // サーバーの停止でフィードが閉じられた
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                };
+                Some(ServerFrame::Event(event))
+            }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                }
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    return;
+                }
+                None
+            }
+        };
+
+        if let Some(frame) = frame
+            && send_frame(&mut socket, format, &frame).await.is_err()
+        {
+            return;
+        }
+    }
+}
+
+// クライアントからのフレームを処理し、返すフレームがあれば返す
+async fn handle_frame(state: &AppState, frame: Result<ClientFrame, String>) -> Option<ServerFrame> {
+    let frame = match frame {
+        Ok(frame) => frame,
+        Err(e) => {
+            return Some(ServerFrame::Error(FrameError {
+                request_id: None,
+                message: format!("Invalid frame: {}", e),
+            }));
+        }
+    };
+
+    match frame {
+        ClientFrame::Send(request) => {
+            // /sendと同じ経路で保存・配信する
+            let (message_id, response) = deliver_message(state, request.message).await;
+            Some(ServerFrame::Ack(SendAck {
+                request_id: request.request_id,
+                message_id,
+                response,
+            }))
+        }
+        ClientFrame::Typing(typing) => {
+            state.publish(ServerEvent::Typing(typing)).await;
+            None
+        }
+        ClientFrame::Ping => Some(ServerFrame::Pong),
+    }
+}
+
+async fn send_frame(
+    socket: &mut WebSocket,
+    format: Format,
+    frame: &ServerFrame,
+) -> Result<(), axum::Error> {
+    let message = match format {
+        Format::Json => match serde_json::to_string(frame) {
+            Ok(json) => Message::Text(json.into()),
+            Err(_) => return Ok(()),
+        },
+        Format::MessagePack => match rmp_serde::to_vec_named(frame) {
+            Ok(bytes) => Message::Binary(bytes.into()),
+            Err(_) => return Ok(()),
+        },
+    };
+    socket.send(message).await
+}
@@ -1,18 +1,39 @@
+pub mod archive;
+pub mod connect;
+pub mod encryption;
+pub mod error;
 pub mod external;
+pub mod logging;
 pub mod message_store;
+pub mod metrics;
+pub mod paths;
+pub mod presence;
+pub mod repository;
 pub mod whoami;
 
+use error::{ServerError, ServerResult};
 use message_store::MessageStore;
+use metrics::Metrics;
+use presence::{ClientInfo, DeviceIdentity, PresenceEvent, PresenceRegistry};
+use repository::MessageRepository;
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, broadcast, mpsc};
 use typeshare::typeshare;
 
 #[derive(Debug, Clone)]
 pub enum ServerMessage {
-    Log(String),
+    Log(logging::LogEntry),
     StatusUpdate(ServerStatus),
+    ClientsUpdate(Vec<ClientInfo>),
+    ConfigChanged(ServerConfig),
+    RestartRequired(Vec<String>), // 再起動するまで反映されない設定の変更があった
+    Event(ServerEvent),           // クライアントに配信したイベント（TUIのメッセージ一覧用）
+    Restart(RestartRecord),       // HTTPサーバーが落ちて、監視タスクが再起動を試みた
 }
 
 #[derive(Debug, Clone)]
@@ -27,36 +48,374 @@ pub struct ServerStatus {
 pub enum ServerState {
     Starting,
     Running,
+    Restarting { attempt: u32, retry_at: Instant }, // 落ちたサーバーの再起動を待っている
+    Stopping, // 接続中のクライアントや書き込み中のメッセージを待っている
     Stopped,
-    Aborted,
+    Aborted, // サーバーのタスクがpanicした
     Error(String),
 }
 
+// HTTPサーバーが落ちたときの記録（TUIのControlタブに履歴を表示する）
+#[derive(Debug, Clone)]
+pub struct RestartRecord {
+    pub at: chrono::DateTime<chrono::Local>,
+    pub reason: String,
+    pub attempt: u32,               // 連続で落ちた回数
+    pub retry_in: Option<Duration>, // 再起動までの待ち時間（諦めた場合はなし）
+}
+
+// 外部APIの待ち受けポート
+pub const DEFAULT_PORT: u16 = 8000;
+
 // メッセージ保持とSSE配信用の状態
 #[derive(Clone, Debug)]
 pub struct AppState {
-    pub messages: Arc<Mutex<Vec<ReceivedMessage>>>, // 一時的な互換性のため残す
-    pub message_broadcaster: broadcast::Sender<ReceivedMessage>,
+    pub messages: Arc<dyn MessageRepository>, // メッセージの読み書き（キャッシュ付き）
+    pub message_broadcaster: broadcast::Sender<StreamEvent>,
     pub config: Arc<Mutex<ServerConfig>>,
     pub log_sender: Option<mpsc::UnboundedSender<ServerMessage>>,
-    pub message_store: Arc<MessageStore>, // 永続化ストレージ
+    pub message_store: Arc<MessageStore>, // 永続化ストレージ（イベントログや保持ポリシー用）
+    pub publish_lock: Arc<Mutex<()>>,     // イベントIDの順に配信するためのロック
+    pub presence: Arc<PresenceRegistry>,  // 接続中のクライアント
+    pub metrics: Arc<Metrics>,            // リクエスト数やレイテンシの集計
+    pub listening: Arc<AtomicBool>, // 待ち受けを開始してから停止するまでtrue（/readyzで確認する）
 }
 
+impl AppState {
+    // イベントを記録してIDを振り、全クライアントに配信する（受信者数を返す）
+    pub async fn publish(&self, event: ServerEvent) -> usize {
+        let _guard = self.publish_lock.lock().await;
+
+        let id = match self.message_store.record_event(&event).await {
+            Ok(id) => id,
+            Err(e) => {
+                tracing::error!("Failed to record event: {}", e);
+                None
+            }
+        };
+
+        self.message_broadcaster
+            .send(StreamEvent { id, event })
+            .unwrap_or(0)
+    }
+
+    // SSE/WebSocketの接続を記録し、オンラインになったデバイスを通知する
+    pub async fn client_connected(&self, device: &DeviceIdentity, ip: &str, transport: &str) {
+        if let Some(client) = self.presence.connect(device, ip, transport).await {
+            self.publish(ServerEvent::Presence(PresenceEvent {
+                client,
+                online: true,
+            }))
+            .await;
+        }
+        self.send_clients_update().await;
+    }
+
+    // 切断を記録し、オフラインになったデバイスを通知する
+    pub async fn client_disconnected(&self, device_id: &str, transport: &str) {
+        if let Some(client) = self.presence.disconnect(device_id, transport).await {
+            self.publish(ServerEvent::Presence(PresenceEvent {
+                client,
+                online: false,
+            }))
+            .await;
+        }
+        self.send_clients_update().await;
+    }
+
+    // 送信などの操作があったことを記録する
+    pub async fn client_active(&self, device_id: &str) {
+        if self.presence.touch(device_id).await {
+            self.send_clients_update().await;
+        }
+    }
+
+    // 設定を変更して保存し、実行中のサーバーに反映する
+    pub async fn update_settings(&self, update: &SettingsUpdate) -> ServerResult<ServerConfig> {
+        update_settings(&self.config, update, self.log_sender.as_ref()).await
+    }
+
+    async fn send_clients_update(&self) {
+        if let Some(ref log_sender) = self.log_sender {
+            let _ = log_sender.send(ServerMessage::ClientsUpdate(self.presence.list().await));
+        }
+    }
+}
+
+// 設定項目を上書きする環境変数の接頭辞（SURE_SHOT_BIND__PORT なら bind.port）
+pub const CONFIG_ENV_PREFIX: &str = "SURE_SHOT_";
+
+// 環境変数で上書きした項目（保存時には設定ファイルの値に戻す）
+#[derive(Clone, Debug)]
+struct EnvOverride {
+    key: Vec<String>,
+    value: toml::Value,              // 上書き後の値
+    file_value: Option<toml::Value>, // 設定ファイルの値（ファイルになければNone）
+}
+
+// SURE_SHOT_* の環境変数で設定を上書きし、上書きした項目を返す
+// 存在しない項目や型の合わない値を指定した変数は警告を出して無視する
+fn apply_env_overrides(table: &mut toml::Table) -> Vec<EnvOverride> {
+    let mut overrides = Vec::new();
+    for (name, value) in std::env::vars_os() {
+        let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+            continue;
+        };
+        let Some(path) = name.strip_prefix(CONFIG_ENV_PREFIX) else {
+            continue;
+        };
+        if name == paths::DATA_DIR_ENV
+            || name == paths::PORTABLE_ENV
+            || name == encryption::PASSPHRASE_ENV
+        {
+            continue;
+        }
+
+        let key: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+        match env_override(table, &key, value) {
+            Ok(applied) => overrides.push(applied),
+            Err(reason) => tracing::warn!("Ignoring {}: {}", name, reason),
+        }
+    }
+    overrides
+}
+
+// 1つの環境変数を適用する（設定として読めない場合は元の内容のまま理由を返す）
+fn env_override(table: &mut toml::Table, key: &[String], raw: &str) -> Result<EnvOverride, String> {
+    let unknown = || format!("unknown config key '{}'", key.join("."));
+    let (field, parents) = key.split_last().ok_or_else(unknown)?;
+
+    let mut trial = table.clone();
+    let target = parents
+        .iter()
+        .try_fold(&mut trial, |current, parent| {
+            current
+                .entry(parent.clone())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+        })
+        .ok_or_else(unknown)?;
+
+    // 文字列の項目には値をそのまま入れ、それ以外はTOMLの値として解釈する
+    let file_value = target.get(field).cloned();
+    let value = match file_value {
+        Some(toml::Value::String(_)) => toml::Value::String(raw.to_string()),
+        _ => parse_toml_value(raw),
+    };
+    target.insert(field.clone(), value);
+
+    let config: ServerConfig = toml::Value::Table(trial.clone())
+        .try_into()
+        .map_err(|e: toml::de::Error| e.to_string().trim_end().to_string())?;
+
+    // 存在しない項目はserdeに無視されて結果に残らない
+    let mut applied = toml::Value::try_from(&config).map_err(|e| e.to_string())?;
+    let value = env_lookup(&mut applied, key).ok_or_else(unknown)?.clone();
+
+    *table = trial;
+    Ok(EnvOverride {
+        key: key.to_vec(),
+        value,
+        file_value,
+    })
+}
+
+fn env_lookup<'a>(value: &'a mut toml::Value, key: &[String]) -> Option<&'a mut toml::Value> {
+    key.iter()
+        .try_fold(value, |current, part| current.as_table_mut()?.get_mut(part))
+}
+
+// "true" や "[\"/ping\"]" をTOMLの値として解釈する（解釈できなければ文字列）
+pub fn parse_toml_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+// 古い形式の設定ファイルを現在の形式に移行する
+// 追加された項目はserdeの既定値で補われるため、ここでは名前や形式の変更だけを扱う
+// v0からv1への移行はバージョン番号を付けるだけ（項目の変更はない）
+// 項目を変更する場合は移行元のバージョンを受け取り、バージョンごとに変換する
+fn migrate_config(table: &mut toml::Table) {
+    table.insert(
+        "version".to_string(),
+        toml::Value::Integer(CONFIG_VERSION.into()),
+    );
+}
+
+// 設定を変更して保存する（TUIとAPIで共通）
+// 保存に成功した場合のみメモリ上の設定を置き換えるため、ロックを保持したまま書き込む
+pub async fn update_settings(
+    config: &Mutex<ServerConfig>,
+    update: &SettingsUpdate,
+    log_sender: Option<&mpsc::UnboundedSender<ServerMessage>>,
+) -> ServerResult<ServerConfig> {
+    let mut current = config.lock().await;
+    let mut updated = current.clone();
+    updated.apply_settings(update)?;
+    updated.save()?;
+    *current = updated.clone();
+    drop(current);
+
+    if update.password.is_some() {
+        tracing::info!("Password changed");
+        if update.revoke_tokens {
+            let revoked = external::auth::revoke_all_tokens().await;
+            tracing::info!("Revoked {} tokens", revoked);
+        }
+    }
+    tracing::info!("Settings saved");
+
+    if let Some(log_sender) = log_sender {
+        let _ = log_sender.send(ServerMessage::ConfigChanged(updated.clone()));
+    }
+    Ok(updated)
+}
+
+// 設定ファイルを再読み込みした結果を実行中のサーバーに反映する
+// 再起動が必要な項目もメモリ上は置き換えておき、次の起動時に使われる
+pub async fn reload_config(
+    config: &Mutex<ServerConfig>,
+    reloaded: ServerConfig,
+    log_sender: Option<&mpsc::UnboundedSender<ServerMessage>>,
+) -> ServerResult<ConfigChanges> {
+    let mut current = config.lock().await;
+    let changes = current.changes(&reloaded);
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    if changes.applied.contains(&"log_config.level") {
+        logging::set_filter(&reloaded.log_config)?;
+    }
+    let password_changed = changes.applied.contains(&"password");
+    *current = reloaded.clone();
+    drop(current);
+
+    if password_changed {
+        // ファイルを直接書き換えた場合も、古いパスワードで発行したトークンは使えなくする
+        let revoked = external::auth::revoke_all_tokens().await;
+        tracing::info!(
+            "Password changed in config file, revoked {} tokens",
+            revoked
+        );
+    }
+    if !changes.applied.is_empty() {
+        tracing::info!("Config reloaded: {}", changes.applied.join(", "));
+    }
+    if !changes.restart_required.is_empty() {
+        tracing::warn!(
+            "Config changes require a server restart: {}",
+            changes.restart_required.join(", ")
+        );
+    }
+
+    if let Some(log_sender) = log_sender {
+        let _ = log_sender.send(ServerMessage::ConfigChanged(reloaded));
+        if !changes.restart_required.is_empty() {
+            let fields = changes.restart_required.iter().map(ToString::to_string);
+            let _ = log_sender.send(ServerMessage::RestartRequired(fields.collect()));
+        }
+    }
+    Ok(changes)
+}
+
+// 設定ファイルの形式のバージョン（項目の追加・変更時に上げ、migrate_configで移行する）
+pub const CONFIG_VERSION: u32 = 1;
+
 // サーバー設定
+// 古い設定ファイルでも読み込めるよう、新しい項目には既定値を持たせる
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ServerConfig {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub server_id: String, // 接続用QRコードでサーバーを識別する（初回読み込み時に生成して保存する）
     pub nickname: String,
     pub password_hash: String,
     pub salt: String,
+    #[serde(default)]
     pub log_config: LogConfig,
+    #[serde(default)]
+    pub bind: BindConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub monitoring: MonitoringConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub restart: RestartConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default = "default_message_cache_size")]
+    pub message_cache_size: usize, // メモリに保持する最新メッセージ数（0でキャッシュしない）
+    #[serde(skip)]
+    env_overrides: Vec<EnvOverride>, // 環境変数で上書きした項目（ファイルには保存しない）
 }
 
-// ログ設定
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// 待ち受けアドレス（変更はサーバーの再起動後に反映される）
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct BindConfig {
+    pub address: Option<String>, // 未設定の場合はローカルIPを自動で検出する
+    pub port: u16,
+    pub watch_interval_secs: u64, // 自動で検出したIPが無くなっていないか確認する間隔（0で確認しない）
+}
+
+impl Default for BindConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            port: DEFAULT_PORT,
+            watch_interval_secs: 5,
+        }
+    }
+}
+
+// 設定の項目ごとの問題
+#[derive(Clone, Debug)]
+pub struct ConfigIssue {
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+// 再読み込み時に変わった項目
+#[derive(Clone, Debug, Default)]
+pub struct ConfigChanges {
+    pub applied: Vec<&'static str>, // 実行中のサーバーにそのまま反映される項目
+    pub restart_required: Vec<&'static str>, // 再起動するまで反映されない項目
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart_required.is_empty()
+    }
+}
+
+fn default_message_cache_size() -> usize {
+    100
+}
+
+// ログ設定（未設定の項目は既定値）
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
 pub struct LogConfig {
-    pub show_requests: bool,          // リクエストログを表示するか
-    pub show_responses: bool,         // レスポンスログを表示するか
-    pub quiet_endpoints: Vec<String>, // ログを表示しないエンドポイント
+    pub show_requests: bool,                     // リクエストログを表示するか
+    pub show_responses: bool,                    // レスポンスログを表示するか
+    pub quiet_endpoints: Vec<String>,            // ログを表示しないエンドポイント
+    pub level: String,                           // 全体のログレベル（error/warn/info/debug/trace）
+    pub module_levels: BTreeMap<String, String>, // モジュールごとのレベル（例: "server::message_store" = "debug"）
+    pub file: bool, // データディレクトリのlogs/に日ごとのログファイルを残すか（再起動後に反映）
+    pub json: bool, // ログファイルと標準エラー出力をJSON Linesで出力するか（再起動後に反映）
+    pub max_log_files: usize, // 残しておくログファイルの数（再起動後に反映）
+    pub tui_buffer_lines: usize, // TUIのログ欄に保持する行数
 }
 
 impl Default for LogConfig {
@@ -69,63 +428,411 @@ impl Default for LogConfig {
                 "/auth/verify".to_string(),
                 "/events".to_string(),
             ],
+            level: "info".to_string(),
+            module_levels: BTreeMap::new(),
+            file: true,
+            json: false,
+            max_log_files: 7,
+            tui_buffer_lines: 10_000,
         }
     }
 }
 
-impl Default for ServerConfig {
+// メッセージ保持ポリシー（未設定の項目は無制限）
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub max_age_days: Option<u32>,        // メッセージの最大保持日数
+    pub max_messages: Option<u64>,        // 保持する最大件数（ピン留めしたメッセージは数えない）
+    pub max_total_size_mb: Option<u64>, // 保持するメッセージの合計サイズ上限（ピン留めしたものも含む）
+    pub attachment_ttl_days: Option<u32>, // 添付ファイルの保持日数（本文は残す）
+    pub check_interval_minutes: u64,    // ポリシーを適用する間隔
+}
+
+impl Default for RetentionConfig {
     fn default() -> Self {
-        use sha2::{Digest, Sha256};
-        use uuid::Uuid;
+        Self {
+            max_age_days: None,
+            max_messages: None,
+            max_total_size_mb: None,
+            attachment_ttl_days: None,
+            check_interval_minutes: 60,
+        }
+    }
+}
+
+// 監視用のエンドポイントの設定（/healthzと/readyzは常に公開する）
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct MonitoringConfig {
+    pub metrics_access: MetricsAccess, // /metricsにアクセスできるクライアント
+}
 
-        let salt = Uuid::new_v4().to_string();
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricsAccess {
+    Localhost, // 同じマシンからのみ
+    #[default]
+    Authenticated, // 同じマシンか、ログインして得たトークンを持つクライアント
+    Public,    // 誰でも
+}
+
+// 書き出し・読み込み・バックアップのエンドポイント（/admin/*）の設定
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub access: AdminAccess, // /admin/*にアクセスできるクライアント（どちらもトークンが必要）
+    pub max_upload_mb: u64,  // /admin/importで受け付けるファイルの上限
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            access: AdminAccess::default(),
+            max_upload_mb: 1024,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminAccess {
+    #[default]
+    Localhost, // 同じマシンからのみ
+    AnyDevice, // ログインしたどのデバイスからでも（QRコードでペアリングしたものを含む）
+}
+
+// HTTPサーバーが落ちた場合の自動再起動（待ち時間は失敗するたびに倍にする）
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct RestartConfig {
+    pub on_failure: bool,          // 落ちたら自動で再起動するか
+    pub max_attempts: u32,         // 連続で落ちた場合に諦めるまでの回数（0で無制限）
+    pub initial_backoff_secs: u64, // 最初の再起動までの待ち時間
+    pub max_backoff_secs: u64,     // 待ち時間の上限
+    pub reset_after_secs: u64,     // この時間動き続けたら連続で落ちた回数を数え直す
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            on_failure: true,
+            max_attempts: 10,
+            initial_backoff_secs: 1,
+            max_backoff_secs: 60,
+            reset_after_secs: 60,
+        }
+    }
+}
+
+impl RestartConfig {
+    // attempt回目（1から）の再起動までの待ち時間
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        Duration::from_secs(
+            self.initial_backoff_secs
+                .saturating_mul(factor)
+                .min(self.max_backoff_secs),
+        )
+    }
+}
+
+// メッセージの暗号化（有効にするかどうかはデータベースに記録し、`server encryption` で切り替える）
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub key_file: Option<std::path::PathBuf>, // 鍵ファイルの場所（未設定の場合はデータディレクトリのmessages.key）
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let salt = uuid::Uuid::new_v4().to_string();
         let default_password = "admin"; // デフォルトパスワード
-        let mut hasher = Sha256::new();
-        hasher.update(default_password.as_bytes());
-        hasher.update(salt.as_bytes());
-        let password_hash = hex::encode(hasher.finalize());
+        let password_hash = hash_password(default_password, &salt);
 
         Self {
+            version: CONFIG_VERSION,
+            server_id: uuid::Uuid::new_v4().to_string(),
             nickname: whoami::whoami().unwrap_or_else(|_| "Unknown".to_string()),
             password_hash,
             salt,
             log_config: LogConfig::default(),
+            bind: BindConfig::default(),
+            retention: RetentionConfig::default(),
+            monitoring: MonitoringConfig::default(),
+            admin: AdminConfig::default(),
+            restart: RestartConfig::default(),
+            encryption: EncryptionConfig::default(),
+            message_cache_size: default_message_cache_size(),
+            env_overrides: Vec::new(),
         }
     }
 }
 
 impl ServerConfig {
-    pub fn get_config_path() -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
-        let mut path = dirs::data_dir().ok_or("Could not find data directory")?;
-        path.push("sure-shot");
-        std::fs::create_dir_all(&path)?;
-        path.push("config.toml");
-        Ok(path)
+    // 設定やログを置くディレクトリ（なければ作成する）
+    pub fn data_dir() -> ServerResult<std::path::PathBuf> {
+        paths::data_dir()
+    }
+
+    pub fn get_config_path() -> ServerResult<std::path::PathBuf> {
+        Ok(Self::data_dir()?.join("config.toml"))
     }
 
-    pub fn load_or_create() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn load_or_create() -> ServerResult<Self> {
         let config_path = Self::get_config_path()?;
 
         if config_path.exists() {
             let content = std::fs::read_to_string(&config_path)?;
-            let config: Self = toml::from_str(&content)?;
+            let mut config = Self::parse(&content)?;
+            if config.server_id.is_empty() {
+                // 識別子がない古い設定ファイルには一度だけ生成して保存する
+                config.server_id = uuid::Uuid::new_v4().to_string();
+                config.save()?;
+            }
             return Ok(config);
         }
 
         // 設定ファイルが存在しない場合は新規作成
-        Err("Config file not found. Please run initial setup.".into())
+        Err(ServerError::ConfigNotFound)
+    }
+
+    // ファイルの内容だけを検証せずに読み込む（CLIから不正な値を直せるようにするため）
+    pub fn load_unchecked() -> ServerResult<Self> {
+        let config_path = Self::get_config_path()?;
+
+        if config_path.exists() {
+            let content = std::fs::read_to_string(&config_path)?;
+            return Ok(Self::read_table(&content)?.try_into()?);
+        }
+
+        // 設定ファイルが存在しない場合は新規作成
+        Err(ServerError::ConfigNotFound)
+    }
+
+    // 設定ファイルの内容を読み込み、古い形式からの移行と環境変数での上書き、検証を行う
+    pub fn parse(content: &str) -> ServerResult<Self> {
+        let mut table = Self::read_table(content)?;
+
+        // 上書きする前に設定ファイルだけで読めることを確かめる
+        let config: Self = toml::Value::Table(table.clone()).try_into()?;
+        let env_overrides = apply_env_overrides(&mut table);
+        let mut config = if env_overrides.is_empty() {
+            config
+        } else {
+            toml::Value::Table(table).try_into()?
+        };
+        config.env_overrides = env_overrides;
+
+        config.check()?;
+        Ok(config)
+    }
+
+    fn read_table(content: &str) -> ServerResult<toml::Table> {
+        let mut table: toml::Table = toml::from_str(content)?;
+
+        let version = match table.get("version") {
+            None => 0, // バージョン番号がない頃の設定ファイル
+            Some(value) => value
+                .as_integer()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| {
+                    ServerError::InvalidConfig(
+                        "version: must be a non-negative integer".to_string(),
+                    )
+                })?,
+        };
+        if version > CONFIG_VERSION {
+            return Err(ServerError::InvalidConfig(format!(
+                "version: {} is newer than this server supports ({})",
+                version, CONFIG_VERSION
+            )));
+        }
+        if version < CONFIG_VERSION {
+            migrate_config(&mut table);
+        }
+        Ok(table)
     }
 
-    pub fn create_with_setup() -> Result<Self, Box<dyn std::error::Error>> {
+    // 検証で見つかった問題をまとめて1つのエラーにする
+    pub fn check(&self) -> ServerResult<()> {
+        let issues = self.validate();
+        if issues.is_empty() {
+            return Ok(());
+        }
+        let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+        Err(ServerError::InvalidConfig(issues.join("; ")))
+    }
+
+    // 値の範囲や形式を項目ごとに検証する
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let mut issue = |field: &str, message: String| {
+            issues.push(ConfigIssue {
+                field: field.to_string(),
+                message,
+            })
+        };
+
+        if self.nickname.trim().is_empty() {
+            issue("nickname", "must not be empty".to_string());
+        }
+        if self.password_hash.len() != 64 || hex::decode(&self.password_hash).is_err() {
+            issue(
+                "password_hash",
+                "must be a SHA-256 hex digest (use `server passwd` to set the password)"
+                    .to_string(),
+            );
+        }
+
+        // EnvFilterは未知の単語をモジュール名として受け付けてしまうため、レベルは個別に確認する
+        let is_level = |level: &str| level.parse::<tracing::level_filters::LevelFilter>().is_ok();
+        if !is_level(&self.log_config.level) {
+            issue(
+                "log_config.level",
+                format!(
+                    "invalid level '{}' (expected off/error/warn/info/debug/trace)",
+                    self.log_config.level
+                ),
+            );
+        }
+        for (module, level) in &self.log_config.module_levels {
+            if !is_level(level) {
+                issue(
+                    "log_config.module_levels",
+                    format!("invalid level '{}' for '{}'", level, module),
+                );
+            }
+        }
+        for endpoint in &self.log_config.quiet_endpoints {
+            if !endpoint.starts_with('/') {
+                issue(
+                    "log_config.quiet_endpoints",
+                    format!("'{}' must start with '/'", endpoint),
+                );
+            }
+        }
+        if self.log_config.max_log_files == 0 {
+            issue("log_config.max_log_files", "must be at least 1".to_string());
+        }
+        if self.log_config.tui_buffer_lines < 100 {
+            issue(
+                "log_config.tui_buffer_lines",
+                "must be at least 100".to_string(),
+            );
+        }
+
+        if let Some(ref address) = self.bind.address
+            && address.parse::<IpAddr>().is_err()
+        {
+            issue(
+                "bind.address",
+                format!("'{}' is not an IP address", address),
+            );
+        }
+        if self.bind.port == 0 {
+            issue("bind.port", "must be between 1 and 65535".to_string());
+        }
+
+        if self.retention.check_interval_minutes == 0 {
+            issue(
+                "retention.check_interval_minutes",
+                "must be at least 1".to_string(),
+            );
+        }
+
+        if self.admin.max_upload_mb == 0 {
+            issue("admin.max_upload_mb", "must be at least 1".to_string());
+        }
+
+        if self.restart.initial_backoff_secs == 0 {
+            issue(
+                "restart.initial_backoff_secs",
+                "must be at least 1".to_string(),
+            );
+        }
+        if self.restart.max_backoff_secs < self.restart.initial_backoff_secs {
+            issue(
+                "restart.max_backoff_secs",
+                "must not be less than restart.initial_backoff_secs".to_string(),
+            );
+        }
+
+        issues
+    }
+
+    // 再読み込みした設定との差分を、すぐ反映できるものと再起動が必要なものに分ける
+    pub fn changes(&self, new: &ServerConfig) -> ConfigChanges {
+        let mut changes = ConfigChanges::default();
+        let mut check = |changed: bool, field: &'static str, live: bool| {
+            if changed {
+                if live {
+                    changes.applied.push(field);
+                } else {
+                    changes.restart_required.push(field);
+                }
+            }
+        };
+
+        let (old_log, new_log) = (&self.log_config, &new.log_config);
+        check(self.server_id != new.server_id, "server_id", true);
+        check(self.nickname != new.nickname, "nickname", true);
+        check(
+            self.password_hash != new.password_hash || self.salt != new.salt,
+            "password",
+            true,
+        );
+        check(
+            old_log.show_requests != new_log.show_requests
+                || old_log.show_responses != new_log.show_responses
+                || old_log.quiet_endpoints != new_log.quiet_endpoints,
+            "log_config",
+            true,
+        );
+        check(
+            old_log.level != new_log.level || old_log.module_levels != new_log.module_levels,
+            "log_config.level",
+            true,
+        );
+        check(
+            old_log.tui_buffer_lines != new_log.tui_buffer_lines,
+            "log_config.tui_buffer_lines",
+            true,
+        );
+        check(
+            old_log.file != new_log.file
+                || old_log.json != new_log.json
+                || old_log.max_log_files != new_log.max_log_files,
+            "log_config.file",
+            false,
+        );
+        check(self.retention != new.retention, "retention", true);
+        check(self.monitoring != new.monitoring, "monitoring", true);
+        check(self.admin != new.admin, "admin", true);
+        check(self.restart != new.restart, "restart", true);
+        check(self.encryption != new.encryption, "encryption", false);
+        check(
+            self.message_cache_size != new.message_cache_size,
+            "message_cache_size",
+            false,
+        );
+        check(self.bind != new.bind, "bind", false);
+        changes
+    }
+
+    pub fn create_with_setup() -> ServerResult<Self> {
         use std::io::{self, Write};
-        
+
         // サーバー名の設定
         let default_name = match whoami::whoami() {
             Ok(name) => name.trim().to_string(),
             Err(_) => "Unknown".to_string(),
         };
-        
-        print!("サーバー名を入力してください (デフォルト: {}): ", default_name);
+
+        print!(
+            "サーバー名を入力してください (デフォルト: {}): ",
+            default_name
+        );
         io::stdout().flush()?;
 
         let mut nickname = String::new();
@@ -137,64 +844,162 @@ impl ServerConfig {
         }
 
         // パスワードの設定
+        let password = Self::prompt_password()?;
+
+        let config = Self::new(nickname, &password)?;
+        config.save()?;
+
+        Ok(config)
+    }
+
+    // パスワードを確認付きで端末から入力させる
+    pub fn prompt_password() -> ServerResult<String> {
+        use std::io::{self, Write};
+
         print!("パスワードを入力してください: ");
         io::stdout().flush()?;
         let password = rpassword::read_password()?;
 
-        if password.is_empty() {
-            return Err("パスワードは空にできません".into());
-        }
-
         // パスワード確認
         print!("確認用にもう一度パスワードを入力してください: ");
         io::stdout().flush()?;
         let password_confirm = rpassword::read_password()?;
 
         if password != password_confirm {
-            return Err("パスワードが一致しません".into());
+            return Err(ServerError::Setup("パスワードが一致しません".to_string()));
         }
+        Ok(password)
+    }
 
-        // パスワードをハッシュ化
-        use sha2::{Digest, Sha256};
-        use uuid::Uuid;
-
-        let salt = Uuid::new_v4().to_string();
-        let mut hasher = Sha256::new();
-        hasher.update(password.as_bytes());
-        hasher.update(salt.as_bytes());
-        let password_hash = hex::encode(hasher.finalize());
-
-        let config = Self {
+    // 指定した名前とパスワードで新しい設定を作る（保存はしない）
+    pub fn new(nickname: String, password: &str) -> ServerResult<Self> {
+        let mut config = Self {
             nickname,
-            password_hash,
-            salt,
-            log_config: LogConfig::default(),
+            ..Self::default()
         };
+        config.set_password(password)?;
+        Ok(config)
+    }
 
-        config.save()?;
+    // パスワードを変更する（ソルトも作り直す）
+    pub fn set_password(&mut self, password: &str) -> ServerResult<()> {
+        if password.is_empty() {
+            return Err(ServerError::Setup("パスワードは空にできません".to_string()));
+        }
 
-        Ok(config)
+        self.salt = uuid::Uuid::new_v4().to_string();
+        self.password_hash = hash_password(password, &self.salt);
+        Ok(())
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    // 環境変数で上書きした項目は、実行中に変更していなければ設定ファイルの値のまま保存する
+    pub fn save(&self) -> ServerResult<()> {
         let config_path = Self::get_config_path()?;
-        let config_content = toml::to_string_pretty(self)?;
+        let mut value = toml::Value::try_from(self)?;
+        for EnvOverride {
+            key,
+            value: overridden,
+            file_value,
+        } in &self.env_overrides
+        {
+            let Some((field, parents)) = key.split_last() else {
+                continue;
+            };
+            let Some(table) = env_lookup(&mut value, parents).and_then(|v| v.as_table_mut()) else {
+                continue;
+            };
+            if table.get(field) != Some(overridden) {
+                continue;
+            }
+            match file_value {
+                Some(file_value) => table.insert(field.clone(), file_value.clone()),
+                None => table.remove(field),
+            };
+        }
+        let config_content = toml::to_string_pretty(&value)?;
         std::fs::write(config_path, config_content)?;
         Ok(())
     }
 
-    pub fn verify_password(&self, password: &str) -> bool {
-        use sha2::{Digest, Sha256};
+    pub fn settings(&self) -> ServerSettings {
+        ServerSettings {
+            nickname: self.nickname.clone(),
+            show_requests: self.log_config.show_requests,
+            show_responses: self.log_config.show_responses,
+            quiet_endpoints: self.log_config.quiet_endpoints.clone(),
+        }
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(password.as_bytes());
-        hasher.update(self.salt.as_bytes());
-        let password_hash = hex::encode(hasher.finalize());
+    // 設定の変更を適用する（保存はしない）
+    pub fn apply_settings(&mut self, update: &SettingsUpdate) -> ServerResult<()> {
+        if let Some(ref nickname) = update.nickname {
+            let nickname = nickname.trim();
+            if nickname.is_empty() {
+                return Err(ServerError::BadRequest(
+                    "nickname must not be empty".to_string(),
+                ));
+            }
+            self.nickname = nickname.to_string();
+        }
+        if let Some(ref password) = update.password {
+            self.set_password(password)
+                .map_err(|_| ServerError::BadRequest("password must not be empty".to_string()))?;
+        }
+        if let Some(show_requests) = update.show_requests {
+            self.log_config.show_requests = show_requests;
+        }
+        if let Some(show_responses) = update.show_responses {
+            self.log_config.show_responses = show_responses;
+        }
+        if let Some(ref endpoints) = update.quiet_endpoints {
+            self.log_config.quiet_endpoints = endpoints
+                .iter()
+                .map(|endpoint| endpoint.trim().to_string())
+                .filter(|endpoint| !endpoint.is_empty())
+                .collect();
+        }
+        Ok(())
+    }
 
-        password_hash == self.password_hash
+    pub fn verify_password(&self, password: &str) -> bool {
+        hash_password(password, &self.salt) == self.password_hash
     }
 }
 
+// パスワードをソルト付きでハッシュ化する
+fn hash_password(password: &str, salt: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(password.as_bytes());
+    hasher.update(salt.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+// 設定画面とAPIで変更できる項目
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ServerSettings {
+    pub nickname: String,
+    pub show_requests: bool,
+    pub show_responses: bool,
+    pub quiet_endpoints: Vec<String>,
+}
+
+// 設定の変更内容（未指定の項目は変更しない）
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[typeshare]
+pub struct SettingsUpdate {
+    pub nickname: Option<String>,
+    pub password: Option<String>,         // 新しいパスワード
+    pub current_password: Option<String>, // APIからパスワードを変更する場合に必要
+    #[serde(default)]
+    pub revoke_tokens: bool, // パスワード変更時に発行済みのトークンを全て無効にするか
+    pub show_requests: Option<bool>,
+    pub show_responses: Option<bool>,
+    pub quiet_endpoints: Option<Vec<String>>,
+}
+
 // API 応答の型定義
 #[derive(Serialize, Deserialize)]
 #[typeshare]
@@ -202,9 +1007,30 @@ pub struct PongResponse {
     pub message: String,
     pub name: String,
     pub is_self: bool,
+    #[serde(default)]
+    pub server_id: String, // 接続用URIのidと照合して、別のサーバーに繋いでいないか確かめる
 }
 
-#[derive(Serialize, Deserialize)]
+// /admin/importの応答
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ImportResponse {
+    #[typeshare(serialized_as = "number")]
+    pub imported: u64, // 保存したメッセージ数
+    #[typeshare(serialized_as = "number")]
+    pub skipped: u64, // 同じIDのメッセージが既にあったため飛ばした数
+}
+
+// /healthzと/readyzの応答（問題がある項目はfalse）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct HealthResponse {
+    pub ok: bool,
+    pub database: bool,
+    pub listener: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[typeshare]
 pub struct SendMessageRequest {
     pub message: String,
@@ -214,7 +1040,7 @@ pub struct SendMessageRequest {
     pub from_ip: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[typeshare]
 pub struct SendMessageResponse {
     pub success: bool,
@@ -225,6 +1051,8 @@ pub struct SendMessageResponse {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[typeshare]
 pub struct ReceivedMessage {
+    #[serde(default)]
+    pub id: String,
     pub from: String,
     pub from_name: String,
     pub message: String,
@@ -232,12 +1060,186 @@ pub struct ReceivedMessage {
     pub timestamp: String,
     pub is_self: bool,
     pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub pinned: bool,
+    pub edited_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[typeshare]
+pub struct EditMessageRequest {
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct MessageEdit {
+    pub message_id: String,
+    pub previous_message: String,
+    pub edited_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct MessageDeleted {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ServerStatusEvent {
+    pub name: String,
+    pub state: String,
+}
+
+// 配信が追いつかずイベントを取りこぼしたことの通知
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct LaggedEvent {
+    #[typeshare(serialized_as = "number")]
+    pub missed: u64,
+    pub resync: bool, // trueの場合は再送できないため/messagesから取り直す必要がある
+}
+
+// 入力中表示（保存・再送はしない）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct TypingEvent {
+    pub from_name: String,
+    pub typing: bool,
+}
+
+// サーバーが停止することの通知（この後ストリームは閉じられる）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ServerShuttingDownEvent {
+    pub name: String,
+}
+
+// SSEで配信するイベント
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Message(ReceivedMessage),
+    Edit(ReceivedMessage),
+    Delete(MessageDeleted),
+    Pin(ReceivedMessage),
+    ServerStatus(ServerStatusEvent),
+    Lagged(LaggedEvent),
+    Typing(TypingEvent),
+    Presence(PresenceEvent),
+    ServerShuttingDown(ServerShuttingDownEvent),
+}
+
+impl ServerEvent {
+    // SSEのイベント名
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            ServerEvent::Message(_) => "message",
+            ServerEvent::Edit(_) => "edit",
+            ServerEvent::Delete(_) => "delete",
+            ServerEvent::Pin(_) => "pin",
+            ServerEvent::ServerStatus(_) => "server-status",
+            ServerEvent::Lagged(_) => "lagged",
+            ServerEvent::Typing(_) => "typing",
+            ServerEvent::Presence(_) => "presence",
+            ServerEvent::ServerShuttingDown(_) => "server-shutting-down",
+        }
+    }
+
+    // 再送対象のイベントであれば対象メッセージのIDを返す
+    pub fn message_id(&self) -> Option<&str> {
+        match self {
+            ServerEvent::Message(message)
+            | ServerEvent::Edit(message)
+            | ServerEvent::Pin(message) => Some(&message.id),
+            ServerEvent::Delete(deleted) => Some(&deleted.id),
+            ServerEvent::ServerStatus(_)
+            | ServerEvent::Lagged(_)
+            | ServerEvent::Typing(_)
+            | ServerEvent::Presence(_)
+            | ServerEvent::ServerShuttingDown(_) => None,
+        }
+    }
+
+    // SSEのdataに載せるJSON（イベント本体のみ）
+    pub fn data_json(&self) -> serde_json::Result<String> {
+        match self {
+            ServerEvent::Message(message)
+            | ServerEvent::Edit(message)
+            | ServerEvent::Pin(message) => serde_json::to_string(message),
+            ServerEvent::Delete(deleted) => serde_json::to_string(deleted),
+            ServerEvent::ServerStatus(status) => serde_json::to_string(status),
+            ServerEvent::Lagged(lagged) => serde_json::to_string(lagged),
+            ServerEvent::Typing(typing) => serde_json::to_string(typing),
+            ServerEvent::Presence(presence) => serde_json::to_string(presence),
+            ServerEvent::ServerShuttingDown(shutdown) => serde_json::to_string(shutdown),
+        }
+    }
+}
+
+// イベントIDを付けて配信されるイベント（IDは再送可能なイベントのみ）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct StreamEvent {
+    #[typeshare(serialized_as = "Option<number>")]
+    pub id: Option<i64>,
+    pub event: ServerEvent,
+}
+
+// WebSocketでクライアントから送られるフレーム
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Send(WsSendRequest),
+    Typing(TypingEvent),
+    Ping,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct WsSendRequest {
+    pub request_id: String, // ackで返される、クライアント側で付ける識別子
+    pub message: SendMessageRequest,
+}
+
+// WebSocketでサーバーから送られるフレーム
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Event(StreamEvent),
+    Ack(SendAck),
+    Error(FrameError),
+    Pong,
+}
+
+// 送信の受付結果（/sendのレスポンスと同じ内容）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct SendAck {
+    pub request_id: String,
+    pub message_id: String,
+    pub response: SendMessageResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct FrameError {
+    pub request_id: Option<String>,
+    pub message: String,
 }
 
 #[derive(Serialize, Deserialize)]
 #[typeshare]
 pub struct AuthRequest {
-    pub password: String,
+    #[serde(default)]
+    pub password: String, // pairing_codeでログインする場合は空でよい
+    pub pairing_code: Option<String>, // 接続用QRコードに含めた一度だけ使えるコード
+    pub device_id: Option<String>,    // 同じ端末の再ログインを同一デバイスとして扱うための識別子
+    pub device_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -271,6 +1273,56 @@ pub struct Attachment {
     pub thumbnail: Option<String>,
 }
 
+impl Attachment {
+    // ファイルを読み込んで添付ファイルにする（データはクライアントと同じくBase64）
+    pub async fn from_path(path: &std::path::Path) -> ServerResult<Self> {
+        use base64::Engine;
+
+        let bytes = {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || std::fs::read(path)).await??
+        };
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| ServerError::BadRequest(format!("{} is not a file", path.display())))?;
+        Ok(Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            mime_type: mime_type_for(&filename).to_string(),
+            size: bytes.len() as u64,
+            data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+            filename,
+            thumbnail: None,
+        })
+    }
+}
+
+// 拡張子からMIMEタイプを推測する（クライアントのMimeTypes.tsの主なもの）
+fn mime_type_for(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
 // ユーティリティ関数
 use local_ip_address::list_afinet_netifas;
 use std::net::IpAddr;
@@ -278,15 +1330,23 @@ use std::net::IpAddr;
 pub fn find_local_ip() -> Option<IpAddr> {
     let ifaces = list_afinet_netifas().ok()?;
     for (_name, ip) in ifaces {
-        if let IpAddr::V4(ipv4) = ip {
-            if ipv4.octets()[0] == 192 {
-                return Some(IpAddr::V4(ipv4));
-            }
+        if let IpAddr::V4(ipv4) = ip
+            && ipv4.octets()[0] == 192
+        {
+            return Some(IpAddr::V4(ipv4));
         }
     }
     None
 }
 
+// 非同期のコードからローカルIPを検出する（インターフェースの一覧の取得はブロックするため、ランタイムの外で行う）
+pub async fn detect_local_ip() -> Option<IpAddr> {
+    tokio::task::spawn_blocking(find_local_ip)
+        .await
+        .ok()
+        .flatten()
+}
+
 // サブネット内のIPアドレスをチェックする関数
 pub async fn check_available_ips(local_ip: IpAddr, port: u16) -> Vec<IpAddr> {
     use futures::future::join_all;
@@ -0,0 +1,253 @@
+use crate::error::{ServerError, ServerResult};
+use crate::{LogConfig, ServerConfig, ServerMessage};
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry, reload};
+
+// APIのリクエスト/レスポンスログのターゲット（module_levelsで個別に絞り込める）
+pub const ACCESS_TARGET: &str = "server::access";
+
+// 設定の再読み込み時にレベルを差し替えるためのハンドル
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// ログファイルの書き込みスレッドを保持する（ドロップ時に残りを書き出す）
+pub struct LoggingGuard {
+    _file_guard: Option<WorkerGuard>,
+}
+
+// ログファイル以外の出力先
+pub enum LogOutput {
+    Tui(mpsc::UnboundedSender<ServerMessage>), // TUIのログ欄
+    Stderr,                                    // ヘッドレス実行時（systemdなどが収集する）
+}
+
+// グローバルなsubscriberを設定する
+pub fn init(config: &LogConfig, output: LogOutput) -> ServerResult<LoggingGuard> {
+    let (filter, filter_handle) = reload::Layer::new(build_filter(config)?);
+
+    let (file_layer, file_guard) = if config.file {
+        let directory = ServerConfig::data_dir()?.join("logs");
+        std::fs::create_dir_all(&directory)?;
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("server")
+            .filename_suffix("log")
+            .max_log_files(config.max_log_files.max(1))
+            .build(&directory)
+            .map_err(|e| ServerError::Internal(format!("Failed to open log file: {}", e)))?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false);
+        let layer = if config.json {
+            layer.json().boxed()
+        } else {
+            layer.boxed()
+        };
+        (Some(layer), Some(guard))
+    } else {
+        (None, None)
+    };
+
+    let output_layer = match output {
+        LogOutput::Tui(sender) => TuiLayer { sender }.boxed(),
+        LogOutput::Stderr => {
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_ansi(std::io::stderr().is_terminal());
+            if config.json {
+                layer.json().boxed()
+            } else {
+                layer.boxed()
+            }
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(file_layer)
+        .with(output_layer)
+        .try_init()
+        .map_err(|e| ServerError::Internal(format!("Failed to initialize logging: {}", e)))?;
+    let _ = FILTER_HANDLE.set(filter_handle);
+
+    Ok(LoggingGuard {
+        _file_guard: file_guard,
+    })
+}
+
+// "info,server::message_store=debug" の形式のフィルターを組み立てる
+pub fn build_filter(config: &LogConfig) -> ServerResult<EnvFilter> {
+    let mut directives = config.level.clone();
+    for (module, level) in &config.module_levels {
+        let _ = write!(directives, ",{}={}", module, level);
+    }
+    EnvFilter::try_new(&directives)
+        .map_err(|e| ServerError::InvalidConfig(format!("log filter '{}': {}", directives, e)))
+}
+
+// 実行中のログレベルを変更する（initの前に呼ばれた場合は何もしない）
+pub fn set_filter(config: &LogConfig) -> ServerResult<()> {
+    let Some(handle) = FILTER_HANDLE.get() else {
+        return Ok(());
+    };
+    handle
+        .reload(build_filter(config)?)
+        .map_err(|e| ServerError::Internal(format!("Failed to update log filter: {}", e)))
+}
+
+// TUIのログ欄に表示する1件分のログ
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,              // messageとその他のフィールド（key=value）
+    pub request: Option<RequestInfo>, // APIのリクエスト中に出力されたログの場合
+}
+
+// requestスパンに記録されたリクエストの情報
+#[derive(Clone, Debug, Default)]
+pub struct RequestInfo {
+    pub id: u64,
+    pub method: String,
+    pub path: String,
+    pub status: Option<u16>,
+    pub latency_ms: Option<f64>,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:>5} {}",
+            self.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.level,
+            self.message
+        )?;
+        if let Some(ref request) = self.request {
+            write!(f, " request_id={}", request.id)?;
+            if let Some(latency_ms) = request.latency_ms {
+                write!(f, " latency_ms={:.2}", latency_ms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// イベントをLogEntryにしてTUIに送るレイヤー
+struct TuiLayer {
+    sender: mpsc::UnboundedSender<ServerMessage>,
+}
+
+impl<S> Layer<S> for TuiLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    // requestスパンのフィールドを、後でイベントから参照できるよう保持しておく
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "request" {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            let mut request = RequestInfo::default();
+            attrs.record(&mut RequestVisitor(&mut request));
+            span.extensions_mut().insert(request);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(request) = span.extensions_mut().get_mut::<RequestInfo>()
+        {
+            values.record(&mut RequestVisitor(request));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut message = String::new();
+        event.record(&mut LineVisitor(&mut message));
+
+        let request = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<RequestInfo>().cloned())
+        });
+
+        let _ = self.sender.send(ServerMessage::Log(LogEntry {
+            time: chrono::Local::now(),
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message,
+            request,
+        }));
+    }
+}
+
+// messageを先頭に、その他のフィールドを key=value で続ける
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.push_str(value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+// requestスパンのフィールドをRequestInfoに記録する
+struct RequestVisitor<'a>(&'a mut RequestInfo);
+
+impl Visit for RequestVisitor<'_> {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "id" => self.0.id = value,
+            "status" => self.0.status = u16::try_from(value).ok(),
+            _ => {}
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "latency_ms" {
+            self.0.latency_ms = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "method" => self.0.method = value.to_string(),
+            "path" => self.0.path = value.to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        // %methodや%pathはDisplayとして記録されるためここに来る
+        match field.name() {
+            "method" => self.0.method = format!("{:?}", value),
+            "path" => self.0.path = format!("{:?}", value),
+            _ => {}
+        }
+    }
+}
@@ -1,57 +1,146 @@
+use crate::cli::{Cli, Command};
 use crate::server_manager::ServerManager;
 use crate::ui::App;
-use color_eyre::eyre::Result;
-use server::ServerConfig;
+use clap::Parser;
+use server::{
+    ServerConfig,
+    encryption::{self, UnlockState},
+    error::{ServerError, ServerResult},
+    logging::{self, LogOutput},
+    message_store::MessageStore,
+    paths,
+};
+use std::io::IsTerminal;
+use std::process::ExitCode;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
+mod cli;
 mod server_manager;
 mod ui;
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
+// SQLiteの処理やTUIの描画がHTTPサーバーを止めないようマルチスレッドで動かす
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Some(data_dir) = cli.data_dir {
+        paths::set_data_dir(data_dir);
+    } else if cli.portable {
+        paths::set_portable();
+    }
+
+    let result = match cli.command {
+        None | Some(Command::Serve { headless: false }) => serve_tui().await,
+        Some(Command::Serve { headless: true }) => cli::serve_headless().await,
+        Some(Command::Setup {
+            nickname,
+            password_stdin,
+            force,
+        }) => cli::setup(nickname, password_stdin, force),
+        Some(Command::Passwd { password_stdin }) => cli::passwd(password_stdin),
+        Some(Command::Config { command }) => cli::config(command),
+        Some(Command::Messages { command }) => cli::messages(command).await,
+        Some(Command::Encryption { command }) => cli::encryption(command).await,
+        Some(Command::Status) => cli::status().await,
+    };
+    cli::report(result)
+}
+
+// TUIでサーバーを起動する
+async fn serve_tui() -> ServerResult<ExitCode> {
+    color_eyre::install().map_err(|e| ServerError::Internal(e.to_string()))?;
 
     // 設定ファイルの存在確認とセットアップ
-    match ServerConfig::load_or_create() {
-        Ok(_) => {
-            // 設定ファイルが存在する場合は何もしない
-        }
-        Err(_) => {
-            // 設定ファイルが存在しない場合はセットアップを実行
+    let config = match ServerConfig::load_or_create() {
+        Ok(config) => config,
+        // 設定ファイルが存在しない場合はセットアップを実行（端末がない場合はエラーで終了）
+        Err(ServerError::ConfigNotFound) if std::io::stdin().is_terminal() => {
             println!("=== Sure-Shot Server 初期設定 ===");
             println!("設定ファイルが見つからないため、初期設定を開始します。");
 
-            match ServerConfig::create_with_setup() {
-                Ok(_) => {
-                    println!("設定が正常に保存されました。サーバーを起動します...");
-                }
-                Err(e) => {
-                    eprintln!("設定の作成に失敗しました: {}", e);
-                    return Ok(());
-                }
-            }
+            let config = ServerConfig::create_with_setup()?;
+            println!("設定が正常に保存されました。サーバーを起動します...");
+            config
         }
-    }
+        Err(e) => return Err(e),
+    };
 
     // サーバーメッセージを送信するためのチャンネル
     let (message_sender, message_receiver) = mpsc::unbounded_channel();
 
+    // ログはTUIとデータディレクトリのログファイルに出力する（TUI起動後は標準出力に書かない）
+    let _logging = logging::init(&config.log_config, LogOutput::Tui(message_sender.clone()))?;
+
+    // 鍵ファイルか環境変数で解錠できなければ、TUIでパスフレーズを入力させる
+    let unlock_state = encryption::unlock_without_prompt(config.encryption.key_file.as_deref())?;
+
     // サーバーマネージャーを作成し、UIと共有
     let server_manager = Arc::new(ServerManager::new(message_sender));
 
     // TUIを起動（server_managerを渡す）
-    let terminal = ratatui::init();
-    let tui_result = App::new(message_receiver, server_manager.clone())
-        .run(terminal)
-        .await;
+    // SIGTERMを受けた場合もTUIを閉じてからサーバーを止める
+    let mut terminal = ratatui::init();
+
+    // Messagesタブ用（サーバーの停止中も保存済みのメッセージを見られるよう別に開く）
+    let message_store = match open_message_store(&mut terminal, unlock_state).await {
+        Ok(Some(store)) => store,
+        result => {
+            ratatui::restore();
+            return result.map(|_| ExitCode::SUCCESS);
+        }
+    };
+
+    // サーバーのタスクでのpanicは監視タスクが再起動するため、端末を元に戻さずログに残すだけにする
+    // （spawnしたタスクはワーカースレッドで動き、TUIはこのスレッドで動く）
+    let tui_thread = std::thread::current().id();
+    let tui_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().id() == tui_thread {
+            tui_hook(info);
+        } else {
+            tracing::error!("{}", info);
+        }
+    }));
+    // タブのクリックやホイールでのスクロールを受け取る
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::event::EnableMouseCapture);
+    // TUIで終了した場合はサーバーを止めてから終了している（もう一度押して待たずに終了した場合も含む）
+    let (tui_result, stopped) = tokio::select! {
+        result = App::new(message_receiver, server_manager.clone(), &config, message_store)
+            .run(terminal) => {
+                let stopped = result.is_ok();
+                (result, stopped)
+            }
+        _ = cli::shutdown_signal() => {
+            tracing::info!("Shutdown signal received");
+            (Ok(()), false)
+        }
+    };
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::event::DisableMouseCapture);
     ratatui::restore();
 
     // サーバーを停止
-    if let Err(e) = server_manager.stop_server().await {
+    if !stopped && let Err(e) = server_manager.stop_server().await {
         eprintln!("Failed to stop server: {}", e);
     }
 
-    println!("Application finished with TUI result: {:?}", tui_result);
-    Ok(())
+    match tui_result {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(e) => Err(ServerError::Internal(format!("TUI error: {}", e))),
+    }
+}
+
+// 必要ならパスフレーズを入力させてからメッセージのデータベースを開く（入力をやめた場合はNone）
+async fn open_message_store(
+    terminal: &mut ratatui::DefaultTerminal,
+    unlock_state: UnlockState,
+) -> ServerResult<Option<Arc<MessageStore>>> {
+    if let UnlockState::NeedsPassphrase(info) = unlock_state {
+        let unlocked = ui::unlock(terminal, &info)
+            .await
+            .map_err(|e| ServerError::Internal(format!("TUI error: {}", e)))?;
+        if !unlocked {
+            return Ok(None);
+        }
+    }
+    Ok(Some(Arc::new(MessageStore::new(None)?)))
 }
@@ -1,26 +1,62 @@
-use crate::ReceivedMessage;
-use rusqlite::Connection;
-use std::path::PathBuf;
+use crate::encryption::{self, EncryptionInfo, KeySource, StoreKey};
+use crate::error::{ServerError, ServerResult};
+use crate::{
+    MessageDeleted, MessageEdit, ReceivedMessage, RetentionConfig, ServerEvent, StreamEvent,
+};
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
+// 再送用に保持するイベント数
+const EVENT_LOG_SIZE: i64 = 10_000;
+
+// 読み込み用の接続数（WALモードでは書き込み中でも並行して読める）
+const READER_CONNECTIONS: usize = 4;
+
+// 他の接続がロックを持っている場合に待つ時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 接続ごとにキャッシュするプリペアドステートメントの数
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+
+// バックアップは一度に全ページを写す（途中で書き込まれてもやり直しにならないようにする）
+const BACKUP_ALL_PAGES: std::os::raw::c_int = -1;
+
+// バックアップ先がロックされていた場合に写し直すまでの間隔
+const BACKUP_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// SQLiteの呼び出しはブロッキングするため、spawn_blockingでランタイムの外で実行する
+// 書き込みは1本の接続に直列化し、読み込みは複数の接続で並行に行う
 #[derive(Debug)]
 pub struct MessageStore {
-    connection: Arc<Mutex<Connection>>,
+    writer: ConnectionPool,
+    readers: ConnectionPool,
+    codec: Codec,
 }
 
 impl MessageStore {
-    pub fn new(db_path: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let path = db_path.unwrap_or_else(|| {
-            let mut path = dirs::data_dir().expect("Could not find data directory");
-            path.push("sure-shot");
-            std::fs::create_dir_all(&path).expect("Failed to create data directory");
-            path.push("messages.db");
-            path
-        });
+    // 暗号化されたデータベースはencryption::set_unlocked_keyで解錠してから開く
+    pub fn new(db_path: Option<PathBuf>) -> ServerResult<Self> {
+        Self::open(db_path, encryption::unlocked_key())
+    }
+
+    // 鍵を指定して開く（暗号化していないデータベースでは使わない）
+    fn open(db_path: Option<PathBuf>, unlocked: Option<Arc<StoreKey>>) -> ServerResult<Self> {
+        let path = Self::resolve_path(db_path)?;
+
+        let conn = Self::open_connection(&path)?;
+
+        // 新規DBではincremental_vacuumを使えるようにする（既存DBは初回のvacuumで切り替え）
+        conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL")?;
+
+        // 複数の接続から同時に読み書きできるようにする（DBファイルに記録される）
+        conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
+            row.get::<_, String>(0)
+        })?;
 
-        let conn = Connection::open(path)?;
-        
         // テーブルを作成
         conn.execute(
             "CREATE TABLE IF NOT EXISTS messages (
@@ -41,7 +77,7 @@ impl MessageStore {
             "CREATE INDEX IF NOT EXISTS idx_timestamp ON messages(timestamp)",
             [],
         )?;
-        
+
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_from_ip ON messages(from_ip)",
             [],
@@ -52,175 +88,1640 @@ impl MessageStore {
             [],
         )?;
 
+        // 編集履歴テーブルを作成
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS message_edits (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_uid TEXT NOT NULL,
+                previous_message TEXT NOT NULL,
+                edited_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_edits_message_uid ON message_edits(message_uid)",
+            [],
+        )?;
+
+        // 再送用のイベントログ（本体はmessagesテーブルから復元する）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                message_uid TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+
+        // 暗号化している場合の鍵の情報（1行だけ。行がなければ暗号化していない）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS encryption (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                kdf TEXT NOT NULL,
+                salt BLOB NOT NULL,
+                iterations INTEGER NOT NULL,
+                check_value BLOB NOT NULL
+            )",
+            [],
+        )?;
+
+        Self::migrate(&conn)?;
+
+        let codec = match Self::read_encryption_info(&conn)? {
+            Some(info) => match unlocked {
+                Some(key) if key.verify(&info) => Codec { key: Some(key) },
+                Some(_) => return Err(ServerError::InvalidKey),
+                None => return Err(ServerError::StoreLocked),
+            },
+            None => Codec::default(),
+        };
+        Self::count_missing_attachments(&conn, &codec)?;
+
+        // スキーマの準備ができてから読み込み用の接続を開く
+        let readers = (0..READER_CONNECTIONS)
+            .map(|_| Self::open_connection(&path))
+            .collect::<Result<Vec<_>, _>>()?;
+
         Ok(Self {
-            connection: Arc::new(Mutex::new(conn)),
+            writer: ConnectionPool::new(&path, vec![conn]),
+            readers: ConnectionPool::new(&path, readers),
+            codec,
         })
     }
 
-    pub async fn save_message(&self, message: &ReceivedMessage) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
-        let json_data = serde_json::to_string(message)?;
-        let conn = self.connection.lock().await;
-        
-        let id = conn.execute(
-            "INSERT INTO messages (timestamp, from_ip, from_name, is_self, message_type, data) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            (
-                &message.timestamp,
-                &message.from,
-                &message.from_name,
-                message.is_self,
-                &message.message_type,
-                &json_data,
-            ),
+    fn resolve_path(db_path: Option<PathBuf>) -> ServerResult<PathBuf> {
+        match db_path {
+            Some(path) => Ok(path),
+            None => Ok(crate::paths::data_dir()?.join("messages.db")),
+        }
+    }
+
+    // データベースが暗号化されていれば鍵の情報を返す（開く前に解錠が必要か調べる）
+    pub fn encryption_info(db_path: Option<PathBuf>) -> ServerResult<Option<EncryptionInfo>> {
+        let path = Self::resolve_path(db_path)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let conn = Self::open_connection(&path)?;
+        let has_table: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'encryption')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_table {
+            return Ok(None);
+        }
+        Self::read_encryption_info(&conn)
+    }
+
+    // メッセージ数とデータベースのサイズ（暗号化していても鍵なしで読める。データベースがなければ0）
+    pub fn summary(db_path: Option<PathBuf>) -> ServerResult<(i64, u64)> {
+        let path = Self::resolve_path(db_path)?;
+        if !path.exists() {
+            return Ok((0, 0));
+        }
+
+        let conn = Self::open_connection(&path)?;
+        let has_table: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'messages')",
+            [],
+            |row| row.get(0),
         )?;
+        let count = match has_table {
+            true => conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?,
+            false => 0,
+        };
+        Ok((count, Self::page_bytes(&conn)?))
+    }
+
+    fn read_encryption_info(conn: &Connection) -> ServerResult<Option<EncryptionInfo>> {
+        let row = conn
+            .query_row(
+                "SELECT kdf, salt, iterations, check_value FROM encryption WHERE id = 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, u32>(2)?,
+                        row.get::<_, Vec<u8>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
 
-        Ok(id as i64)
+        row.map(|(kdf, salt, iterations, check)| {
+            Ok(EncryptionInfo {
+                source: KeySource::parse(&kdf)?,
+                salt,
+                iterations,
+                check,
+            })
+        })
+        .transpose()
     }
 
-    pub async fn get_recent_messages(&self, limit: usize) -> Result<Vec<ReceivedMessage>, Box<dyn std::error::Error + Send + Sync>> {
-        let conn = self.connection.lock().await;
-        let mut stmt = conn.prepare(
-            "SELECT data FROM messages ORDER BY created_at DESC LIMIT ?1"
+    // 保存している本文と編集履歴を新しい鍵で暗号化し直す（Noneの場合は平文に戻す）
+    // 日時や送信元などの検索用のカラムと添付ファイルの集計は平文のまま残る。呼び出した後はストアを開き直す
+    pub async fn change_encryption(self, key: Option<Arc<StoreKey>>) -> ServerResult<usize> {
+        let current = self.codec.clone();
+        self.writer
+            .run(move |conn| {
+                let info = key.as_deref().map(StoreKey::info).transpose()?;
+                let next = Codec { key };
+
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                // 1件でも復号できなければ何も変更しない
+                let converted =
+                    Self::reencrypt_column(&tx, "messages", "data", "uid", &current, &next)?;
+                Self::reencrypt_column(
+                    &tx,
+                    "message_edits",
+                    "previous_message",
+                    "message_uid",
+                    &current,
+                    &next,
+                )?;
+
+                tx.execute("DELETE FROM encryption", [])?;
+                if let Some(info) = info {
+                    tx.execute(
+                        "INSERT INTO encryption (id, kdf, salt, iterations, check_value)
+                         VALUES (1, ?1, ?2, ?3, ?4)",
+                        (
+                            info.source.as_str(),
+                            &info.salt,
+                            info.iterations,
+                            &info.check,
+                        ),
+                    )?;
+                }
+                tx.commit()?;
+
+                // 変換前の内容が空きページやWALに残らないよう、ファイルを作り直す
+                conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")?;
+                conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+                Ok(converted)
+            })
+            .await
+    }
+
+    fn reencrypt_column(
+        conn: &Connection,
+        table: &str,
+        column: &str,
+        uid_column: &str,
+        current: &Codec,
+        next: &Codec,
+    ) -> ServerResult<usize> {
+        // 関連データのカラム名はCodecの定数と同じ形式（messages.dataなど）
+        let name = format!("{}.{}", table, column);
+
+        // 添付ファイルを含むため、まとめて読まずに1行ずつ変換する
+        let ids = conn
+            .prepare(&format!("SELECT id FROM {}", table))?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut select = conn.prepare(&format!(
+            "SELECT {}, {} FROM {} WHERE id = ?1",
+            uid_column, column, table
+        ))?;
+        let mut update = conn.prepare(&format!(
+            "UPDATE {} SET {} = ?1 WHERE id = ?2",
+            table, column
+        ))?;
+        let mut plaintext = 0;
+        for &id in &ids {
+            let (uid, value) = select.query_row([id], uid_and_data)?;
+            let text = match value {
+                // 暗号化しているのに平文で残っている行も、この機会に暗号化し直す
+                Value::Text(text) if current.is_encrypted() => {
+                    plaintext += 1;
+                    text
+                }
+                // 鍵は確認済みのため、復号できないのは書き換えられたか壊れた行
+                value => current.open(value, &name, &uid).map_err(|e| match e {
+                    ServerError::InvalidKey => ServerError::Internal(format!(
+                        "Cannot decrypt {} of message {} (modified or corrupted)",
+                        name, uid
+                    )),
+                    e => e,
+                })?,
+            };
+            update.execute((next.seal(text, &name, &uid)?, id))?;
+        }
+        if plaintext > 0 {
+            tracing::warn!("{} unencrypted rows found in {}", plaintext, name);
+        }
+        Ok(ids.len())
+    }
+
+    fn open_connection(path: &Path) -> ServerResult<Connection> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        // WALでは接続ごとの設定（開き直した接続でも同じにする）
+        conn.execute_batch("PRAGMA synchronous = NORMAL")?;
+        Ok(conn)
+    }
+
+    // 既存DBに後から追加したカラムを補う
+    fn migrate(conn: &Connection) -> ServerResult<()> {
+        let columns = conn
+            .prepare("SELECT name FROM pragma_table_info('messages')")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !columns.iter().any(|c| c == "uid") {
+            conn.execute("ALTER TABLE messages ADD COLUMN uid TEXT", [])?;
+        }
+        if !columns.iter().any(|c| c == "pinned") {
+            conn.execute(
+                "ALTER TABLE messages ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0",
+                [],
+            )?;
+        }
+        // 添付ファイルの集計（暗号化していても復号せずに数えられるよう平文で持つ。NULLはまだ数えていない行）
+        if !columns.iter().any(|c| c == "attachment_count") {
+            conn.execute_batch(
+                "ALTER TABLE messages ADD COLUMN attachment_count INTEGER;
+                 ALTER TABLE messages ADD COLUMN attachment_bytes INTEGER;",
+            )?;
+        }
+
+        // IDを持たない古いメッセージにIDを割り当てる
+        let missing = conn
+            .prepare("SELECT id FROM messages WHERE uid IS NULL")?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for row_id in missing {
+            conn.execute(
+                "UPDATE messages SET uid = ?1, data = json_set(data, '$.id', ?1) WHERE id = ?2",
+                (uuid::Uuid::new_v4().to_string(), row_id),
+            )?;
+        }
+
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_uid ON messages(uid)",
+            [],
         )?;
 
-        let message_iter = stmt.query_map([limit], |row| {
-            let json_data: String = row.get(0)?;
-            Ok(json_data)
-        })?;
+        Ok(())
+    }
 
-        let mut messages = Vec::new();
-        for message_result in message_iter {
-            match message_result {
-                Ok(json_data) => {
-                    match serde_json::from_str::<ReceivedMessage>(&json_data) {
-                        Ok(message) => messages.push(message),
-                        Err(e) => {
-                            eprintln!("Failed to deserialize message: {}", e);
-                            // 破損したメッセージはスキップ
-                        }
+    pub async fn save_message(&self, message: &ReceivedMessage) -> ServerResult<i64> {
+        let message = message.clone();
+        let codec = self.codec.clone();
+        self.writer
+            .run(move |conn| {
+                let data = codec.encode(&message)?;
+                let (attachment_count, attachment_bytes) = attachment_totals(&message);
+                conn.prepare_cached(
+                    "INSERT INTO messages (timestamp, from_ip, from_name, is_self, message_type, data, uid, pinned, attachment_count, attachment_bytes)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                )?
+                .execute((
+                    &message.timestamp,
+                    &message.from,
+                    &message.from_name,
+                    message.is_self,
+                    &message.message_type,
+                    &data,
+                    &message.id,
+                    message.pinned,
+                    attachment_count,
+                    attachment_bytes,
+                ))?;
+
+                Ok(conn.last_insert_rowid())
+            })
+            .await
+    }
+
+    pub async fn get_message(&self, id: &str) -> ServerResult<Option<ReceivedMessage>> {
+        let id = id.to_string();
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| Self::find_message(conn, &codec, &id))
+            .await
+    }
+
+    fn find_message(
+        conn: &Connection,
+        codec: &Codec,
+        id: &str,
+    ) -> ServerResult<Option<ReceivedMessage>> {
+        let data = conn
+            .prepare_cached("SELECT data FROM messages WHERE uid = ?1")?
+            .query_row([id], |row| row.get::<_, Value>(0));
+
+        match data {
+            Ok(data) => Ok(Some(codec.decode(data, id)?)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // 別の環境から持ってきたメッセージを保存する（同じIDのメッセージが既にあれば飛ばす）
+    // 全て保存するか、失敗した場合は何も保存しない。保存した件数を返す
+    pub async fn import_messages(&self, messages: Vec<ReceivedMessage>) -> ServerResult<usize> {
+        let codec = self.codec.clone();
+        self.writer
+            .run(move |conn| {
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let mut imported = 0;
+                {
+                    // 保存日時には元の日時を使う（保持期間の判定や並び順が書き出した時と同じになる）
+                    let mut stmt = tx.prepare_cached(
+                        "INSERT INTO messages (timestamp, from_ip, from_name, is_self, message_type, data, uid, pinned, created_at, attachment_count, attachment_bytes)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, COALESCE(?9, CURRENT_TIMESTAMP), ?10, ?11)
+                         ON CONFLICT(uid) DO NOTHING",
+                    )?;
+                    for message in &messages {
+                        let sent_at = chrono::DateTime::parse_from_rfc3339(&message.timestamp)
+                            .ok()
+                            .map(|time| time.with_timezone(&chrono::Utc));
+                        let (attachment_count, attachment_bytes) = attachment_totals(message);
+                        imported += stmt.execute((
+                            sent_at.map_or_else(|| message.timestamp.clone(), |time| time.to_rfc3339()),
+                            &message.from,
+                            &message.from_name,
+                            message.is_self,
+                            &message.message_type,
+                            codec.encode(message)?,
+                            &message.id,
+                            message.pinned,
+                            sent_at.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()),
+                            attachment_count,
+                            attachment_bytes,
+                        ))?;
                     }
                 }
+                tx.commit()?;
+                Ok(imported)
+            })
+            .await
+    }
+
+    // 本文を編集し、編集前の本文を履歴に残す
+    pub async fn edit_message(
+        &self,
+        id: &str,
+        new_text: &str,
+    ) -> ServerResult<Option<ReceivedMessage>> {
+        let id = id.to_string();
+        let new_text = new_text.to_string();
+        let codec = self.codec.clone();
+        self.writer
+            .run(move |conn| {
+                // 読んでから書き込むため、最初から書き込みロックを取る
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+                let Some(mut message) = Self::find_message(&tx, &codec, &id)? else {
+                    return Ok(None);
+                };
+
+                let edited_at = chrono::Utc::now().to_rfc3339();
+                tx.prepare_cached(
+                    "INSERT INTO message_edits (message_uid, previous_message, edited_at) VALUES (?1, ?2, ?3)",
+                )?
+                .execute((
+                    &id,
+                    codec.seal(message.message.clone(), EDIT_PREVIOUS_MESSAGE, &id)?,
+                    &edited_at,
+                ))?;
+
+                message.message = new_text;
+                message.edited_at = Some(edited_at);
+                tx.prepare_cached("UPDATE messages SET data = ?1 WHERE uid = ?2")?
+                    .execute((codec.encode(&message)?, &id))?;
+
+                tx.commit()?;
+                Ok(Some(message))
+            })
+            .await
+    }
+
+    pub async fn set_pinned(
+        &self,
+        id: &str,
+        pinned: bool,
+    ) -> ServerResult<Option<ReceivedMessage>> {
+        let id = id.to_string();
+        let codec = self.codec.clone();
+        self.writer
+            .run(move |conn| {
+                let Some(mut message) = Self::find_message(conn, &codec, &id)? else {
+                    return Ok(None);
+                };
+
+                message.pinned = pinned;
+                conn.prepare_cached("UPDATE messages SET data = ?1, pinned = ?2 WHERE uid = ?3")?
+                    .execute((codec.encode(&message)?, pinned, &id))?;
+
+                Ok(Some(message))
+            })
+            .await
+    }
+
+    // メッセージと添付ファイル、編集履歴を削除する
+    pub async fn delete_message(&self, id: &str) -> ServerResult<bool> {
+        let id = id.to_string();
+        self.writer
+            .run(move |conn| {
+                let deleted = conn
+                    .prepare_cached("DELETE FROM messages WHERE uid = ?1")?
+                    .execute([&id])?;
+                conn.prepare_cached("DELETE FROM message_edits WHERE message_uid = ?1")?
+                    .execute([&id])?;
+
+                // 添付ファイルのデータが残らないよう空きページを回収する
+                if deleted > 0 {
+                    Self::incremental_vacuum(conn)?;
+                }
+
+                Ok(deleted > 0)
+            })
+            .await
+    }
+
+    pub async fn get_edit_history(&self, id: &str) -> ServerResult<Vec<MessageEdit>> {
+        let id = id.to_string();
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT message_uid, previous_message, edited_at FROM message_edits
+                     WHERE message_uid = ?1 ORDER BY id ASC",
+                )?;
+
+                let rows = stmt
+                    .query_map([&id], |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, Value>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                rows.into_iter()
+                    .map(|(message_id, previous_message, edited_at)| {
+                        let previous_message =
+                            codec.open(previous_message, EDIT_PREVIOUS_MESSAGE, &message_id)?;
+                        Ok(MessageEdit {
+                            message_id,
+                            previous_message,
+                            edited_at,
+                        })
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    pub async fn get_recent_messages(&self, limit: usize) -> ServerResult<Vec<ReceivedMessage>> {
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| {
+                // 読み込んだ古いメッセージが新しく見えないよう、保存順ではなく日時順にする
+                let mut stmt = conn.prepare_cached(
+                    "SELECT uid, data FROM messages ORDER BY timestamp DESC, id DESC LIMIT ?1",
+                )?;
+                let rows = stmt.query_map([limit], uid_and_data)?;
+                let mut messages = decode_messages(&codec, rows);
+
+                // 時系列順に戻す（最新が最後）
+                messages.reverse();
+                Ok(messages)
+            })
+            .await
+    }
+
+    // 全てのメッセージを時系列順に返す（エクスポート用）
+    pub async fn get_all_messages(&self) -> ServerResult<Vec<ReceivedMessage>> {
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT uid, data FROM messages ORDER BY timestamp ASC, id ASC",
+                )?;
+                let rows = stmt.query_map([], uid_and_data)?;
+                Ok(decode_messages(&codec, rows))
+            })
+            .await
+    }
+
+    pub async fn get_messages_by_date_range(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        limit: Option<usize>,
+    ) -> ServerResult<Vec<ReceivedMessage>> {
+        let start_date = start_date.to_string();
+        let end_date = end_date.to_string();
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| {
+                // LIMITに負の値を渡すと上限なしになる
+                let mut stmt = conn.prepare_cached(
+                    "SELECT uid, data FROM messages
+                     WHERE timestamp BETWEEN ?1 AND ?2
+                     ORDER BY timestamp ASC
+                     LIMIT ?3",
+                )?;
+                let rows =
+                    stmt.query_map((&start_date, &end_date, sql_limit(limit)), uid_and_data)?;
+
+                Ok(decode_messages(&codec, rows))
+            })
+            .await
+    }
+
+    pub async fn get_message_count(&self) -> ServerResult<i64> {
+        self.readers
+            .run(|conn| {
+                let count: i64 = conn
+                    .prepare_cached("SELECT COUNT(*) FROM messages")?
+                    .query_row([], |row| row.get(0))?;
+                Ok(count)
+            })
+            .await
+    }
+
+    pub async fn search_messages(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+    ) -> ServerResult<Vec<ReceivedMessage>> {
+        let query = query.to_string();
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| {
+                if codec.is_encrypted() {
+                    return Self::search_encrypted(conn, &codec, &query, limit);
+                }
+
+                let mut stmt = conn.prepare_cached(
+                    "SELECT uid, data FROM messages
+                     WHERE data LIKE '%' || ?1 || '%'
+                     ORDER BY timestamp DESC
+                     LIMIT ?2",
+                )?;
+                let rows = stmt.query_map((&query, sql_limit(limit)), uid_and_data)?;
+                let mut messages = decode_messages(&codec, rows);
+
+                messages.reverse(); // 時系列順に戻す
+                Ok(messages)
+            })
+            .await
+    }
+
+    // 暗号化している場合はSQLで絞り込めないため、新しい方から復号して探す
+    // （LIKEと同じく英字の大文字と小文字は区別しない）
+    fn search_encrypted(
+        conn: &Connection,
+        codec: &Codec,
+        query: &str,
+        limit: Option<usize>,
+    ) -> ServerResult<Vec<ReceivedMessage>> {
+        let query = query.to_ascii_lowercase();
+        let mut stmt =
+            conn.prepare_cached("SELECT uid, data FROM messages ORDER BY timestamp DESC")?;
+        let mut rows = stmt.query([])?;
+
+        let mut messages = Vec::new();
+        while let Some(row) = rows.next()? {
+            if limit.is_some_and(|limit| messages.len() >= limit) {
+                break;
+            }
+            let uid: String = row.get(0)?;
+            let json_data = match codec.open(row.get(1)?, MESSAGE_DATA, &uid) {
+                Ok(json_data) => json_data,
                 Err(e) => {
-                    eprintln!("Database error: {}", e);
+                    tracing::warn!("Failed to decrypt message: {}", e);
+                    continue;
                 }
+            };
+            if !json_data.to_ascii_lowercase().contains(&query) {
+                continue;
+            }
+            match serde_json::from_str::<ReceivedMessage>(&json_data) {
+                Ok(message) => messages.push(message),
+                Err(e) => tracing::warn!("Failed to deserialize message: {}", e),
             }
         }
 
-        // 時系列順に戻す（最新が最後）
-        messages.reverse();
+        messages.reverse(); // 時系列順に戻す
         Ok(messages)
     }
 
-    pub async fn get_messages_by_date_range(
-        &self, 
-        start_date: &str, 
-        end_date: &str, 
-        limit: Option<usize>
-    ) -> Result<Vec<ReceivedMessage>, Box<dyn std::error::Error + Send + Sync>> {
-        let conn = self.connection.lock().await;
-        
-        let query = if let Some(limit_val) = limit {
-            format!(
-                "SELECT data FROM messages 
-                 WHERE timestamp BETWEEN ?1 AND ?2 
-                 ORDER BY timestamp ASC 
-                 LIMIT {}",
-                limit_val
-            )
-        } else {
-            "SELECT data FROM messages 
-             WHERE timestamp BETWEEN ?1 AND ?2 
-             ORDER BY timestamp ASC".to_string()
+    // 再送可能なイベントを記録し、イベントIDを返す
+    pub async fn record_event(&self, event: &ServerEvent) -> ServerResult<Option<i64>> {
+        let Some(message_id) = event.message_id() else {
+            return Ok(None);
         };
 
-        let mut stmt = conn.prepare(&query)?;
-        let message_iter = stmt.query_map([start_date, end_date], |row| {
-            let json_data: String = row.get(0)?;
-            Ok(json_data)
-        })?;
+        let kind = event.event_name();
+        let message_id = message_id.to_string();
+        self.writer
+            .run(move |conn| {
+                conn.prepare_cached("INSERT INTO events (kind, message_uid) VALUES (?1, ?2)")?
+                    .execute((kind, &message_id))?;
+                Ok(Some(conn.last_insert_rowid()))
+            })
+            .await
+    }
 
-        let mut messages = Vec::new();
-        for message_result in message_iter {
-            match message_result {
-                Ok(json_data) => {
-                    match serde_json::from_str::<ReceivedMessage>(&json_data) {
-                        Ok(message) => messages.push(message),
-                        Err(e) => {
-                            eprintln!("Failed to deserialize message: {}", e);
+    pub async fn latest_event_id(&self) -> ServerResult<i64> {
+        self.readers
+            .run(|conn| {
+                let id: Option<i64> = conn
+                    .prepare_cached("SELECT MAX(id) FROM events")?
+                    .query_row([], |row| row.get(0))?;
+                Ok(id.unwrap_or(0))
+            })
+            .await
+    }
+
+    // 指定したID以降のイベントの件数
+    // 間のイベントが既に削除されている場合や、記録にないIDの場合は再送できないためNone
+    pub async fn count_events_since(&self, after: i64) -> ServerResult<Option<usize>> {
+        self.readers
+            .run(move |conn| {
+                let (oldest, latest, count) = conn
+                    .prepare_cached(
+                        "SELECT MIN(id), MAX(id), COUNT(*) FILTER (WHERE id > ?1) FROM events",
+                    )?
+                    .query_row([after], |row| {
+                        Ok((
+                            row.get::<_, Option<i64>>(0)?,
+                            row.get::<_, Option<i64>>(1)?,
+                            row.get::<_, i64>(2)?,
+                        ))
+                    })?;
+                let replayable = match (oldest, latest) {
+                    (Some(oldest), Some(latest)) => after >= oldest - 1 && after <= latest,
+                    _ => after == 0,
+                };
+                Ok(replayable.then_some(count as usize))
+            })
+            .await
+    }
+
+    // 指定したID以降のイベントを、メッセージの現在の内容で復元して返す
+    pub async fn get_events_since(&self, after: i64) -> ServerResult<Vec<StreamEvent>> {
+        let codec = self.codec.clone();
+        self.readers
+            .run(move |conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT events.id, events.kind, events.message_uid, messages.data
+                     FROM events LEFT JOIN messages ON messages.uid = events.message_uid
+                     WHERE events.id > ?1 ORDER BY events.id ASC",
+                )?;
+
+                let rows = stmt
+                    .query_map([after], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, Option<Value>>(3)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut events = Vec::new();
+                for (id, kind, message_uid, data) in rows {
+                    let event = match (kind.as_str(), data) {
+                        ("delete", _) => ServerEvent::Delete(MessageDeleted { id: message_uid }),
+                        (kind, Some(data)) => {
+                            let message = match codec.decode(data, &message_uid) {
+                                Ok(message) => message,
+                                Err(e) => {
+                                    tracing::warn!("Failed to deserialize message: {}", e);
+                                    continue;
+                                }
+                            };
+                            match kind {
+                                "message" => ServerEvent::Message(message),
+                                "edit" => ServerEvent::Edit(message),
+                                "pin" => ServerEvent::Pin(message),
+                                _ => continue,
+                            }
                         }
-                    }
+                        // 既に削除されたメッセージは後続のdeleteイベントに任せる
+                        (_, None) => continue,
+                    };
+                    events.push(StreamEvent {
+                        id: Some(id),
+                        event,
+                    });
                 }
-                Err(e) => {
-                    eprintln!("Database error: {}", e);
+
+                Ok(events)
+            })
+            .await
+    }
+
+    // データベースに接続して読めるかだけを確かめる（ヘルスチェック用）
+    pub async fn ping(&self) -> ServerResult<()> {
+        self.readers
+            .run(|conn| {
+                conn.query_row("SELECT 1", [], |_| Ok(()))?;
+                Ok(())
+            })
+            .await
+    }
+
+    // データベースの整合性チェック（全体を読むため時間がかかる）
+    pub async fn verify_integrity(&self) -> ServerResult<bool> {
+        self.readers
+            .run(|conn| {
+                let result: Result<String, rusqlite::Error> =
+                    conn.query_row("PRAGMA integrity_check", [], |row| row.get(0));
+
+                // 問題がなければ"ok"の1行だけが返り、それ以外は見つかった問題の一覧
+                match result {
+                    Ok(status) => Ok(status == "ok"),
+                    Err(_) => Ok(false),
+                }
+            })
+            .await
+    }
+
+    // サーバーを止めずにデータベースを別のファイルに写す
+    // 書き込み途中のファイルを残さないよう、一時ファイルに写してから置き換える
+    pub async fn backup(&self, path: PathBuf) -> ServerResult<()> {
+        self.readers
+            .run(move |conn| {
+                let mut partial = path.clone().into_os_string();
+                partial.push(".partial");
+                let partial = PathBuf::from(partial);
+                if partial.exists() {
+                    std::fs::remove_file(&partial)?;
+                }
+
+                let result = Self::backup_connection(conn, &partial);
+                if result.is_err() {
+                    let _ = std::fs::remove_file(&partial);
+                }
+                result?;
+                std::fs::rename(&partial, &path)?;
+                Ok(())
+            })
+            .await
+    }
+
+    fn backup_connection(conn: &Connection, path: &Path) -> ServerResult<()> {
+        use rusqlite::backup::{Backup, StepResult};
+
+        let mut destination = Connection::open(path)?;
+        let backup = Backup::new(conn, &mut destination)?;
+        let started = std::time::Instant::now();
+        loop {
+            match backup.step(BACKUP_ALL_PAGES)? {
+                StepResult::Done => return Ok(()),
+                _ if started.elapsed() < BUSY_TIMEOUT => std::thread::sleep(BACKUP_RETRY_INTERVAL),
+                result => {
+                    return Err(ServerError::Internal(format!(
+                        "Backup did not finish: {:?}",
+                        result
+                    )));
                 }
             }
         }
+    }
 
-        Ok(messages)
+    // データベースファイルのサイズ（バイト）
+    pub async fn database_size(&self) -> ServerResult<u64> {
+        self.readers.run(|conn| Self::page_bytes(conn)).await
     }
 
-    pub async fn get_message_count(&self) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
-        let conn = self.connection.lock().await;
-        let count: i64 = conn.query_row("SELECT COUNT(*) FROM messages", [], |row| {
-            row.get(0)
-        })?;
+    // データベースと添付ファイルが使っている容量（添付ファイルはBase64のまま保存した分）
+    pub async fn storage_stats(&self) -> ServerResult<StorageStats> {
+        self.readers
+            .run(|conn| {
+                let (attachment_count, attachment_bytes): (i64, i64) = conn
+                    .prepare_cached(
+                        "SELECT COALESCE(SUM(attachment_count), 0), COALESCE(SUM(attachment_bytes), 0)
+                         FROM messages",
+                    )?
+                    .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                Ok(StorageStats {
+                    database_bytes: Self::page_bytes(conn)?,
+                    attachment_count: attachment_count as usize,
+                    attachment_bytes: attachment_bytes as u64,
+                })
+            })
+            .await
+    }
+
+    // 集計のカラムを追加する前に保存したメッセージの添付ファイルを数える（開いたときに一度だけ）
+    // 読めない行は数えずに残し、次に開いたときに数え直す
+    fn count_missing_attachments(conn: &Connection, codec: &Codec) -> ServerResult<()> {
+        let rows = conn
+            .prepare("SELECT id, uid, data FROM messages WHERE attachment_count IS NULL")?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Value>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut update = conn.prepare(
+            "UPDATE messages SET attachment_count = ?1, attachment_bytes = ?2 WHERE id = ?3",
+        )?;
+        for (id, uid, data) in rows {
+            match codec.decode(data, &uid) {
+                Ok(message) => {
+                    let (count, bytes) = attachment_totals(&message);
+                    update.execute((count, bytes, id))?;
+                }
+                Err(e) => tracing::warn!("Cannot count attachments of message {}: {}", uid, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn page_bytes(conn: &Connection) -> ServerResult<u64> {
+        let size: i64 = conn
+            .prepare_cached(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            )?
+            .query_row([], |row| row.get(0))?;
+        Ok(size as u64)
+    }
+
+    // 保持ポリシーを適用し、削除した内容を返す
+    pub async fn apply_retention(&self, policy: &RetentionConfig) -> ServerResult<PruneReport> {
+        let policy = policy.clone();
+        let codec = self.codec.clone();
+        self.writer
+            .run(move |conn| {
+                let bytes_before = Self::page_bytes(conn)?;
+                let mut report = PruneReport {
+                    bytes_before,
+                    bytes_after: bytes_before,
+                    ..Default::default()
+                };
+
+                // 最大保持期間を超えたメッセージを削除
+                if let Some(days) = policy.max_age_days {
+                    report.expired = Self::delete_returning_uids(
+                        conn,
+                        "DELETE FROM messages WHERE pinned = 0 AND created_at < datetime('now', ?1)
+                         RETURNING uid",
+                        format!("-{} days", days),
+                        &mut report.deleted_ids,
+                    )?;
+                }
+
+                // 最大件数を超えた古いメッセージを削除（ピン留めしたメッセージは件数に数えない）
+                if let Some(max_messages) = policy.max_messages {
+                    report.over_count = Self::delete_returning_uids(
+                        conn,
+                        "DELETE FROM messages WHERE pinned = 0 AND id NOT IN (
+                            SELECT id FROM messages WHERE pinned = 0
+                            ORDER BY timestamp DESC, id DESC LIMIT ?1
+                         ) RETURNING uid",
+                        max_messages as i64,
+                        &mut report.deleted_ids,
+                    )?;
+                }
+
+                // 合計サイズの上限を超えた古いメッセージを削除（新しい方から積算）
+                // ピン留めしたメッセージも容量を使うため合計には含めるが、削除はしない
+                if let Some(max_mb) = policy.max_total_size_mb {
+                    report.over_size = Self::delete_returning_uids(
+                        conn,
+                        "DELETE FROM messages WHERE id IN (
+                            SELECT id FROM (
+                                SELECT id, SUM(LENGTH(data)) OVER (ORDER BY timestamp DESC, id DESC) AS total
+                                FROM messages
+                            ) WHERE total > ?1
+                         ) AND pinned = 0 RETURNING uid",
+                        (max_mb * 1024 * 1024) as i64,
+                        &mut report.deleted_ids,
+                    )?;
+                }
+
+                // 添付ファイルの保持期限を過ぎたものは本文を残してデータだけ削除
+                if let Some(days) = policy.attachment_ttl_days {
+                    report.attachments_stripped =
+                        Self::strip_expired_attachments(conn, &codec, &format!("-{} days", days))?;
+                }
+
+                // 削除されたメッセージの編集履歴を片付ける
+                if report.removed_messages() > 0 {
+                    conn.execute(
+                        "DELETE FROM message_edits WHERE message_uid NOT IN (SELECT uid FROM messages)",
+                        [],
+                    )?;
+                }
+
+                // 再送用のイベントログは直近の分だけ残す
+                conn.execute(
+                    "DELETE FROM events WHERE id <= (SELECT MAX(id) FROM events) - ?1",
+                    [EVENT_LOG_SIZE],
+                )?;
+
+                if report.removed_messages() > 0 || report.attachments_stripped > 0 {
+                    Self::vacuum_connection(conn)?;
+                    report.bytes_after = Self::page_bytes(conn)?;
+                }
+
+                Ok(report)
+            })
+            .await
+    }
+
+    // 削除したメッセージのuidを集め、削除した件数を返す（クライアントに削除を通知するため）
+    fn delete_returning_uids(
+        conn: &Connection,
+        sql: &str,
+        param: impl rusqlite::ToSql,
+        deleted: &mut Vec<String>,
+    ) -> ServerResult<usize> {
+        let uids = conn
+            .prepare(sql)?
+            .query_map([param], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = uids.len();
+        deleted.extend(uids);
         Ok(count)
     }
 
-    pub async fn search_messages(&self, query: &str, limit: Option<usize>) -> Result<Vec<ReceivedMessage>, Box<dyn std::error::Error + Send + Sync>> {
-        let conn = self.connection.lock().await;
-        
-        let sql_query = if let Some(limit_val) = limit {
-            format!(
-                "SELECT data FROM messages 
-                 WHERE data LIKE '%' || ?1 || '%' 
-                 ORDER BY timestamp DESC 
-                 LIMIT {}",
-                limit_val
-            )
-        } else {
-            "SELECT data FROM messages 
-             WHERE data LIKE '%' || ?1 || '%' 
-             ORDER BY timestamp DESC".to_string()
-        };
+    fn strip_expired_attachments(
+        conn: &Connection,
+        codec: &Codec,
+        modifier: &str,
+    ) -> ServerResult<usize> {
+        // 添付ファイルの集計で絞り込むため、暗号化していても対象の行だけを復号する
+        let mut stmt = conn.prepare(
+            "SELECT id, uid, data FROM messages
+             WHERE pinned = 0 AND created_at < datetime('now', ?1) AND attachment_count > 0",
+        )?;
+        let rows = stmt
+            .query_map([modifier], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Value>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let mut stmt = conn.prepare(&sql_query)?;
-        let message_iter = stmt.query_map([query], |row| {
-            let json_data: String = row.get(0)?;
-            Ok(json_data)
-        })?;
+        let mut stripped = 0;
+        for (id, uid, data) in rows {
+            let mut message = match codec.decode(data, &uid) {
+                Ok(message) => message,
+                Err(e) => {
+                    tracing::warn!("Failed to deserialize message: {}", e);
+                    continue;
+                }
+            };
 
-        let mut messages = Vec::new();
-        for message_result in message_iter {
-            match message_result {
-                Ok(json_data) => {
-                    match serde_json::from_str::<ReceivedMessage>(&json_data) {
-                        Ok(message) => messages.push(message),
-                        Err(e) => {
-                            eprintln!("Failed to deserialize message: {}", e);
-                        }
-                    }
+            let mut changed = false;
+            for attachment in message.attachments.iter_mut() {
+                if !attachment.data.is_empty() {
+                    attachment.data.clear();
+                    attachment.thumbnail = None;
+                    stripped += 1;
+                    changed = true;
                 }
+            }
+            if !changed {
+                continue;
+            }
+
+            conn.execute(
+                "UPDATE messages SET data = ?1, attachment_count = 0, attachment_bytes = 0
+                 WHERE id = ?2",
+                (codec.encode(&message)?, id),
+            )?;
+        }
+
+        Ok(stripped)
+    }
+
+    // 書き込み中の処理が終わるのを待ち、WALの内容をデータベースファイルに書き戻す（停止時に呼ぶ）
+    pub async fn flush(&self) -> ServerResult<()> {
+        self.writer
+            .run(|conn| {
+                conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+                Ok(())
+            })
+            .await
+    }
+
+    // 空き領域を回収する
+    pub async fn vacuum(&self) -> ServerResult<()> {
+        self.writer.run(|conn| Self::vacuum_connection(conn)).await
+    }
+
+    // incremental_vacuumに未対応の既存DBは一度だけ完全なVACUUMで切り替える
+    fn vacuum_connection(conn: &Connection) -> ServerResult<()> {
+        if !Self::incremental_vacuum(conn)? {
+            conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")?;
+        }
+        Ok(())
+    }
+
+    // incremental_vacuumに対応していればページを回収してtrueを返す
+    fn incremental_vacuum(conn: &Connection) -> ServerResult<bool> {
+        let auto_vacuum: i64 = conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+        if auto_vacuum != 2 {
+            return Ok(false);
+        }
+
+        // 1ステップごとに1ページ解放されるため最後まで実行する
+        let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+        Ok(true)
+    }
+}
+
+// SELECT uid, data の行を読む
+fn uid_and_data(row: &rusqlite::Row) -> rusqlite::Result<(String, Value)> {
+    Ok((row.get(0)?, row.get(1)?))
+}
+
+// 保存した行をメッセージに変換する（破損したメッセージはスキップ）
+fn decode_messages(
+    codec: &Codec,
+    rows: impl Iterator<Item = rusqlite::Result<(String, Value)>>,
+) -> Vec<ReceivedMessage> {
+    let mut messages = Vec::new();
+    for row in rows {
+        match row {
+            Ok((uid, data)) => match codec.decode(data, &uid) {
+                Ok(message) => messages.push(message),
                 Err(e) => {
-                    eprintln!("Database error: {}", e);
+                    tracing::warn!("Failed to deserialize message: {}", e);
                 }
+            },
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
             }
         }
+    }
+    messages
+}
 
-        messages.reverse(); // 時系列順に戻す
-        Ok(messages)
+// 暗号化するカラム（保存先の行のメッセージIDと合わせて関連データにする）
+const MESSAGE_DATA: &str = "messages.data";
+const EDIT_PREVIOUS_MESSAGE: &str = "message_edits.previous_message";
+
+// 本文の保存形式（暗号化している場合は暗号文のBLOB、していなければJSONのテキスト）
+// 暗号化している場合は平文の行を読まない（鍵を持たない誰かが書き換えた可能性がある）
+#[derive(Clone, Debug, Default)]
+struct Codec {
+    key: Option<Arc<StoreKey>>,
+}
+
+impl Codec {
+    fn is_encrypted(&self) -> bool {
+        self.key.is_some()
     }
 
-    // データベースの整合性チェック
-    pub async fn verify_integrity(&self) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        let conn = self.connection.lock().await;
-        let result: Result<i64, rusqlite::Error> = conn.query_row("PRAGMA integrity_check", [], |row| {
-            row.get(0)
+    fn seal(&self, text: String, column: &str, uid: &str) -> ServerResult<Value> {
+        match &self.key {
+            Some(key) => Ok(Value::Blob(key.seal(text.as_bytes(), &aad(column, uid))?)),
+            None => Ok(Value::Text(text)),
+        }
+    }
+
+    fn open(&self, value: Value, column: &str, uid: &str) -> ServerResult<String> {
+        match (value, &self.key) {
+            (Value::Text(text), None) => Ok(text),
+            (Value::Text(_), Some(_)) => Err(ServerError::Internal(format!(
+                "Unencrypted {} in encrypted store (message {})",
+                column, uid
+            ))),
+            (Value::Blob(sealed), Some(key)) => {
+                String::from_utf8(key.open(&sealed, &aad(column, uid))?)
+                    .map_err(|e| ServerError::Internal(format!("Invalid decrypted text: {}", e)))
+            }
+            (Value::Blob(_), None) => Err(ServerError::StoreLocked),
+            (other, _) => Err(ServerError::Internal(format!(
+                "Unexpected stored value: {:?}",
+                other.data_type()
+            ))),
+        }
+    }
+
+    fn encode(&self, message: &ReceivedMessage) -> ServerResult<Value> {
+        self.seal(serde_json::to_string(message)?, MESSAGE_DATA, &message.id)
+    }
+
+    fn decode(&self, value: Value, uid: &str) -> ServerResult<ReceivedMessage> {
+        let json_data = self.open(value, MESSAGE_DATA, uid)?;
+        Ok(serde_json::from_str(&json_data)?)
+    }
+}
+
+// 暗号文を別の行やカラムに移しても復号できないよう、カラムとメッセージのIDを関連データにする
+fn aad(column: &str, uid: &str) -> Vec<u8> {
+    format!("{}:{}", column, uid).into_bytes()
+}
+
+// 中身の残っている添付ファイルの数とサイズ（Base64のまま）
+fn attachment_totals(message: &ReceivedMessage) -> (i64, i64) {
+    message
+        .attachments
+        .iter()
+        .filter(|attachment| !attachment.data.is_empty())
+        .fold((0, 0), |(count, bytes), attachment| {
+            (count + 1, bytes + attachment.data.len() as i64)
+        })
+}
+
+// SQLiteのLIMITは負の値で上限なしになる
+fn sql_limit(limit: Option<usize>) -> i64 {
+    limit.map_or(-1, |limit| limit as i64)
+}
+
+// 空いている接続を1本借りて、ブロッキング用のスレッドで処理を実行する
+#[derive(Debug)]
+struct ConnectionPool {
+    path: PathBuf,
+    connections: Arc<std::sync::Mutex<Vec<Connection>>>,
+    available: Arc<Semaphore>,
+}
+
+impl ConnectionPool {
+    fn new(path: &Path, connections: Vec<Connection>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            available: Arc::new(Semaphore::new(connections.len())),
+            connections: Arc::new(std::sync::Mutex::new(connections)),
+        }
+    }
+
+    async fn run<T, F>(&self, task: F) -> ServerResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> ServerResult<T> + Send + 'static,
+    {
+        let _permit = self.available.clone().acquire_owned().await?;
+        let connections = self.connections.clone();
+        let path = self.path.clone();
+
+        tokio::task::spawn_blocking(move || {
+            // 処理中にパニックした接続は戻らないため、空いていなければ開き直す
+            let conn = connections.lock().unwrap_or_else(|e| e.into_inner()).pop();
+            let mut conn = match conn {
+                Some(conn) => conn,
+                None => MessageStore::open_connection(&path)?,
+            };
+            let result = task(&mut conn);
+            connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(conn);
+            result
+        })
+        .await?
+    }
+}
+
+// ストレージの使用状況
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StorageStats {
+    pub database_bytes: u64,
+    pub attachment_count: usize,
+    pub attachment_bytes: u64,
+}
+
+// 保持ポリシーの適用結果
+#[derive(Debug, Default, Clone)]
+pub struct PruneReport {
+    pub expired: usize,
+    pub over_count: usize,
+    pub over_size: usize,
+    pub attachments_stripped: usize,
+    pub deleted_ids: Vec<String>, // 削除したメッセージのuid
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl PruneReport {
+    pub fn removed_messages(&self) -> usize {
+        self.expired + self.over_count + self.over_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, timestamp: &str) -> ReceivedMessage {
+        ReceivedMessage {
+            id: id.to_string(),
+            from: "127.0.0.1".to_string(),
+            from_name: "tester".to_string(),
+            message: format!("message {}", id),
+            message_type: "text".to_string(),
+            timestamp: timestamp.to_string(),
+            is_self: false,
+            attachments: Vec::new(),
+            pinned: false,
+            edited_at: None,
+        }
+    }
+
+    // 暗号化していないストアにメッセージを保存してから、鍵ファイルの鍵で暗号化して開き直す
+    async fn encrypted_store(
+        dir: &Path,
+        messages: &[ReceivedMessage],
+    ) -> (MessageStore, Arc<StoreKey>) {
+        let path = dir.join("messages.db");
+        let store = MessageStore::open(Some(path.clone()), None).unwrap();
+        for message in messages {
+            store.save_message(message).await.unwrap();
+        }
+        let key = Arc::new(StoreKey::generate_key_file(&dir.join("messages.key")).unwrap());
+        store.change_encryption(Some(key.clone())).await.unwrap();
+        (
+            MessageStore::open(Some(path), Some(key.clone())).unwrap(),
+            key,
+        )
+    }
+
+    // テストからデータベースを直接書き換える
+    fn execute(dir: &Path, sql: &str) {
+        Connection::open(dir.join("messages.db"))
+            .unwrap()
+            .execute_batch(sql)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn encrypted_store_round_trips_messages_and_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) =
+            encrypted_store(dir.path(), &[message("m1", "2026-01-01T00:00:00Z")]).await;
+        store.edit_message("m1", "edited").await.unwrap();
+
+        let stored = store.get_message("m1").await.unwrap().unwrap();
+        assert_eq!(stored.message, "edited");
+        let history = store.get_edit_history("m1").await.unwrap();
+        assert_eq!(history[0].previous_message, "message m1");
+
+        let conn = Connection::open(dir.path().join("messages.db")).unwrap();
+        let kinds: (String, String) = conn
+            .query_row(
+                "SELECT typeof(m.data), typeof(e.previous_message)
+                 FROM messages m JOIN message_edits e ON e.message_uid = m.uid",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(kinds, ("blob".to_string(), "blob".to_string()));
+    }
+
+    #[tokio::test]
+    async fn wrong_or_missing_key_cannot_open_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) =
+            encrypted_store(dir.path(), &[message("m1", "2026-01-01T00:00:00Z")]).await;
+        drop(store);
+        let path = dir.path().join("messages.db");
+
+        let other = StoreKey::generate_key_file(&dir.path().join("other.key")).unwrap();
+        assert!(matches!(
+            MessageStore::open(Some(path.clone()), Some(Arc::new(other))),
+            Err(ServerError::InvalidKey)
+        ));
+        assert!(matches!(
+            MessageStore::open(Some(path), None),
+            Err(ServerError::StoreLocked)
+        ));
+    }
+
+    #[tokio::test]
+    async fn ciphertext_moved_to_another_message_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) = encrypted_store(
+            dir.path(),
+            &[
+                message("m1", "2026-01-01T00:00:00Z"),
+                message("m2", "2026-01-02T00:00:00Z"),
+            ],
+        )
+        .await;
+
+        execute(
+            dir.path(),
+            "UPDATE messages SET data = (SELECT data FROM messages WHERE uid = 'm1')
+             WHERE uid = 'm2'",
+        );
+        assert!(store.get_message("m1").await.is_ok());
+        assert!(matches!(
+            store.get_message("m2").await,
+            Err(ServerError::InvalidKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn ciphertext_moved_to_another_column_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) =
+            encrypted_store(dir.path(), &[message("m1", "2026-01-01T00:00:00Z")]).await;
+        store.edit_message("m1", "edited").await.unwrap();
+
+        // 編集前の本文をメッセージ本体の場所に移す
+        execute(
+            dir.path(),
+            "UPDATE messages SET data = (SELECT previous_message FROM message_edits
+             WHERE message_uid = 'm1') WHERE uid = 'm1'",
+        );
+        assert!(matches!(
+            store.get_message("m1").await,
+            Err(ServerError::InvalidKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn plaintext_row_in_encrypted_store_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let injected = message("m1", "2026-01-01T00:00:00Z");
+        let (store, key) = encrypted_store(dir.path(), std::slice::from_ref(&injected)).await;
+
+        let json = serde_json::to_string(&injected).unwrap();
+        execute(
+            dir.path(),
+            &format!(
+                "UPDATE messages SET data = '{}' WHERE uid = 'm1'",
+                json.replace('\'', "''")
+            ),
+        );
+        assert!(matches!(
+            store.get_message("m1").await,
+            Err(ServerError::Internal(reason)) if reason.contains("Unencrypted")
+        ));
+        // 一覧では読めない行を飛ばす
+        assert!(store.get_all_messages().await.unwrap().is_empty());
+
+        // 鍵を変え直すと平文の行も暗号化される
+        store.change_encryption(Some(key.clone())).await.unwrap();
+        let store = MessageStore::open(Some(dir.path().join("messages.db")), Some(key)).unwrap();
+        assert_eq!(store.get_message("m1").await.unwrap().unwrap().id, "m1");
+    }
+
+    fn plain_store(dir: &Path) -> MessageStore {
+        MessageStore::open(Some(dir.join("messages.db")), None).unwrap()
+    }
+
+    async fn stored_ids(store: &MessageStore) -> Vec<String> {
+        let messages = store.get_all_messages().await.unwrap();
+        messages.into_iter().map(|message| message.id).collect()
+    }
+
+    #[tokio::test]
+    async fn retention_by_age_spares_pinned_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = plain_store(dir.path());
+        let mut pinned = message("m1", "2020-01-01T00:00:00Z");
+        pinned.pinned = true;
+        // 読み込んだメッセージは送信日時を保存日時にする
+        store
+            .import_messages(vec![pinned, message("m2", "2020-01-02T00:00:00Z")])
+            .await
+            .unwrap();
+        store
+            .save_message(&message("m3", &chrono::Utc::now().to_rfc3339()))
+            .await
+            .unwrap();
+
+        let policy = RetentionConfig {
+            max_age_days: Some(30),
+            ..Default::default()
+        };
+        let report = store.apply_retention(&policy).await.unwrap();
+
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.deleted_ids, ["m2"]);
+        assert_eq!(stored_ids(&store).await, ["m1", "m3"]);
+    }
+
+    #[tokio::test]
+    async fn retention_by_count_does_not_count_pinned_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = plain_store(dir.path());
+        let mut pinned = message("m1", "2026-01-01T00:00:00Z");
+        pinned.pinned = true;
+        store.save_message(&pinned).await.unwrap();
+        for (id, day) in [("m2", 2), ("m3", 3), ("m4", 4)] {
+            let timestamp = format!("2026-01-{:02}T00:00:00Z", day);
+            store.save_message(&message(id, &timestamp)).await.unwrap();
+        }
+
+        let policy = RetentionConfig {
+            max_messages: Some(2),
+            ..Default::default()
+        };
+        let report = store.apply_retention(&policy).await.unwrap();
+
+        assert_eq!(report.over_count, 1);
+        assert_eq!(report.deleted_ids, ["m2"]);
+        assert_eq!(stored_ids(&store).await, ["m1", "m3", "m4"]);
+        // 上限以内になった後は何も削除しない
+        let again = store.apply_retention(&policy).await.unwrap();
+        assert!(again.deleted_ids.is_empty());
+    }
+
+    #[tokio::test]
+    async fn retention_by_size_drops_oldest_and_their_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = plain_store(dir.path());
+        for (id, day) in [("m1", 1), ("m2", 2), ("m3", 3)] {
+            let mut large = message(id, &format!("2026-01-{:02}T00:00:00Z", day));
+            large.message = "x".repeat(400 * 1024);
+            store.save_message(&large).await.unwrap();
+        }
+        store
+            .edit_message("m1", &"y".repeat(400 * 1024))
+            .await
+            .unwrap();
+
+        let policy = RetentionConfig {
+            max_total_size_mb: Some(1),
+            ..Default::default()
+        };
+        let report = store.apply_retention(&policy).await.unwrap();
+
+        assert_eq!(report.over_size, 1);
+        assert_eq!(report.deleted_ids, ["m1"]);
+        assert_eq!(stored_ids(&store).await, ["m2", "m3"]);
+        assert!(store.get_edit_history("m1").await.unwrap().is_empty());
+        assert!(report.bytes_after < report.bytes_before);
+    }
+
+    // 再送するイベントの種類とメッセージのIDと本文
+    fn replayed(events: &[StreamEvent]) -> Vec<(i64, &'static str, String)> {
+        events
+            .iter()
+            .map(|event| {
+                let id = event.id.unwrap();
+                match &event.event {
+                    ServerEvent::Message(message) => (id, "message", message.message.clone()),
+                    ServerEvent::Edit(message) => (id, "edit", message.message.clone()),
+                    ServerEvent::Delete(deleted) => (id, "delete", deleted.id.clone()),
+                    other => panic!("unexpected event {}", other.event_name()),
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn events_after_an_id_are_replayed_with_current_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = plain_store(dir.path());
+        for id in ["m1", "m2"] {
+            let message = message(id, "2026-01-01T00:00:00Z");
+            store.save_message(&message).await.unwrap();
+            store
+                .record_event(&ServerEvent::Message(message))
+                .await
+                .unwrap();
+        }
+        let edited = store.edit_message("m1", "edited").await.unwrap().unwrap();
+        store
+            .record_event(&ServerEvent::Edit(edited))
+            .await
+            .unwrap();
+        store.delete_message("m2").await.unwrap();
+        let delete = ServerEvent::Delete(MessageDeleted {
+            id: "m2".to_string(),
         });
-        
-        match result {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
+        assert_eq!(store.record_event(&delete).await.unwrap(), Some(4));
+
+        assert_eq!(store.latest_event_id().await.unwrap(), 4);
+        assert_eq!(store.count_events_since(1).await.unwrap(), Some(3));
+        // 削除されたメッセージの作成イベントは飛ばし、後のdeleteイベントで知らせる
+        assert_eq!(
+            replayed(&store.get_events_since(1).await.unwrap()),
+            [
+                (3, "edit", "edited".to_string()),
+                (4, "delete", "m2".to_string()),
+            ]
+        );
+        assert!(store.get_events_since(4).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pruned_or_unknown_event_ids_cannot_be_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = plain_store(dir.path());
+        assert_eq!(store.count_events_since(0).await.unwrap(), Some(0));
+        assert_eq!(store.count_events_since(5).await.unwrap(), None);
+
+        for id in ["m1", "m2", "m3"] {
+            let message = message(id, "2026-01-01T00:00:00Z");
+            store.save_message(&message).await.unwrap();
+            store
+                .record_event(&ServerEvent::Message(message))
+                .await
+                .unwrap();
         }
+        execute(dir.path(), "DELETE FROM events WHERE id <= 2");
+
+        // イベント1と2は削除済みのため、それより前からは再送できない
+        assert_eq!(store.count_events_since(0).await.unwrap(), None);
+        assert_eq!(store.count_events_since(1).await.unwrap(), None);
+        assert_eq!(store.count_events_since(2).await.unwrap(), Some(1));
+        assert_eq!(store.count_events_since(3).await.unwrap(), Some(0));
+        assert_eq!(store.count_events_since(99).await.unwrap(), None);
+    }
+
+    fn with_attachment(mut message: ReceivedMessage, data: &str) -> ReceivedMessage {
+        message.attachments.push(crate::Attachment {
+            id: "a1".to_string(),
+            filename: "note.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            size: data.len() as u64,
+            data: data.to_string(),
+            thumbnail: None,
+        });
+        message
+    }
+
+    async fn attachment_stats(store: &MessageStore) -> (usize, u64) {
+        let stats = store.storage_stats().await.unwrap();
+        (stats.attachment_count, stats.attachment_bytes)
+    }
+
+    #[tokio::test]
+    async fn attachment_stats_are_counted_without_decrypting() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, key) = encrypted_store(
+            dir.path(),
+            &[
+                with_attachment(message("m1", "2026-01-01T00:00:00Z"), "aGVsbG8="),
+                message("m2", "2026-01-02T00:00:00Z"),
+            ],
+        )
+        .await;
+        store
+            .save_message(&with_attachment(
+                message("m3", "2026-01-03T00:00:00Z"),
+                "d29ybGQ=",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(attachment_stats(&store).await, (2, 16));
+
+        // 集計のカラムを追加する前のデータベースは、開いたときに数える
+        drop(store);
+        execute(
+            dir.path(),
+            "UPDATE messages SET attachment_count = NULL, attachment_bytes = NULL",
+        );
+        let store = MessageStore::open(Some(dir.path().join("messages.db")), Some(key)).unwrap();
+        assert_eq!(attachment_stats(&store).await, (2, 16));
+    }
+
+    #[tokio::test]
+    async fn expired_attachments_are_stripped_and_uncounted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = plain_store(dir.path());
+        store
+            .import_messages(vec![with_attachment(
+                message("m1", "2020-01-01T00:00:00Z"),
+                "aGVsbG8=",
+            )])
+            .await
+            .unwrap();
+        store
+            .save_message(&with_attachment(
+                message("m2", &chrono::Utc::now().to_rfc3339()),
+                "d29ybGQ=",
+            ))
+            .await
+            .unwrap();
+
+        let policy = RetentionConfig {
+            attachment_ttl_days: Some(30),
+            ..Default::default()
+        };
+        let report = store.apply_retention(&policy).await.unwrap();
+
+        assert_eq!(report.attachments_stripped, 1);
+        assert!(report.deleted_ids.is_empty());
+        let stripped = store.get_message("m1").await.unwrap().unwrap();
+        assert!(stripped.attachments[0].data.is_empty());
+        assert_eq!(attachment_stats(&store).await, (1, 8));
     }
 }
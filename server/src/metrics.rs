@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// 毎秒のリクエスト数を保持する秒数
+const REQUEST_HISTORY_SECONDS: u64 = 60;
+// 毎分の受信メッセージ数を保持する分数
+const MESSAGE_HISTORY_MINUTES: u64 = 30;
+// エンドポイントごとにパーセンタイルの計算に使う直近のレイテンシ数
+const LATENCY_SAMPLES: usize = 512;
+// /metricsのレイテンシのヒストグラムの区切り（ミリ秒）
+const LATENCY_BUCKETS_MS: [f64; 12] = [
+    1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+// /metricsの項目名の接頭辞
+const METRIC_PREFIX: &str = "magazine";
+
+// サーバーの稼働状況の集計（TUIのStatsタブと/metricsで表示する）
+// サーバーを再起動しても値を引き継ぐよう、ServerManagerが1つだけ作って使い回す
+#[derive(Debug)]
+pub struct Metrics {
+    started_at: Instant,
+    sse_subscribers: Arc<AtomicUsize>,
+    recorder: Mutex<Recorder>,
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    requests: VecDeque<(u64, u64)>,       // (経過秒, リクエスト数)
+    messages: VecDeque<(u64, u64, u64)>,  // (経過分, メッセージ数, バイト数)
+    endpoints: HashMap<String, Endpoint>, // "GET /messages" のようなルートごと
+    messages_total: u64,
+    message_bytes_total: u64,
+    login_failures: BTreeMap<&'static str, u64>, // 失敗の理由ごと
+}
+
+#[derive(Debug, Default)]
+struct Endpoint {
+    requests: u64,
+    statuses: BTreeMap<u16, u64>,
+    buckets: [u64; LATENCY_BUCKETS_MS.len()], // 区切りごとの件数（累積ではない）
+    latency_sum_ms: f64,
+    latencies_ms: VecDeque<f64>,
+}
+
+// 集計結果
+#[derive(Clone, Debug, Default)]
+pub struct MetricsSnapshot {
+    pub uptime: Duration,
+    pub requests_per_second: Vec<u64>, // 古い順。現在の秒は集計中のため含めない
+    pub endpoints: Vec<EndpointStats>, // リクエスト数の多い順
+    pub sse_subscribers: usize,
+    pub messages_per_minute: Vec<u64>, // 古い順。最後が現在の分
+    pub bytes_per_minute: Vec<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EndpointStats {
+    pub endpoint: String,
+    pub requests: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+}
+
+// SSEの接続中だけ購読者数に数える（ストリームと一緒に破棄する）
+#[derive(Debug)]
+pub struct SubscriberGuard(Arc<AtomicUsize>);
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            sse_subscribers: Arc::new(AtomicUsize::new(0)),
+            recorder: Mutex::new(Recorder::default()),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    // 処理したリクエストを記録する
+    pub fn record_request(&self, endpoint: String, status: u16, latency_ms: f64) {
+        let second = self.uptime().as_secs();
+        let mut recorder = self.recorder.lock().unwrap();
+
+        match recorder.requests.back_mut() {
+            Some((last, count)) if *last == second => *count += 1,
+            _ => recorder.requests.push_back((second, 1)),
+        }
+        while recorder
+            .requests
+            .front()
+            .is_some_and(|(time, _)| *time + REQUEST_HISTORY_SECONDS < second)
+        {
+            recorder.requests.pop_front();
+        }
+
+        let endpoint = recorder.endpoints.entry(endpoint).or_default();
+        endpoint.requests += 1;
+        *endpoint.statuses.entry(status).or_default() += 1;
+        if let Some(bucket) = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+        {
+            endpoint.buckets[bucket] += 1;
+        }
+        endpoint.latency_sum_ms += latency_ms;
+        endpoint.latencies_ms.push_back(latency_ms);
+        if endpoint.latencies_ms.len() > LATENCY_SAMPLES {
+            endpoint.latencies_ms.pop_front();
+        }
+    }
+
+    // 受信したメッセージを記録する（バイト数は本文と添付ファイルの合計）
+    pub fn record_message(&self, bytes: u64) {
+        let minute = self.uptime().as_secs() / 60;
+        let mut recorder = self.recorder.lock().unwrap();
+        recorder.messages_total += 1;
+        recorder.message_bytes_total += bytes;
+
+        match recorder.messages.back_mut() {
+            Some((last, count, total)) if *last == minute => {
+                *count += 1;
+                *total += bytes;
+            }
+            _ => recorder.messages.push_back((minute, 1, bytes)),
+        }
+        while recorder
+            .messages
+            .front()
+            .is_some_and(|(time, _, _)| *time + MESSAGE_HISTORY_MINUTES <= minute)
+        {
+            recorder.messages.pop_front();
+        }
+    }
+
+    // ログインの失敗を理由（"password"や"pairing_code"）ごとに数える
+    pub fn record_login_failure(&self, reason: &'static str) {
+        let mut recorder = self.recorder.lock().unwrap();
+        *recorder.login_failures.entry(reason).or_default() += 1;
+    }
+
+    pub fn subscribe_sse(&self) -> SubscriberGuard {
+        self.sse_subscribers.fetch_add(1, Ordering::Relaxed);
+        SubscriberGuard(self.sse_subscribers.clone())
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let uptime = self.uptime();
+        let second = uptime.as_secs();
+        let minute = second / 60;
+        let recorder = self.recorder.lock().unwrap();
+
+        // 記録のない秒・分は0で埋める
+        let requests_per_second = (second.saturating_sub(REQUEST_HISTORY_SECONDS)..second)
+            .map(|time| {
+                recorder
+                    .requests
+                    .iter()
+                    .find(|(t, _)| *t == time)
+                    .map_or(0, |(_, count)| *count)
+            })
+            .collect();
+        let per_minute = |value: fn(&(u64, u64, u64)) -> u64| {
+            (minute.saturating_sub(MESSAGE_HISTORY_MINUTES - 1)..=minute)
+                .map(|time| {
+                    recorder
+                        .messages
+                        .iter()
+                        .find(|entry| entry.0 == time)
+                        .map_or(0, value)
+                })
+                .collect()
+        };
+
+        let mut endpoints: Vec<EndpointStats> = recorder
+            .endpoints
+            .iter()
+            .map(|(name, endpoint)| {
+                let mut latencies: Vec<f64> = endpoint.latencies_ms.iter().copied().collect();
+                latencies.sort_by(f64::total_cmp);
+                EndpointStats {
+                    endpoint: name.clone(),
+                    requests: endpoint.requests,
+                    p50_ms: percentile(&latencies, 0.50),
+                    p95_ms: percentile(&latencies, 0.95),
+                }
+            })
+            .collect();
+        endpoints.sort_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.endpoint.cmp(&b.endpoint))
+        });
+
+        MetricsSnapshot {
+            uptime,
+            requests_per_second,
+            endpoints,
+            sse_subscribers: self.sse_subscribers.load(Ordering::Relaxed),
+            messages_per_minute: per_minute(|entry| entry.1),
+            bytes_per_minute: per_minute(|entry| entry.2),
+        }
+    }
+
+    // OpenMetricsのテキスト形式で出力する（保存件数とDBサイズは呼び出し側でストアから取得する）
+    pub fn encode_openmetrics(&self, stored_messages: i64, database_bytes: u64) -> String {
+        let recorder = self.recorder.lock().unwrap();
+        let mut out = String::new();
+
+        write_family(
+            &mut out,
+            "uptime_seconds",
+            "gauge",
+            "Seconds since the server process started.",
+        );
+        let _ = writeln!(
+            out,
+            "{}_uptime_seconds {:.3}",
+            METRIC_PREFIX,
+            self.uptime().as_secs_f64()
+        );
+
+        // 出力が毎回同じ順になるよう、ルートの名前順に並べる
+        let mut endpoints: Vec<(&String, &Endpoint)> = recorder.endpoints.iter().collect();
+        endpoints.sort_by(|a, b| a.0.cmp(b.0));
+        let labels = |name: &str| {
+            let (method, route) = name.split_once(' ').unwrap_or(("", name));
+            format!(
+                "method=\"{}\",route=\"{}\"",
+                escape_label(method),
+                escape_label(route)
+            )
+        };
+
+        write_family(
+            &mut out,
+            "http_requests",
+            "counter",
+            "HTTP requests handled, by route and status code.",
+        );
+        for (name, endpoint) in &endpoints {
+            for (status, count) in &endpoint.statuses {
+                let _ = writeln!(
+                    out,
+                    "{}_http_requests_total{{{},status=\"{}\"}} {}",
+                    METRIC_PREFIX,
+                    labels(name),
+                    status,
+                    count
+                );
+            }
+        }
+
+        write_family(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency measured by the logger middleware.",
+        );
+        for (name, endpoint) in &endpoints {
+            let labels = labels(name);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(endpoint.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_http_request_duration_seconds_bucket{{{},le=\"{:?}\"}} {}",
+                    METRIC_PREFIX,
+                    labels,
+                    bound / 1000.0,
+                    cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                METRIC_PREFIX, labels, endpoint.requests
+            );
+            let _ = writeln!(
+                out,
+                "{}_http_request_duration_seconds_sum{{{}}} {}",
+                METRIC_PREFIX,
+                labels,
+                endpoint.latency_sum_ms / 1000.0
+            );
+            let _ = writeln!(
+                out,
+                "{}_http_request_duration_seconds_count{{{}}} {}",
+                METRIC_PREFIX, labels, endpoint.requests
+            );
+        }
+
+        write_family(
+            &mut out,
+            "sse_subscribers",
+            "gauge",
+            "Clients currently connected to the event stream.",
+        );
+        let _ = writeln!(
+            out,
+            "{}_sse_subscribers {}",
+            METRIC_PREFIX,
+            self.sse_subscribers.load(Ordering::Relaxed)
+        );
+
+        write_family(
+            &mut out,
+            "messages_received",
+            "counter",
+            "Messages received since the server process started.",
+        );
+        let _ = writeln!(
+            out,
+            "{}_messages_received_total {}",
+            METRIC_PREFIX, recorder.messages_total
+        );
+        write_family(
+            &mut out,
+            "message_bytes_received",
+            "counter",
+            "Bytes of message text and attachments received since the server process started.",
+        );
+        let _ = writeln!(
+            out,
+            "{}_message_bytes_received_total {}",
+            METRIC_PREFIX, recorder.message_bytes_total
+        );
+
+        write_family(
+            &mut out,
+            "messages_stored",
+            "gauge",
+            "Messages currently kept in the database.",
+        );
+        let _ = writeln!(out, "{}_messages_stored {}", METRIC_PREFIX, stored_messages);
+        write_family(
+            &mut out,
+            "database_size_bytes",
+            "gauge",
+            "Size of the message database file.",
+        );
+        let _ = writeln!(
+            out,
+            "{}_database_size_bytes {}",
+            METRIC_PREFIX, database_bytes
+        );
+
+        write_family(
+            &mut out,
+            "login_failures",
+            "counter",
+            "Rejected login attempts, by reason.",
+        );
+        for (reason, count) in &recorder.login_failures {
+            let _ = writeln!(
+                out,
+                "{}_login_failures_total{{reason=\"{}\"}} {}",
+                METRIC_PREFIX, reason, count
+            );
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {}_{} {}", METRIC_PREFIX, name, kind);
+    let _ = writeln!(out, "# HELP {}_{} {}", METRIC_PREFIX, name, help);
+}
+
+// ラベルの値に使えない文字をエスケープする
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// 昇順に並べた値から最近傍順位法でパーセンタイルを求める
+fn percentile(sorted: &[f64], ratio: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (ratio * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -0,0 +1,90 @@
+use crate::error::{ServerError, ServerResult};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// データディレクトリを指定する環境変数（--data-dirが優先）
+pub const DATA_DIR_ENV: &str = "SURE_SHOT_DATA_DIR";
+// 1にするとポータブルモードで起動する
+pub const PORTABLE_ENV: &str = "SURE_SHOT_PORTABLE";
+// 実行ファイルと同じ場所にこのファイルがあればポータブルモードで起動する
+pub const PORTABLE_MARKER: &str = "sure-shot.portable";
+// ポータブルモードで実行ファイルの隣に作るディレクトリ
+const PORTABLE_DIR: &str = "sure-shot-data";
+
+// データディレクトリをどこから決めたか
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataDirSource {
+    Argument,    // --data-dir
+    Environment, // SURE_SHOT_DATA_DIR
+    Portable,    // --portable / SURE_SHOT_PORTABLE / マーカーファイル
+    Default,     // OSのデータディレクトリ
+}
+
+impl std::fmt::Display for DataDirSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataDirSource::Argument => write!(f, "--data-dir"),
+            DataDirSource::Environment => write!(f, "{}", DATA_DIR_ENV),
+            DataDirSource::Portable => write!(f, "portable"),
+            DataDirSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+// コマンドライン引数での指定（起動時に一度だけ設定する）
+enum Override {
+    Path(PathBuf),
+    Portable,
+}
+
+static OVERRIDE: OnceLock<Override> = OnceLock::new();
+
+// --data-dirで指定されたディレクトリを使う
+pub fn set_data_dir(path: PathBuf) {
+    let _ = OVERRIDE.set(Override::Path(path));
+}
+
+// --portableが指定された
+pub fn set_portable() {
+    let _ = OVERRIDE.set(Override::Portable);
+}
+
+// 設定・メッセージ・ログを置くディレクトリを決める（作成はしない）
+pub fn resolve_data_dir() -> ServerResult<(PathBuf, DataDirSource)> {
+    match OVERRIDE.get() {
+        Some(Override::Path(path)) => return Ok((path.clone(), DataDirSource::Argument)),
+        Some(Override::Portable) => return Ok((portable_dir()?, DataDirSource::Portable)),
+        None => {}
+    }
+
+    if let Some(path) = std::env::var_os(DATA_DIR_ENV).filter(|path| !path.is_empty()) {
+        return Ok((PathBuf::from(path), DataDirSource::Environment));
+    }
+    if std::env::var(PORTABLE_ENV).is_ok_and(|value| value == "1" || value == "true")
+        || executable_dir().is_ok_and(|dir| dir.join(PORTABLE_MARKER).exists())
+    {
+        return Ok((portable_dir()?, DataDirSource::Portable));
+    }
+
+    let mut path = dirs::data_dir().ok_or(ServerError::DataDirNotFound)?;
+    path.push("sure-shot");
+    Ok((path, DataDirSource::Default))
+}
+
+// データディレクトリを返す（なければ作成する）
+pub fn data_dir() -> ServerResult<PathBuf> {
+    let (path, _) = resolve_data_dir()?;
+    std::fs::create_dir_all(&path).map_err(|e| ServerError::DataDir(path.clone(), e))?;
+    Ok(path)
+}
+
+fn portable_dir() -> ServerResult<PathBuf> {
+    Ok(executable_dir()?.join(PORTABLE_DIR))
+}
+
+fn executable_dir() -> ServerResult<PathBuf> {
+    let exe = std::env::current_exe()?;
+    exe.parent()
+        .map(|dir| dir.to_path_buf())
+        .ok_or(ServerError::DataDirNotFound)
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+use typeshare::typeshare;
+
+// ログイン時に申告されたデバイス
+#[derive(Debug, Clone)]
+pub struct DeviceIdentity {
+    pub device_id: String,
+    pub device_name: String,
+}
+
+// 接続中のクライアント（デバイス単位）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct ClientInfo {
+    pub device_id: String,
+    pub device_name: String,
+    pub ip: String,
+    pub transports: Vec<String>, // 接続ごとの種類（"sse" / "ws"）
+    pub connected_since: String,
+    pub last_activity: String,
+}
+
+// デバイスがオンライン/オフラインになったことの通知
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[typeshare]
+pub struct PresenceEvent {
+    pub client: ClientInfo,
+    pub online: bool,
+}
+
+// 認証済みデバイスの接続状況を管理する
+// 同じデバイスから複数接続している場合は、全て切断されるまでオンラインとして扱う
+#[derive(Debug, Default)]
+pub struct PresenceRegistry {
+    clients: Mutex<HashMap<String, ClientInfo>>,
+}
+
+impl PresenceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 接続を記録し、新たにオンラインになった場合はその情報を返す
+    pub async fn connect(
+        &self,
+        device: &DeviceIdentity,
+        ip: &str,
+        transport: &str,
+    ) -> Option<ClientInfo> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let mut clients = self.clients.lock().await;
+
+        if let Some(client) = clients.get_mut(&device.device_id) {
+            client.transports.push(transport.to_string());
+            client.device_name = device.device_name.clone();
+            client.ip = ip.to_string();
+            client.last_activity = now;
+            return None;
+        }
+
+        let client = ClientInfo {
+            device_id: device.device_id.clone(),
+            device_name: device.device_name.clone(),
+            ip: ip.to_string(),
+            transports: vec![transport.to_string()],
+            connected_since: now.clone(),
+            last_activity: now,
+        };
+        clients.insert(device.device_id.clone(), client.clone());
+        Some(client)
+    }
+
+    // 切断を記録し、オフラインになった場合はその情報を返す
+    pub async fn disconnect(&self, device_id: &str, transport: &str) -> Option<ClientInfo> {
+        let mut clients = self.clients.lock().await;
+        let client = clients.get_mut(device_id)?;
+
+        if let Some(index) = client.transports.iter().position(|t| t == transport) {
+            client.transports.remove(index);
+        }
+        if client.transports.is_empty() {
+            return clients.remove(device_id);
+        }
+        None
+    }
+
+    // 最終アクティビティを更新する（接続中のデバイスのみ）
+    pub async fn touch(&self, device_id: &str) -> bool {
+        let mut clients = self.clients.lock().await;
+        match clients.get_mut(device_id) {
+            Some(client) => {
+                client.last_activity = chrono::Utc::now().to_rfc3339();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 接続が古い順に返す
+    pub async fn list(&self) -> Vec<ClientInfo> {
+        let clients = self.clients.lock().await;
+        let mut list: Vec<ClientInfo> = clients.values().cloned().collect();
+        list.sort_by(|a, b| a.connected_since.cmp(&b.connected_since));
+        list
+    }
+}
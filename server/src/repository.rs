@@ -0,0 +1,395 @@
+use crate::ReceivedMessage;
+use crate::error::ServerResult;
+use crate::message_store::MessageStore;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// ハンドラーが使うメッセージの読み書き（保存先はMessageStoreのみ）
+#[async_trait]
+pub trait MessageRepository: Send + Sync + std::fmt::Debug {
+    async fn save(&self, message: &ReceivedMessage) -> ServerResult<()>;
+    async fn get(&self, id: &str) -> ServerResult<Option<ReceivedMessage>>;
+    // 最新のlimit件を時系列順（最新が最後）で返す
+    async fn recent(&self, limit: usize) -> ServerResult<Vec<ReceivedMessage>>;
+    async fn edit(&self, id: &str, new_text: &str) -> ServerResult<Option<ReceivedMessage>>;
+    async fn set_pinned(&self, id: &str, pinned: bool) -> ServerResult<Option<ReceivedMessage>>;
+    async fn delete(&self, id: &str) -> ServerResult<bool>;
+    // ストアが直接変更された場合（保持ポリシーの適用など）に呼ぶ
+    async fn invalidate(&self) {}
+}
+
+#[async_trait]
+impl<T: MessageRepository + ?Sized> MessageRepository for Arc<T> {
+    async fn save(&self, message: &ReceivedMessage) -> ServerResult<()> {
+        (**self).save(message).await
+    }
+
+    async fn get(&self, id: &str) -> ServerResult<Option<ReceivedMessage>> {
+        (**self).get(id).await
+    }
+
+    async fn recent(&self, limit: usize) -> ServerResult<Vec<ReceivedMessage>> {
+        (**self).recent(limit).await
+    }
+
+    async fn edit(&self, id: &str, new_text: &str) -> ServerResult<Option<ReceivedMessage>> {
+        (**self).edit(id, new_text).await
+    }
+
+    async fn set_pinned(&self, id: &str, pinned: bool) -> ServerResult<Option<ReceivedMessage>> {
+        (**self).set_pinned(id, pinned).await
+    }
+
+    async fn delete(&self, id: &str) -> ServerResult<bool> {
+        (**self).delete(id).await
+    }
+
+    async fn invalidate(&self) {
+        (**self).invalidate().await
+    }
+}
+
+#[async_trait]
+impl MessageRepository for MessageStore {
+    async fn save(&self, message: &ReceivedMessage) -> ServerResult<()> {
+        self.save_message(message).await.map(|_| ())
+    }
+
+    async fn get(&self, id: &str) -> ServerResult<Option<ReceivedMessage>> {
+        self.get_message(id).await
+    }
+
+    async fn recent(&self, limit: usize) -> ServerResult<Vec<ReceivedMessage>> {
+        self.get_recent_messages(limit).await
+    }
+
+    async fn edit(&self, id: &str, new_text: &str) -> ServerResult<Option<ReceivedMessage>> {
+        self.edit_message(id, new_text).await
+    }
+
+    async fn set_pinned(&self, id: &str, pinned: bool) -> ServerResult<Option<ReceivedMessage>> {
+        MessageStore::set_pinned(self, id, pinned).await
+    }
+
+    async fn delete(&self, id: &str) -> ServerResult<bool> {
+        self.delete_message(id).await
+    }
+}
+
+// 最新のメッセージを一定件数だけメモリに保持するキャッシュ
+// 書き込みは常に内側のリポジトリに先に反映し、成功した場合のみキャッシュを更新する
+#[derive(Debug)]
+pub struct CachedRepository<R> {
+    inner: R,
+    capacity: usize,                   // 0の場合はキャッシュしない
+    cache: Mutex<Option<RecentCache>>, // 初回の読み込みまではNone
+}
+
+// 常に「ストアの最新len件」と一致するように保つ
+#[derive(Debug)]
+struct RecentCache {
+    messages: VecDeque<ReceivedMessage>,
+    complete: bool, // ストアの全件を保持しているか
+}
+
+impl<R: MessageRepository> CachedRepository<R> {
+    pub fn new(inner: R, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            cache: Mutex::new(None),
+        }
+    }
+
+    // キャッシュが空ならストアから読み込む
+    async fn load(&self, cache: &mut Option<RecentCache>) -> ServerResult<()> {
+        if cache.is_none() {
+            let messages = self.inner.recent(self.capacity).await?;
+            *cache = Some(RecentCache {
+                complete: messages.len() < self.capacity,
+                messages: messages.into(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<R: MessageRepository> MessageRepository for CachedRepository<R> {
+    async fn save(&self, message: &ReceivedMessage) -> ServerResult<()> {
+        // ストアと同じ順でキャッシュに積むため、保存中はロックを保持する
+        let mut cache = self.cache.lock().await;
+        self.inner.save(message).await?;
+
+        if let Some(cache) = cache.as_mut() {
+            cache.messages.push_back(message.clone());
+            // 容量を超えたら最も古いものから捨てる
+            while cache.messages.len() > self.capacity {
+                cache.messages.pop_front();
+                cache.complete = false;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> ServerResult<Option<ReceivedMessage>> {
+        if let Some(cache) = self.cache.lock().await.as_ref()
+            && let Some(message) = cache.messages.iter().find(|m| m.id == id)
+        {
+            return Ok(Some(message.clone()));
+        }
+        self.inner.get(id).await
+    }
+
+    async fn recent(&self, limit: usize) -> ServerResult<Vec<ReceivedMessage>> {
+        if self.capacity == 0 {
+            return self.inner.recent(limit).await;
+        }
+
+        let mut cache = self.cache.lock().await;
+        self.load(&mut cache).await?;
+
+        match cache.as_ref() {
+            Some(cache) if limit <= cache.messages.len() || cache.complete => {
+                let skip = cache.messages.len().saturating_sub(limit);
+                Ok(cache.messages.iter().skip(skip).cloned().collect())
+            }
+            // キャッシュより多く求められた場合はストアから読む
+            _ => self.inner.recent(limit).await,
+        }
+    }
+
+    async fn edit(&self, id: &str, new_text: &str) -> ServerResult<Option<ReceivedMessage>> {
+        let edited = self.inner.edit(id, new_text).await?;
+        if let Some(ref message) = edited {
+            self.replace(message).await;
+        }
+        Ok(edited)
+    }
+
+    async fn set_pinned(&self, id: &str, pinned: bool) -> ServerResult<Option<ReceivedMessage>> {
+        let updated = self.inner.set_pinned(id, pinned).await?;
+        if let Some(ref message) = updated {
+            self.replace(message).await;
+        }
+        Ok(updated)
+    }
+
+    async fn delete(&self, id: &str) -> ServerResult<bool> {
+        let mut cache = self.cache.lock().await;
+        let deleted = self.inner.delete(id).await?;
+        if deleted && let Some(cache) = cache.as_mut() {
+            cache.messages.retain(|m| m.id != id);
+        }
+        Ok(deleted)
+    }
+
+    async fn invalidate(&self) {
+        *self.cache.lock().await = None;
+        self.inner.invalidate().await;
+    }
+}
+
+impl<R> CachedRepository<R> {
+    async fn replace(&self, message: &ReceivedMessage) {
+        if let Some(cache) = self.cache.lock().await.as_mut()
+            && let Some(cached) = cache.messages.iter_mut().find(|m| m.id == message.id)
+        {
+            *cached = message.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 呼び出し回数を数えるだけのメモリ上のストア
+    #[derive(Debug, Default)]
+    struct FakeStore {
+        messages: std::sync::Mutex<Vec<ReceivedMessage>>,
+        recent_calls: AtomicUsize,
+    }
+
+    impl FakeStore {
+        fn with_messages(count: usize) -> Arc<Self> {
+            let store = Self::default();
+            *store.messages.lock().unwrap() = (0..count).map(message).collect();
+            Arc::new(store)
+        }
+
+        fn recent_calls(&self) -> usize {
+            self.recent_calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl MessageRepository for FakeStore {
+        async fn save(&self, message: &ReceivedMessage) -> ServerResult<()> {
+            self.messages.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+
+        async fn get(&self, id: &str) -> ServerResult<Option<ReceivedMessage>> {
+            Ok(self
+                .messages
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|m| m.id == id)
+                .cloned())
+        }
+
+        async fn recent(&self, limit: usize) -> ServerResult<Vec<ReceivedMessage>> {
+            self.recent_calls.fetch_add(1, Ordering::SeqCst);
+            let messages = self.messages.lock().unwrap();
+            let skip = messages.len().saturating_sub(limit);
+            Ok(messages.iter().skip(skip).cloned().collect())
+        }
+
+        async fn edit(&self, id: &str, new_text: &str) -> ServerResult<Option<ReceivedMessage>> {
+            let mut messages = self.messages.lock().unwrap();
+            Ok(messages.iter_mut().find(|m| m.id == id).map(|m| {
+                m.message = new_text.to_string();
+                m.clone()
+            }))
+        }
+
+        async fn set_pinned(
+            &self,
+            id: &str,
+            pinned: bool,
+        ) -> ServerResult<Option<ReceivedMessage>> {
+            let mut messages = self.messages.lock().unwrap();
+            Ok(messages.iter_mut().find(|m| m.id == id).map(|m| {
+                m.pinned = pinned;
+                m.clone()
+            }))
+        }
+
+        async fn delete(&self, id: &str) -> ServerResult<bool> {
+            let mut messages = self.messages.lock().unwrap();
+            let before = messages.len();
+            messages.retain(|m| m.id != id);
+            Ok(messages.len() < before)
+        }
+    }
+
+    fn message(n: usize) -> ReceivedMessage {
+        ReceivedMessage {
+            id: format!("m{}", n),
+            from: "127.0.0.1".to_string(),
+            from_name: "tester".to_string(),
+            message: format!("message {}", n),
+            message_type: "text".to_string(),
+            timestamp: format!("2026-01-01T00:00:{:02}Z", n),
+            is_self: false,
+            attachments: Vec::new(),
+            pinned: false,
+            edited_at: None,
+        }
+    }
+
+    fn ids(messages: &[ReceivedMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn recent_is_served_from_cache_after_first_load() {
+        let store = FakeStore::with_messages(5);
+        let repo = CachedRepository::new(store.clone(), 3);
+
+        assert_eq!(ids(&repo.recent(3).await.unwrap()), ["m2", "m3", "m4"]);
+        assert_eq!(ids(&repo.recent(2).await.unwrap()), ["m3", "m4"]);
+        assert_eq!(store.recent_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn save_appends_to_cache_and_drops_oldest() {
+        let store = FakeStore::with_messages(3);
+        let repo = CachedRepository::new(store.clone(), 3);
+        repo.recent(3).await.unwrap();
+
+        repo.save(&message(3)).await.unwrap();
+
+        assert_eq!(ids(&repo.recent(3).await.unwrap()), ["m1", "m2", "m3"]);
+        assert_eq!(store.recent_calls(), 1);
+        // 捨てた分はストアから読み直す
+        assert_eq!(repo.recent(4).await.unwrap().len(), 4);
+        assert_eq!(store.recent_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn edit_and_pin_update_cached_message() {
+        let store = FakeStore::with_messages(2);
+        let repo = CachedRepository::new(store.clone(), 5);
+        repo.recent(5).await.unwrap();
+
+        repo.edit("m1", "edited").await.unwrap();
+        repo.set_pinned("m0", true).await.unwrap();
+
+        let recent = repo.recent(5).await.unwrap();
+        assert_eq!(recent[1].message, "edited");
+        assert!(recent[0].pinned);
+        assert_eq!(store.recent_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_message_from_cache() {
+        let store = FakeStore::with_messages(3);
+        let repo = CachedRepository::new(store.clone(), 5);
+        repo.recent(5).await.unwrap();
+
+        assert!(repo.delete("m1").await.unwrap());
+        assert!(!repo.delete("m1").await.unwrap());
+
+        assert_eq!(ids(&repo.recent(5).await.unwrap()), ["m0", "m2"]);
+        assert!(repo.get("m1").await.unwrap().is_none());
+        assert_eq!(store.recent_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalidate_reloads_from_store() {
+        let store = FakeStore::with_messages(2);
+        let repo = CachedRepository::new(store.clone(), 5);
+        repo.recent(5).await.unwrap();
+
+        // キャッシュを通さずにストアを変更する（保持ポリシーの適用など）
+        store.delete("m0").await.unwrap();
+        assert_eq!(ids(&repo.recent(5).await.unwrap()), ["m0", "m1"]);
+
+        repo.invalidate().await;
+        assert_eq!(ids(&repo.recent(5).await.unwrap()), ["m1"]);
+        assert_eq!(store.recent_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn limit_beyond_cache_reads_store_unless_complete() {
+        // ストアの全件を保持していればキャッシュだけで足りる
+        let store = FakeStore::with_messages(2);
+        let repo = CachedRepository::new(store.clone(), 5);
+        assert_eq!(repo.recent(10).await.unwrap().len(), 2);
+        assert_eq!(repo.recent(10).await.unwrap().len(), 2);
+        assert_eq!(store.recent_calls(), 1);
+
+        // 一部しか保持していなければストアから読む
+        let store = FakeStore::with_messages(8);
+        let repo = CachedRepository::new(store.clone(), 5);
+        assert_eq!(repo.recent(10).await.unwrap().len(), 8);
+        assert_eq!(repo.recent(10).await.unwrap().len(), 8);
+        assert_eq!(store.recent_calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_always_reads_store() {
+        let store = FakeStore::with_messages(3);
+        let repo = CachedRepository::new(store.clone(), 0);
+
+        assert_eq!(repo.recent(2).await.unwrap().len(), 2);
+        assert_eq!(repo.recent(2).await.unwrap().len(), 2);
+        assert_eq!(store.recent_calls(), 2);
+    }
+}
@@ -1,12 +1,27 @@
 use color_eyre::eyre::Result;
-use std::net::SocketAddr;
+use local_ip_address::list_afinet_netifas;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::{Mutex, broadcast, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, broadcast, mpsc, oneshot};
 use tokio::task::JoinHandle;
+use tokio::time::{Interval, MissedTickBehavior};
 
 use server::{
-    AppState, ReceivedMessage, ServerConfig, ServerMessage, ServerState, ServerStatus,
-    external::create_external_router, find_local_ip, message_store::MessageStore,
+    AppState, Attachment, LaggedEvent, MessageDeleted, RestartRecord, ServerConfig, ServerEvent,
+    ServerMessage, ServerShuttingDownEvent, ServerState, ServerStatus, SettingsUpdate, StreamEvent,
+    connect::ConnectUri,
+    detect_local_ip,
+    error::{ServerError, ServerResult},
+    external::{auth::issue_pairing_code, create_external_router, send::deliver_local_message},
+    find_local_ip,
+    message_store::MessageStore,
+    metrics::Metrics,
+    presence::PresenceRegistry,
+    repository::{CachedRepository, MessageRepository},
 };
 
 #[derive(Debug)]
@@ -15,8 +30,17 @@ pub struct ServerManager {
     server_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
     shutdown_sender: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
     app_state: Arc<Mutex<Option<AppState>>>, // AppStateを保持
+    retention_handle: Arc<Mutex<Option<JoinHandle<()>>>>, // 保持ポリシーの定期実行タスク
+    config_watch_handle: Arc<Mutex<Option<JoinHandle<()>>>>, // 設定ファイルの監視タスク
+    event_forward_handle: Arc<Mutex<Option<JoinHandle<()>>>>, // 配信イベントをTUIに転送するタスク
+    metrics: Arc<Metrics>,                   // 再起動しても引き継ぐ稼働状況の集計
 }
 
+// 設定ファイルの変更を確認する間隔
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+// 停止時に接続中のクライアントや書き込み中のメッセージを待つ上限（超えた場合は打ち切る）
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl ServerManager {
     pub fn new(message_sender: mpsc::UnboundedSender<ServerMessage>) -> Self {
         Self {
@@ -24,17 +48,27 @@ impl ServerManager {
             server_handle: Arc::new(Mutex::new(None)),
             shutdown_sender: Arc::new(Mutex::new(None)),
             app_state: Arc::new(Mutex::new(None)),
+            retention_handle: Arc::new(Mutex::new(None)),
+            config_watch_handle: Arc::new(Mutex::new(None)),
+            event_forward_handle: Arc::new(Mutex::new(None)),
+            metrics: Arc::new(Metrics::new()),
         }
     }
 
     pub async fn start_server(&self) -> Result<()> {
         // 既にサーバーが起動している場合は何もしない
         let mut handle_guard = self.server_handle.lock().await;
-        if handle_guard.is_some() {
-            let _ = self
-                .message_sender
-                .send(ServerMessage::Log("Server is already running".to_string()));
-            return Ok(());
+        match handle_guard.as_ref() {
+            Some(handle) if !handle.is_finished() => {
+                tracing::warn!("Server is already running");
+                return Ok(());
+            }
+            Some(_) => {
+                // 再起動を諦めて止まったサーバーの後片付け
+                handle_guard.take();
+                self.stop_background_tasks().await;
+            }
+            None => {}
         }
         // 初期状態を送信
         let _ = self
@@ -45,59 +79,56 @@ impl ServerManager {
                 ip: None,
                 port: None,
             }));
-        let _ = self
-            .message_sender
-            .send(ServerMessage::Log("Starting server...".to_string()));
-
-        let Some(ip) = find_local_ip() else {
-            let _ = self
-                .message_sender
-                .send(ServerMessage::Log("No local IP found".to_string()));
-            let _ = self
-                .message_sender
-                .send(ServerMessage::StatusUpdate(ServerStatus {
-                    state: ServerState::Error("No local IP found".to_string()),
-                    nickname: None,
-                    ip: None,
-                    port: None,
-                }));
-            return Ok(());
-        };
-        let _ = self
-            .message_sender
-            .send(ServerMessage::Log(format!("Found local IP: {}", ip)));
+        tracing::info!("Starting server...");
 
         // 設定をロード（事前にmain()でセットアップ済み）
         let config = match ServerConfig::load_or_create() {
             Ok(config) => {
-                let _ = self
-                    .message_sender
-                    .send(ServerMessage::Log("Config loaded successfully".to_string()));
+                tracing::info!("Config loaded successfully");
                 config
             }
             Err(e) => {
-                let _ = self
-                    .message_sender
-                    .send(ServerMessage::Log(format!("Failed to load config: {}", e)));
+                tracing::error!("Failed to load config: {}", e);
                 let _ = self
                     .message_sender
                     .send(ServerMessage::StatusUpdate(ServerStatus {
                         state: ServerState::Error(format!("Config load failed: {}", e)),
                         nickname: None,
-                        ip: Some(ip.to_string()),
+                        ip: None,
                         port: None,
                     }));
                 return Ok(());
             }
         };
 
-        let _ = self.message_sender.send(ServerMessage::Log(format!(
-            "Server nickname: {}",
-            config.nickname
-        )));
+        // 待ち受けアドレスが設定されていない場合はローカルIPを検出する
+        let detect_ip = !matches!(
+            config.bind.address.as_deref().map(str::parse::<IpAddr>),
+            Some(Ok(_))
+        );
+        let ip = match config.bind.address.as_deref().map(str::parse::<IpAddr>) {
+            Some(Ok(ip)) => ip,
+            Some(Err(_)) | None => {
+                let Some(ip) = detect_local_ip().await else {
+                    tracing::error!("No local IP found");
+                    let _ = self
+                        .message_sender
+                        .send(ServerMessage::StatusUpdate(ServerStatus {
+                            state: ServerState::Error("No local IP found".to_string()),
+                            nickname: None,
+                            ip: None,
+                            port: None,
+                        }));
+                    return Ok(());
+                };
+                tracing::info!("Found local IP: {}", ip);
+                ip
+            }
+        };
+
+        tracing::info!("Server nickname: {}", config.nickname);
 
         // アプリケーション状態を初期化
-        let messages = Arc::new(Mutex::new(Vec::<ReceivedMessage>::new()));
         let (message_broadcaster, dummy_receiver) = broadcast::channel(100);
         let config_arc = Arc::new(Mutex::new(config.clone()));
 
@@ -105,10 +136,7 @@ impl ServerManager {
         let message_store = match MessageStore::new(None) {
             Ok(store) => Arc::new(store),
             Err(e) => {
-                let _ = self.message_sender.send(ServerMessage::Log(format!(
-                    "Failed to initialize message store: {}",
-                    e
-                )));
+                tracing::error!("Failed to initialize message store: {}", e);
                 let _ = self
                     .message_sender
                     .send(ServerMessage::StatusUpdate(ServerStatus {
@@ -121,21 +149,17 @@ impl ServerManager {
             }
         };
 
-        // 既存のメッセージをロード
-        match message_store.get_recent_messages(100).await {
+        // 最新のメッセージをキャッシュに読み込んでおく
+        let messages: Arc<dyn MessageRepository> = Arc::new(CachedRepository::new(
+            message_store.clone(),
+            config.message_cache_size,
+        ));
+        match messages.recent(config.message_cache_size).await {
             Ok(stored_messages) => {
-                let mut messages_guard = messages.lock().await;
-                *messages_guard = stored_messages;
-                let _ = self.message_sender.send(ServerMessage::Log(format!(
-                    "Loaded {} stored messages",
-                    messages_guard.len()
-                )));
+                tracing::info!("Loaded {} stored messages", stored_messages.len());
             }
             Err(e) => {
-                let _ = self.message_sender.send(ServerMessage::Log(format!(
-                    "Failed to load stored messages: {}",
-                    e
-                )));
+                tracing::error!("Failed to load stored messages: {}", e);
             }
         }
 
@@ -148,32 +172,24 @@ impl ServerManager {
             config: config_arc.clone(),
             log_sender: Some(self.message_sender.clone()),
             message_store: message_store.clone(),
+            publish_lock: Arc::new(Mutex::new(())),
+            presence: Arc::new(PresenceRegistry::new()),
+            metrics: self.metrics.clone(),
+            listening: Arc::new(AtomicBool::new(false)),
         };
 
-        // AppStateを保存
-        {
-            let mut app_state_guard = self.app_state.lock().await;
-            *app_state_guard = Some(app_state.clone());
-        }
-
-        let port = 8000;
+        let port = config.bind.port;
         let external_addr = SocketAddr::new(ip, port);
 
         // ルーターを作成
         let external_app = create_external_router(app_state.clone());
 
         // サーバーを起動
-        let _ = self.message_sender.send(ServerMessage::Log(format!(
-            "Binding to address: {}",
-            external_addr
-        )));
+        tracing::info!("Binding to address: {}", external_addr);
         let external_listener = match tokio::net::TcpListener::bind(external_addr).await {
             Ok(listener) => listener,
             Err(e) => {
-                let _ = self.message_sender.send(ServerMessage::Log(format!(
-                    "Failed to bind to {}: {}",
-                    external_addr, e
-                )));
+                tracing::error!("Failed to bind to {}: {}", external_addr, e);
                 let _ = self
                     .message_sender
                     .send(ServerMessage::StatusUpdate(ServerStatus {
@@ -186,10 +202,37 @@ impl ServerManager {
             }
         };
 
-        let _ = self.message_sender.send(ServerMessage::Log(format!(
+        tracing::info!(
             "External API listening on http://{} (accessible from network)",
             external_addr
-        )));
+        );
+
+        // 待ち受けを開始できてからAppStateを保存し、一緒に動かすタスクを起動する
+        {
+            let mut app_state_guard = self.app_state.lock().await;
+            *app_state_guard = Some(app_state.clone());
+        }
+
+        // 保持ポリシーの定期実行を開始
+        Self::replace_task(
+            &self.retention_handle,
+            self.spawn_retention_task(app_state.clone()),
+        )
+        .await;
+
+        // 設定ファイルの監視を開始
+        Self::replace_task(
+            &self.config_watch_handle,
+            self.spawn_config_watch_task(app_state.clone()),
+        )
+        .await;
+
+        // 配信されたイベントをTUIに転送
+        Self::replace_task(
+            &self.event_forward_handle,
+            self.spawn_event_forward_task(&app_state),
+        )
+        .await;
 
         // シャットダウン用のチャンネルを作成
         let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
@@ -197,49 +240,17 @@ impl ServerManager {
         *shutdown_guard = Some(shutdown_tx);
         drop(shutdown_guard);
 
-        // サーバーが正常に起動したことを通知
-        let _ = self
-            .message_sender
-            .send(ServerMessage::StatusUpdate(ServerStatus {
-                state: ServerState::Running,
-                nickname: Some(config.nickname.clone()),
-                ip: Some(ip.to_string()),
-                port: Some(port),
-            }));
-
-        // サーバータスクを起動
-        let message_sender = self.message_sender.clone();
-        let config_clone = config.clone();
-        let ip_clone = ip;
-        let handle = tokio::spawn(async move {
-            let external_serve = axum::serve(external_listener, external_app);
-
-            // graceful shutdownを実装
-            tokio::select! {
-                result = external_serve => {
-                    if let Err(e) = result {
-                        let _ = message_sender.send(ServerMessage::Log(format!("External server error: {}", e)));
-                        let _ = message_sender.send(ServerMessage::StatusUpdate(ServerStatus {
-                            state: ServerState::Error(format!("Server error: {}", e)),
-                            nickname: Some(config_clone.nickname.clone()),
-                            ip: Some(ip_clone.to_string()),
-                            port: Some(port),
-                        }));
-                    }
-                }
-                _ = shutdown_rx => {
-                    let _ = message_sender.send(ServerMessage::Log("Server shutdown requested".to_string()));
-                }
-            }
-
-            let _ = message_sender.send(ServerMessage::StatusUpdate(ServerStatus {
-                state: ServerState::Stopped,
-                nickname: Some(config_clone.nickname.clone()),
-                ip: Some(ip_clone.to_string()),
-                port: Some(port),
-            }));
-            let _ = message_sender.send(ServerMessage::Log("Server ended".to_string()));
-        });
+        // サーバーを動かし、落ちた場合は設定に従って再起動する
+        let supervisor = Supervisor {
+            message_sender: self.message_sender.clone(),
+            app_state: app_state.clone(),
+            router: external_app,
+            detect_ip,
+            port,
+            watch_interval: (detect_ip && config.bind.watch_interval_secs > 0)
+                .then(|| Duration::from_secs(config.bind.watch_interval_secs)),
+        };
+        let handle = tokio::spawn(supervisor.run(external_listener, ip, shutdown_rx));
 
         *handle_guard = Some(handle);
         Ok(())
@@ -250,9 +261,7 @@ impl ServerManager {
         let mut shutdown_guard = self.shutdown_sender.lock().await;
 
         if let Some(shutdown_tx) = shutdown_guard.take() {
-            let _ = self
-                .message_sender
-                .send(ServerMessage::Log("Stopping server...".to_string()));
+            tracing::info!("Stopping server...");
             let _ = shutdown_tx.send(());
         }
 
@@ -260,15 +269,541 @@ impl ServerManager {
             let _ = handle.await;
         }
 
-        let _ = self
-            .message_sender
-            .send(ServerMessage::Log("Server stopped".to_string()));
+        // 残っている書き込みを待ってからデータベースを閉じられる状態にする
+        if let Some(app_state) = self.get_app_state().await {
+            match tokio::time::timeout(SHUTDOWN_TIMEOUT, app_state.message_store.flush()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!("Failed to flush message store: {}", e),
+                Err(_) => tracing::warn!("Timed out flushing message store"),
+            }
+        }
+        self.stop_background_tasks().await;
+
+        tracing::info!("Server stopped");
+        Ok(())
+    }
+
+    // サーバーと一緒に起動したタスクを止める
+    async fn stop_background_tasks(&self) {
+        if let Some(handle) = self.retention_handle.lock().await.take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.config_watch_handle.lock().await.take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.event_forward_handle.lock().await.take() {
+            handle.abort();
+        }
+        *self.app_state.lock().await = None;
+    }
+
+    // 新しいタスクを登録する（前のタスクが残っていれば止める）
+    async fn replace_task(slot: &Mutex<Option<JoinHandle<()>>>, handle: JoinHandle<()>) {
+        if let Some(old) = slot.lock().await.replace(handle) {
+            old.abort();
+        }
+    }
+
+    // 保持ポリシーを定期的に適用するタスクを起動
+    fn spawn_retention_task(&self, app_state: AppState) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                // 設定は毎回読み直す（実行中の変更を反映するため）
+                let policy = app_state.config.lock().await.retention.clone();
+
+                match app_state.message_store.apply_retention(&policy).await {
+                    Ok(report) => {
+                        if report.removed_messages() > 0 || report.attachments_stripped > 0 {
+                            // ストアを直接変更したためキャッシュを読み直させる
+                            app_state.messages.invalidate().await;
+
+                            // 手動で削除した場合と同じく、接続中のクライアントに削除を通知する
+                            for id in report.deleted_ids.iter().cloned() {
+                                app_state
+                                    .publish(ServerEvent::Delete(MessageDeleted { id }))
+                                    .await;
+                            }
+                            tracing::info!(
+                                "Retention: removed {} messages (age {}, count {}, size {}), stripped {} attachments, db {} KB -> {} KB",
+                                report.removed_messages(),
+                                report.expired,
+                                report.over_count,
+                                report.over_size,
+                                report.attachments_stripped,
+                                report.bytes_before / 1024,
+                                report.bytes_after / 1024
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("Retention failed: {}", e);
+                    }
+                }
+
+                let interval = policy.check_interval_minutes.max(1);
+                tokio::time::sleep(Duration::from_secs(interval * 60)).await;
+            }
+        })
+    }
+
+    // 設定ファイルの変更を監視して実行中のサーバーに反映するタスクを起動
+    // エディタによっては書き込み途中の内容が見えるため、読み込めない場合は前の設定のまま次の確認を待つ
+    fn spawn_config_watch_task(&self, app_state: AppState) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let path = match ServerConfig::get_config_path() {
+                Ok(path) => path,
+                Err(e) => {
+                    tracing::error!("Config watch disabled: {}", e);
+                    return;
+                }
+            };
+            let mut last_content = std::fs::read_to_string(&path).ok();
+
+            loop {
+                tokio::time::sleep(CONFIG_WATCH_INTERVAL).await;
+
+                let content = match std::fs::read_to_string(&path) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        if last_content.take().is_some() {
+                            tracing::warn!("Failed to read config file: {}", e);
+                        }
+                        continue;
+                    }
+                };
+                if last_content.as_deref() == Some(content.as_str()) {
+                    continue;
+                }
+                last_content = Some(content.clone());
+
+                match ServerConfig::parse(&content) {
+                    Ok(reloaded) => {
+                        if let Err(e) = server::reload_config(
+                            &app_state.config,
+                            reloaded,
+                            app_state.log_sender.as_ref(),
+                        )
+                        .await
+                        {
+                            tracing::warn!("Failed to apply config: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Ignoring invalid config file, keeping current settings: {}",
+                            e
+                        );
+                    }
+                }
+            }
+        })
+    }
+
+    // メッセージの配信を購読してServerMessage::Eventとして送るタスクを起動
+    // 取りこぼした場合はLaggedを送り、受け取った側でストアから読み直させる
+    fn spawn_event_forward_task(&self, app_state: &AppState) -> JoinHandle<()> {
+        let mut receiver = app_state.message_broadcaster.subscribe();
+        let message_sender = self.message_sender.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(stream_event) => stream_event.event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        ServerEvent::Lagged(LaggedEvent {
+                            missed,
+                            resync: true,
+                        })
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if message_sender.send(ServerMessage::Event(event)).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    // 設定を変更して保存する（停止中は設定ファイルのみ更新する）
+    pub async fn update_settings(&self, update: SettingsUpdate) -> ServerResult<()> {
+        match self.get_app_state().await {
+            Some(app_state) => {
+                app_state.update_settings(&update).await?;
+            }
+            None => {
+                let config = Mutex::new(ServerConfig::load_or_create()?);
+                server::update_settings(&config, &update, Some(&self.message_sender)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // サーバー自身のメッセージとして送信する（起動中のみ）
+    pub async fn send_message(
+        &self,
+        message: String,
+        attachments: Vec<PathBuf>,
+    ) -> ServerResult<()> {
+        let Some(app_state) = self.get_app_state().await else {
+            return Err(ServerError::BadRequest("Server is not running".to_string()));
+        };
+
+        let mut loaded = Vec::with_capacity(attachments.len());
+        for path in &attachments {
+            loaded.push(Attachment::from_path(path).await?);
+        }
+        let sent = deliver_local_message(&app_state, message, loaded).await?;
+        tracing::info!(
+            "Sent message {} with {} attachments",
+            sent.id,
+            sent.attachments.len()
+        );
         Ok(())
     }
 
+    // 接続用QRコードに載せるURIを作る（起動中のみ）
+    // pairing_codeがtrueなら一度だけ使えるコードを発行して含め、その有効期限も返す
+    pub async fn connect_uri(
+        &self,
+        ip: String,
+        port: u16,
+        pairing_code: bool,
+    ) -> ServerResult<(ConnectUri, Option<Instant>)> {
+        let Some(app_state) = self.get_app_state().await else {
+            return Err(ServerError::BadRequest("Server is not running".to_string()));
+        };
+        let server_id = app_state.config.lock().await.server_id.clone();
+
+        let pairing = match pairing_code {
+            true => Some(issue_pairing_code().await),
+            false => None,
+        };
+        let expires_at = pairing.as_ref().map(|pairing| pairing.expires_at);
+        let uri = ConnectUri {
+            ip,
+            port,
+            server_id,
+            fingerprint: None, // 現在はHTTPのみで待ち受けている
+            pairing_code: pairing.map(|pairing| pairing.code),
+        };
+        Ok((uri, expires_at))
+    }
+
+    // TUIのStatsタブで表示する集計
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
     // AppStateを取得するヘルパーメソッド
     async fn get_app_state(&self) -> Option<AppState> {
         let app_state_guard = self.app_state.lock().await;
         app_state_guard.clone()
     }
 }
+
+// HTTPサーバーを動かし、落ちた場合は設定に従って再起動するタスク
+struct Supervisor {
+    message_sender: mpsc::UnboundedSender<ServerMessage>,
+    app_state: AppState,
+    router: axum::Router,
+    detect_ip: bool, // 待ち受けアドレスが設定されていない（再起動のたびにローカルIPを検出し直す）
+    port: u16,
+    watch_interval: Option<Duration>, // 検出したIPが無くなっていないか確認する間隔
+}
+
+// サーバーを動かすのをやめた理由
+enum Exit {
+    Shutdown,                   // 停止を要求された
+    Moved(TcpListener, IpAddr), // ローカルIPが変わったため、新しいアドレスで待ち受け直す
+    Failed(Failure),
+}
+
+// サーバーが止まった理由
+enum Failure {
+    Error(String), // axum::serveがエラーを返した、または再起動時に待ち受けられなかった
+    Panic(String), // サーバーのタスクがpanicした
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Error(reason) => write!(f, "{}", reason),
+            Failure::Panic(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+impl Supervisor {
+    async fn run(
+        self,
+        listener: TcpListener,
+        mut ip: IpAddr,
+        mut shutdown_rx: oneshot::Receiver<()>,
+    ) {
+        let mut listener = Some(listener);
+        let mut attempt = 0;
+        loop {
+            let started_at = Instant::now();
+            let bound = match listener.take() {
+                Some(listener) => Ok(listener),
+                None => self.rebind(&mut ip).await,
+            };
+            let failure = match bound {
+                Ok(bound) => match self.serve(bound, ip, &mut shutdown_rx).await {
+                    Exit::Shutdown => break,
+                    Exit::Moved(moved, moved_ip) => {
+                        // 落ちたわけではないため、待たずに続けて動かす
+                        listener = Some(moved);
+                        ip = moved_ip;
+                        continue;
+                    }
+                    Exit::Failed(failure) => failure,
+                },
+                Err(failure) => failure,
+            };
+
+            // しばらく動いていた場合は、続けて落ちたとは数えない
+            let policy = self.app_state.config.lock().await.restart.clone();
+            if started_at.elapsed() >= Duration::from_secs(policy.reset_after_secs) {
+                attempt = 0;
+            }
+            attempt += 1;
+            let retry_in = (policy.on_failure
+                && (policy.max_attempts == 0 || attempt <= policy.max_attempts))
+                .then(|| policy.backoff(attempt));
+
+            match retry_in {
+                Some(delay) => tracing::error!(
+                    "Server went down ({}), restarting in {}s (attempt {})",
+                    failure,
+                    delay.as_secs(),
+                    attempt
+                ),
+                None => tracing::error!("Server went down ({}), not restarting", failure),
+            }
+            let _ = self
+                .message_sender
+                .send(ServerMessage::Restart(RestartRecord {
+                    at: chrono::Local::now(),
+                    reason: failure.to_string(),
+                    attempt,
+                    retry_in,
+                }));
+
+            let Some(delay) = retry_in else {
+                let state = match failure {
+                    Failure::Error(reason) => ServerState::Error(reason),
+                    Failure::Panic(_) => ServerState::Aborted,
+                };
+                self.send_status(state, ip).await;
+                return;
+            };
+            self.send_status(
+                ServerState::Restarting {
+                    attempt,
+                    retry_at: Instant::now() + delay,
+                },
+                ip,
+            )
+            .await;
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut shutdown_rx => break,
+            }
+        }
+
+        self.send_status(ServerState::Stopped, ip).await;
+        tracing::info!("Server ended");
+    }
+
+    // 止まるまでサーバーを動かし、やめた理由を返す
+    async fn serve(
+        &self,
+        listener: TcpListener,
+        ip: IpAddr,
+        shutdown_rx: &mut oneshot::Receiver<()>,
+    ) -> Exit {
+        // panicを検出できるよう別のタスクで動かす
+        let router = self.router.clone();
+        let (drain_tx, drain_rx) = oneshot::channel::<()>();
+        let mut task = tokio::spawn(async move {
+            // 接続元のIPをハンドラーで取得できるようにする
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async {
+                let _ = drain_rx.await;
+            })
+            .await
+        });
+        self.app_state.listening.store(true, Ordering::Relaxed);
+        self.send_status(ServerState::Running, ip).await;
+
+        let mut watch = self.watch_interval.map(|period| {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            interval
+        });
+        let mut warned = false;
+        let result = loop {
+            tokio::select! {
+                result = &mut task => break result,
+                _ = &mut *shutdown_rx => {
+                    tracing::info!("Server shutdown requested");
+                    self.drain(task, drain_tx, ip).await;
+                    return Exit::Shutdown;
+                }
+                _ = tick(&mut watch) => {
+                    if let Some((moved, moved_ip)) = self.follow_network(ip, &mut warned).await {
+                        // 停止する場合と同じく、接続中のクライアントを切断してから移る
+                        self.drain(task, drain_tx, ip).await;
+                        return Exit::Moved(moved, moved_ip);
+                    }
+                }
+            }
+        };
+        self.app_state.listening.store(false, Ordering::Relaxed);
+
+        Exit::Failed(match result {
+            Ok(Ok(())) => Failure::Error("Server stopped unexpectedly".to_string()),
+            Ok(Err(e)) => Failure::Error(format!("Server error: {}", e)),
+            Err(e) if e.is_panic() => Failure::Panic(panic_message(e.into_panic())),
+            Err(e) => Failure::Error(format!("Server task failed: {}", e)),
+        })
+    }
+
+    // 新しい接続の受け付けをやめ、接続中のクライアントに停止を知らせて切断されるのを待つ
+    // 処理中のリクエスト（/sendの保存など）は最後まで実行される
+    async fn drain(
+        &self,
+        mut task: JoinHandle<std::io::Result<()>>,
+        drain_tx: oneshot::Sender<()>,
+        ip: IpAddr,
+    ) {
+        self.app_state.listening.store(false, Ordering::Relaxed);
+        self.send_status(ServerState::Stopping, ip).await;
+        let _ = drain_tx.send(());
+
+        // SSEとWebSocketのフィードは通知を送ると閉じる（記録も再送もしない）
+        let name = self.app_state.config.lock().await.nickname.clone();
+        let _ = self.app_state.message_broadcaster.send(StreamEvent {
+            id: None,
+            event: ServerEvent::ServerShuttingDown(ServerShuttingDownEvent { name }),
+        });
+        tracing::info!("Waiting for connections to close");
+
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut task).await {
+            Ok(Ok(Ok(()))) => tracing::info!("All connections closed"),
+            Ok(Ok(Err(e))) => tracing::warn!("Server error during shutdown: {}", e),
+            Ok(Err(e)) => tracing::warn!("Server task failed during shutdown: {}", e),
+            Err(_) => {
+                tracing::warn!(
+                    "Connections still open after {}s, closing them",
+                    SHUTDOWN_TIMEOUT.as_secs()
+                );
+                task.abort();
+            }
+        }
+    }
+
+    // 待ち受けているIPが無くなっていれば、新しいローカルIPで待ち受ける
+    // （DHCPで別のアドレスになった場合や、別のネットワークに繋ぎ直した場合）
+    // サーバーを告知する仕組み（mDNSなど）は無いため、新しいアドレスは移った後のStatusUpdateでTUIに知らせるだけ
+    async fn follow_network(&self, ip: IpAddr, warned: &mut bool) -> Option<(TcpListener, IpAddr)> {
+        // インターフェースの一覧の取得はブロックするため、ランタイムの外で行う
+        let (present, found) = tokio::task::spawn_blocking(move || {
+            let interfaces = list_afinet_netifas().ok()?;
+            if interfaces.iter().any(|(_, local)| *local == ip) {
+                return Some((true, None));
+            }
+            Some((false, find_local_ip()))
+        })
+        .await
+        .ok()??;
+        if present {
+            *warned = false;
+            return None;
+        }
+
+        // 新しいアドレスで待ち受けられるまでは、古いアドレスのまま確認を続ける
+        let Some(found) = found else {
+            if !*warned {
+                tracing::warn!("Local IP {} is gone, waiting for the network", ip);
+                *warned = true;
+            }
+            return None;
+        };
+        let addr = SocketAddr::new(found, self.port);
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                tracing::info!(
+                    "Local IP changed: {} -> {}, listening on {}",
+                    ip,
+                    found,
+                    addr
+                );
+                Some((listener, found))
+            }
+            Err(e) => {
+                if !*warned {
+                    tracing::warn!("Local IP {} is gone, failed to bind to {}: {}", ip, addr, e);
+                    *warned = true;
+                }
+                None
+            }
+        }
+    }
+
+    // 再起動のために待ち受け直す（ネットワークが変わっていればローカルIPも検出し直す）
+    async fn rebind(&self, ip: &mut IpAddr) -> Result<TcpListener, Failure> {
+        if self.detect_ip {
+            match detect_local_ip().await {
+                Some(found) if found != *ip => {
+                    tracing::info!("Local IP changed: {} -> {}", ip, found);
+                    *ip = found;
+                }
+                Some(_) => {}
+                None => return Err(Failure::Error("No local IP found".to_string())),
+            }
+        }
+
+        let addr = SocketAddr::new(*ip, self.port);
+        tracing::info!("Binding to address: {}", addr);
+        TcpListener::bind(addr)
+            .await
+            .map_err(|e| Failure::Error(format!("Bind failed: {}", e)))
+    }
+
+    async fn send_status(&self, state: ServerState, ip: IpAddr) {
+        let nickname = self.app_state.config.lock().await.nickname.clone();
+        let _ = self
+            .message_sender
+            .send(ServerMessage::StatusUpdate(ServerStatus {
+                state,
+                nickname: Some(nickname),
+                ip: Some(ip.to_string()),
+                port: Some(self.port),
+            }));
+    }
+}
+
+// 確認する間隔が設定されていない場合は完了しない
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// panicの内容を文字列にする（文字列以外で起きた場合は内容を取り出せない）
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or_else(|| "unknown panic".to_string(), ToString::to_string),
+    }
+}
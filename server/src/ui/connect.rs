@@ -0,0 +1,192 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use qrcode::{Color as Module, EcLevel, QrCode};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Flex, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, Padding, Paragraph, Wrap},
+};
+use server::connect::ConnectUri;
+use std::time::Instant;
+
+// QRコードの周囲に空ける余白（モジュール数）
+const QUIET_ZONE: usize = 2;
+
+// 接続用URIと、ペアリングコードの有効期限
+pub type ConnectResult = Result<(ConnectUri, Option<Instant>), String>;
+
+// 接続用QRコードのポップアップ
+#[derive(Debug, Default)]
+pub struct ConnectCard {
+    open: bool,
+    uri: Option<ConnectUri>,
+    expires_at: Option<Instant>, // ペアリングコードの有効期限
+    qr: Vec<String>,             // 描画済みのQRコード（1文字で上下2モジュール）
+    loading: bool,
+    error: Option<String>,
+}
+
+impl ConnectCard {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // ポップアップを開く（URIの取得はAppが行い、finishで受け取る）
+    pub fn open(&mut self) {
+        self.open = true;
+        self.uri = None;
+        self.expires_at = None;
+        self.qr.clear();
+        self.loading = true;
+        self.error = None;
+    }
+
+    // ペアリングコードを含めるかどうかを返した場合、Appが作り直す
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<bool> {
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('c') => {
+                self.open = false;
+                None
+            }
+            KeyCode::Char('p') if !self.loading => {
+                self.loading = true;
+                Some(true)
+            }
+            KeyCode::Char('n') if !self.loading => {
+                self.loading = true;
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+
+    // サーバーのアドレスが変わった場合にAppが作り直す（ペアリングコードを含めていたかどうかを返す）
+    pub fn reload(&mut self) -> bool {
+        self.loading = true;
+        self.expires_at.is_some()
+    }
+
+    pub fn finish(&mut self, result: ConnectResult) {
+        self.loading = false;
+        match result {
+            Ok((uri, expires_at)) => match render_qr(&uri.to_string()) {
+                Ok(qr) => {
+                    self.qr = qr;
+                    self.uri = Some(uri);
+                    self.expires_at = expires_at;
+                    self.error = None;
+                }
+                Err(e) => self.error = Some(format!("Failed to encode QR code: {}", e)),
+            },
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        if !self.open {
+            return;
+        }
+
+        let qr_width = self.qr.first().map_or(0, |row| row.chars().count()) as u16;
+        let uri = self
+            .uri
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        // QRコード、URI、状態、操作説明が収まる大きさ（画面より大きければ縮める）
+        let width = (qr_width.max(48) + 4).min(area.width);
+        let uri_lines = (uri.chars().count() as u16).div_ceil(width.saturating_sub(4).max(1));
+        let height = (self.qr.len() as u16 + uri_lines + 6).min(area.height);
+        let [popup] = Layout::vertical([Constraint::Length(height)])
+            .flex(Flex::Center)
+            .areas(area);
+        let [popup] = Layout::horizontal([Constraint::Length(width)])
+            .flex(Flex::Center)
+            .areas(popup);
+
+        frame.render_widget(Clear, popup);
+        let block = Block::bordered()
+            .title(Line::from(" Connect a device ").bold())
+            .padding(Padding::horizontal(1));
+        let inner = block.inner(popup);
+        frame.render_widget(block, popup);
+
+        let mut lines = Vec::new();
+        if self.loading && self.uri.is_none() {
+            lines.push(Line::from("Preparing..."));
+        } else if let Some(ref error) = self.error {
+            lines.push(Line::from(error.as_str()).red());
+        }
+
+        if self.uri.is_some() {
+            // 小さい画面ではQRコードが崩れるため、URIだけを表示する
+            let fits =
+                qr_width <= inner.width && self.qr.len() as u16 + uri_lines + 2 <= inner.height;
+            if fits {
+                let style = Style::new()
+                    .fg(Color::Rgb(0, 0, 0))
+                    .bg(Color::Rgb(255, 255, 255));
+                lines.extend(
+                    self.qr
+                        .iter()
+                        .map(|row| Line::styled(row.clone(), style).centered()),
+                );
+            } else {
+                lines.push(Line::from("Terminal is too small for the QR code").yellow());
+            }
+            lines.push(Line::from(uri.clone()).cyan());
+            lines.push(self.pairing_line());
+        }
+
+        lines.push(Line::from("p: new pairing code  n: without code  Esc: close").dark_gray());
+        frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), inner);
+    }
+
+    // ペアリングコードの残り時間
+    fn pairing_line(&self) -> Line<'static> {
+        let Some(expires_at) = self.expires_at else {
+            return Line::from("No pairing code (the password is required)").dark_gray();
+        };
+        let remaining = expires_at
+            .saturating_duration_since(Instant::now())
+            .as_secs();
+        if remaining == 0 {
+            return Line::from("Pairing code expired (p: new code)").red();
+        }
+        Line::from(format!(
+            "One-time pairing code expires in {}:{:02}",
+            remaining / 60,
+            remaining % 60
+        ))
+        .green()
+    }
+}
+
+// 上下2モジュールを1文字に詰めてQRコードを描画する（黒地に白ではなく白地に黒で表示する）
+fn render_qr(data: &str) -> Result<Vec<String>, qrcode::types::QrError> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::L)?;
+    let width = code.width();
+    let modules = code.to_colors();
+    let size = width + QUIET_ZONE * 2;
+    let is_dark = |x: usize, y: usize| {
+        let (Some(x), Some(y)) = (x.checked_sub(QUIET_ZONE), y.checked_sub(QUIET_ZONE)) else {
+            return false;
+        };
+        x < width && y < width && modules[y * width + x] == Module::Dark
+    };
+
+    Ok((0..size)
+        .step_by(2)
+        .map(|y| {
+            (0..size)
+                .map(|x| match (is_dark(x, y), is_dark(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect()
+        })
+        .collect())
+}
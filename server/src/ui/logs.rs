@@ -0,0 +1,326 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Padding, Paragraph, Row, Table},
+};
+use server::{ServerConfig, logging::LogEntry};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::Level;
+
+// レベルの絞り込み（Lキーで順に切り替える）
+const LEVEL_FILTERS: [Option<Level>; 5] = [
+    None,
+    Some(Level::DEBUG),
+    Some(Level::INFO),
+    Some(Level::WARN),
+    Some(Level::ERROR),
+];
+
+// 入力欄
+#[derive(Debug)]
+enum Input {
+    Search(String),
+    Endpoint(String),
+}
+
+// ログタブの状態
+#[derive(Debug)]
+pub struct LogsView {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    scroll: usize, // 末尾から何行さかのぼって表示しているか（0なら末尾を追う）
+    page: usize,   // 前回の描画で表示できた行数（PgUp/PgDnの移動量）
+    level_filter: usize,
+    endpoint_filter: Option<String>, // パスの前方一致
+    search: Option<String>,          // 大文字小文字を区別しない部分一致
+    input: Option<Input>,
+    notice: Option<(String, bool)>, // 書き出し結果などの表示（trueはエラー）
+}
+
+impl LogsView {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: config.log_config.tui_buffer_lines,
+            scroll: 0,
+            page: 10,
+            level_filter: 0,
+            endpoint_filter: None,
+            search: None,
+            input: None,
+            notice: None,
+        }
+    }
+
+    // 設定の変更を反映する（減らした場合は古いものから捨てる）
+    pub fn load(&mut self, config: &ServerConfig) {
+        self.capacity = config.log_config.tui_buffer_lines;
+        self.truncate();
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        // さかのぼって読んでいる間は表示位置がずれないようにする
+        if self.scroll > 0 && self.matches(&entry) {
+            self.scroll += 1;
+        }
+        self.entries.push_back(entry);
+        self.truncate();
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if let Some(input) = self.input.as_mut() {
+            let buffer = match input {
+                Input::Search(buffer) | Input::Endpoint(buffer) => buffer,
+            };
+            match key.code {
+                KeyCode::Char(c) => buffer.push(c),
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Enter => {
+                    match self.input.take() {
+                        Some(Input::Search(query)) => self.search = non_empty(query),
+                        Some(Input::Endpoint(path)) => self.endpoint_filter = non_empty(path),
+                        None => {}
+                    }
+                    self.scroll = 0;
+                }
+                KeyCode::Esc => self.input = None,
+                _ => {}
+            }
+            return;
+        }
+
+        self.notice = None;
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_up(1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_down(1),
+            KeyCode::PageUp => self.scroll_up(self.page),
+            KeyCode::PageDown => self.scroll_down(self.page),
+            KeyCode::Home => self.scroll = self.filtered_len(),
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Char('l') => {
+                self.level_filter = (self.level_filter + 1) % LEVEL_FILTERS.len();
+                self.scroll = 0;
+            }
+            KeyCode::Char('e') => {
+                self.input = Some(Input::Endpoint(
+                    self.endpoint_filter.clone().unwrap_or_default(),
+                ));
+            }
+            KeyCode::Char('/') => {
+                self.input = Some(Input::Search(self.search.clone().unwrap_or_default()));
+            }
+            KeyCode::Char('c') => {
+                self.level_filter = 0;
+                self.endpoint_filter = None;
+                self.search = None;
+                self.scroll = 0;
+            }
+            KeyCode::Char('w') => self.dump(),
+            _ => {}
+        }
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),    // ログ
+                Constraint::Length(2), // 絞り込みの状態と操作説明
+            ])
+            .split(area);
+
+        // ヘッダー行を除いた表示可能行数
+        let height = chunks[0].height.saturating_sub(1) as usize;
+        self.page = height.max(1);
+
+        let filtered: Vec<&LogEntry> = self.entries.iter().filter(|e| self.matches(e)).collect();
+        self.scroll = self.scroll.min(filtered.len().saturating_sub(height));
+        let end = filtered.len() - self.scroll;
+        let start = end.saturating_sub(height);
+
+        let container = Block::new().padding(Padding::horizontal(1));
+        if filtered.is_empty() {
+            let text = if self.entries.is_empty() {
+                "サーバーを起動中..."
+            } else {
+                "条件に一致するログはありません"
+            };
+            frame.render_widget(Paragraph::new(text).block(container), chunks[0]);
+        } else {
+            let rows = filtered[start..end].iter().map(|entry| log_row(entry));
+            let widths = [
+                Constraint::Length(8),
+                Constraint::Length(5),
+                Constraint::Length(3),
+                Constraint::Length(9),
+                Constraint::Min(10),
+            ];
+            let table = Table::new(rows, widths)
+                .column_spacing(1)
+                .header(
+                    Row::new(vec!["time", "level", "st", "latency", "message"])
+                        .style(Style::new().bold().magenta()),
+                )
+                .block(container);
+            frame.render_widget(table, chunks[0]);
+        }
+
+        self.render_footer(frame, chunks[1], filtered.len());
+    }
+
+    fn render_footer(&self, frame: &mut Frame, area: Rect, shown: usize) {
+        let first = match &self.input {
+            Some(Input::Search(buffer)) => input_line("search: ", buffer),
+            Some(Input::Endpoint(buffer)) => input_line("endpoint: ", buffer),
+            None => Line::from(
+                "↑/↓ PgUp/PgDn Home/End: scroll  l: level  e: endpoint  /: search  c: clear  w: dump",
+            )
+            .dark_gray(),
+        };
+
+        let second = match &self.notice {
+            Some((notice, is_error)) => {
+                let line = Line::from(notice.as_str());
+                if *is_error { line.red() } else { line.green() }
+            }
+            None => {
+                let mut spans = vec![Span::raw(format!("{}/{} lines", shown, self.entries.len()))];
+                if let Some(level) = LEVEL_FILTERS[self.level_filter] {
+                    spans.push(Span::raw(format!("  level>={}", level)).yellow());
+                }
+                if let Some(ref endpoint) = self.endpoint_filter {
+                    spans.push(Span::raw(format!("  endpoint={}", endpoint)).yellow());
+                }
+                if let Some(ref search) = self.search {
+                    spans.push(Span::raw(format!("  search=\"{}\"", search)).yellow());
+                }
+                if self.scroll > 0 {
+                    spans.push(Span::raw(format!("  ↑{} (End: follow)", self.scroll)).cyan());
+                }
+                Line::from(spans)
+            }
+        };
+
+        let container = Block::new().padding(Padding::horizontal(1));
+        frame.render_widget(Paragraph::new(vec![first, second]).block(container), area);
+    }
+
+    fn matches(&self, entry: &LogEntry) -> bool {
+        if let Some(level) = LEVEL_FILTERS[self.level_filter]
+            && entry.level > level
+        {
+            return false;
+        }
+        if let Some(ref endpoint) = self.endpoint_filter {
+            match entry.request {
+                Some(ref request) if request.path.starts_with(endpoint.as_str()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(ref search) = self.search {
+            return entry
+                .message
+                .to_lowercase()
+                .contains(&search.to_lowercase());
+        }
+        true
+    }
+
+    fn filtered_len(&self) -> usize {
+        self.entries.iter().filter(|e| self.matches(e)).count()
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.filtered_len());
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    fn truncate(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    // 絞り込み後のログをデータディレクトリのlogs/に書き出す
+    fn dump(&mut self) {
+        let result = (|| -> server::error::ServerResult<PathBuf> {
+            let directory = ServerConfig::data_dir()?.join("logs");
+            std::fs::create_dir_all(&directory)?;
+            let path = directory.join(format!(
+                "tui-{}.log",
+                chrono::Local::now().format("%Y%m%d-%H%M%S")
+            ));
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            for entry in self.entries.iter().filter(|e| self.matches(e)) {
+                writeln!(file, "{}", entry)?;
+            }
+            file.flush()?;
+            Ok(path)
+        })();
+
+        self.notice = Some(match result {
+            Ok(path) => (format!("Dumped to {}", path.display()), false),
+            Err(e) => (format!("Failed to dump logs: {}", e), true),
+        });
+    }
+}
+
+fn log_row(entry: &LogEntry) -> Row<'_> {
+    let level = match entry.level {
+        Level::ERROR => Span::raw("ERROR").red(),
+        Level::WARN => Span::raw("WARN").yellow(),
+        Level::INFO => Span::raw("INFO").green(),
+        Level::DEBUG => Span::raw("DEBUG").blue(),
+        Level::TRACE => Span::raw("TRACE").dark_gray(),
+    };
+    let (status, latency) = match entry.request {
+        Some(ref request) => (
+            request.status.map(|status| {
+                let text = Span::raw(status.to_string());
+                match status {
+                    500.. => text.red(),
+                    400.. => text.yellow(),
+                    _ => text,
+                }
+            }),
+            request
+                .latency_ms
+                .map(|latency_ms| format!("{:.2}ms", latency_ms)),
+        ),
+        None => (None, None),
+    };
+    Row::new(vec![
+        Line::from(entry.time.format("%H:%M:%S").to_string()),
+        Line::from(level),
+        Line::from(status.unwrap_or_default()),
+        Line::from(latency.unwrap_or_default()).right_aligned(),
+        Line::from(entry.message.as_str()),
+    ])
+}
+
+fn input_line<'a>(label: &'a str, buffer: &str) -> Line<'a> {
+    Line::from(vec![
+        Span::raw(label),
+        Span::styled(format!("{} ", buffer), Style::new().reversed()),
+        Span::raw("  Enter: apply (empty to clear)  Esc: cancel").dark_gray(),
+    ])
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
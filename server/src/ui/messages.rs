@@ -0,0 +1,643 @@
+use base64::Engine;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Padding, Paragraph, Row, Table, TableState, Wrap},
+};
+use server::{
+    Attachment, ReceivedMessage, ServerEvent, error::ServerResult, message_store::MessageStore,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+// 一覧に読み込む件数（それより古いものは検索で探す）
+const HISTORY_LIMIT: usize = 500;
+// PgUp/PgDnで移動する件数
+const PAGE_SIZE: usize = 10;
+
+// 入力欄
+#[derive(Debug)]
+enum Input {
+    Search(String),
+    SavePath(String),
+    Compose,            // 送信するメッセージ（本文はdraftに持つ）
+    AttachPath(String), // 送信するメッセージに添付するファイルのパス
+}
+
+// TUIから送信するメッセージ
+#[derive(Debug)]
+pub struct OutgoingMessage {
+    pub message: String,
+    pub attachments: Vec<PathBuf>,
+}
+
+// メッセージタブの状態
+#[derive(Debug)]
+pub struct MessagesView {
+    store: Arc<MessageStore>,
+    messages: Vec<ReceivedMessage>, // 時系列順
+    table: TableState,
+    follow: bool,           // 新しいメッセージが届いたら末尾を選択する
+    search: Option<String>, // 適用中の検索語
+    input: Option<Input>,
+    detail: bool,      // 詳細欄を開いているか
+    attachment: usize, // 詳細欄で選択中の添付ファイル
+    pending_load: Option<oneshot::Receiver<ServerResult<Vec<ReceivedMessage>>>>,
+    pending_save: Option<oneshot::Receiver<Result<PathBuf, String>>>,
+    notice: Option<(String, bool)>, // 保存結果などの表示（trueはエラー）
+    draft: String,                  // 送信前の本文（送信に失敗した場合も残す）
+    draft_attachments: Vec<PathBuf>,
+    sending: bool,
+}
+
+impl MessagesView {
+    pub fn new(store: Arc<MessageStore>) -> Self {
+        let mut view = Self {
+            store,
+            messages: Vec::new(),
+            table: TableState::default(),
+            follow: true,
+            search: None,
+            input: None,
+            detail: false,
+            attachment: 0,
+            pending_load: None,
+            pending_save: None,
+            notice: None,
+            draft: String::new(),
+            draft_attachments: Vec::new(),
+            sending: false,
+        };
+        view.reload();
+        view
+    }
+
+    // ストアから読み直す（検索中は検索結果）
+    pub fn reload(&mut self) {
+        let (sender, receiver) = oneshot::channel();
+        self.pending_load = Some(receiver);
+
+        let store = self.store.clone();
+        let search = self.search.clone();
+        tokio::spawn(async move {
+            let result = match search {
+                Some(query) => store.search_messages(&query, Some(HISTORY_LIMIT)).await,
+                None => store.get_recent_messages(HISTORY_LIMIT).await,
+            };
+            let _ = sender.send(result);
+        });
+    }
+
+    // 読み込みと保存の結果を受け取る（表示が変わった場合はtrue）
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        if let Some(receiver) = self.pending_load.as_mut() {
+            match receiver.try_recv() {
+                Ok(Ok(messages)) => {
+                    self.pending_load = None;
+                    self.messages = messages;
+                    self.follow = true;
+                    self.select_last();
+                    changed = true;
+                }
+                Ok(Err(e)) => {
+                    self.pending_load = None;
+                    self.set_notice(format!("Failed to load messages: {}", e), true);
+                    changed = true;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => self.pending_load = None,
+            }
+        }
+
+        if let Some(receiver) = self.pending_save.as_mut() {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    self.pending_save = None;
+                    match result {
+                        Ok(path) => self.set_notice(format!("Saved to {}", path.display()), false),
+                        Err(e) => self.set_notice(format!("Failed to save: {}", e), true),
+                    }
+                    changed = true;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => self.pending_save = None,
+            }
+        }
+        changed
+    }
+
+    // 配信されたイベントを一覧に反映する
+    pub fn handle_event(&mut self, event: &ServerEvent) {
+        match event {
+            ServerEvent::Message(message) => {
+                if !self.matches_search(message) {
+                    return;
+                }
+                self.messages.push(message.clone());
+                if self.messages.len() > HISTORY_LIMIT {
+                    self.messages.remove(0);
+                    if let Some(selected) = self.table.selected() {
+                        self.table.select(Some(selected.saturating_sub(1)));
+                    }
+                }
+                if self.follow {
+                    self.select_last();
+                }
+            }
+            ServerEvent::Edit(message) | ServerEvent::Pin(message) => {
+                if let Some(existing) = self.messages.iter_mut().find(|m| m.id == message.id) {
+                    *existing = message.clone();
+                }
+            }
+            ServerEvent::Delete(deleted) => {
+                if let Some(index) = self.messages.iter().position(|m| m.id == deleted.id) {
+                    self.messages.remove(index);
+                    match self.table.selected() {
+                        _ if self.messages.is_empty() => self.table.select(None),
+                        Some(selected) if selected >= index => {
+                            self.table.select(Some(
+                                selected.saturating_sub(1).min(self.messages.len() - 1),
+                            ));
+                        }
+                        _ => {}
+                    }
+                    self.attachment = 0;
+                }
+            }
+            ServerEvent::Lagged(_) => self.reload(),
+            _ => {}
+        }
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn is_detail_open(&self) -> bool {
+        self.detail
+    }
+
+    // 送信の結果を表示する（成功した場合は下書きを消す）
+    pub fn finish_send(&mut self, result: Result<(), String>) {
+        self.sending = false;
+        match result {
+            Ok(()) => {
+                self.draft.clear();
+                self.draft_attachments.clear();
+                self.set_notice("Message sent", false);
+            }
+            Err(e) => self.set_notice(format!("Failed to send: {}", e), true),
+        }
+    }
+
+    // キー入力を処理し、送信が要求された場合はメッセージを返す
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<OutgoingMessage> {
+        match self.input.as_mut() {
+            Some(Input::Compose) => return self.handle_compose_key(key),
+            Some(Input::Search(buffer) | Input::SavePath(buffer) | Input::AttachPath(buffer)) => {
+                match key.code {
+                    KeyCode::Char(c) => buffer.push(c),
+                    KeyCode::Backspace => {
+                        buffer.pop();
+                    }
+                    KeyCode::Enter => match self.input.take() {
+                        Some(Input::Search(query)) => self.apply_search(query),
+                        Some(Input::SavePath(path)) => self.save_attachment(PathBuf::from(path)),
+                        Some(Input::AttachPath(path)) => self.add_draft_attachment(&path),
+                        _ => {}
+                    },
+                    // 添付ファイルの入力をやめた場合は本文の入力に戻る
+                    KeyCode::Esc => {
+                        if let Some(Input::AttachPath(_)) = self.input.take() {
+                            self.input = Some(Input::Compose);
+                        }
+                    }
+                    _ => {}
+                }
+                return None;
+            }
+            None => {}
+        }
+
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-(PAGE_SIZE as isize)),
+            KeyCode::PageDown => self.move_selection(PAGE_SIZE as isize),
+            KeyCode::Home if !self.messages.is_empty() => {
+                self.table.select(Some(0));
+                self.follow = false;
+                self.attachment = 0;
+            }
+            KeyCode::End => {
+                self.follow = true;
+                self.select_last();
+            }
+            KeyCode::Enter => {
+                self.detail = !self.detail && self.selected().is_some();
+                self.attachment = 0;
+            }
+            KeyCode::Esc => self.detail = false,
+            KeyCode::Tab if self.detail => {
+                let count = self.selected().map_or(0, |m| m.attachments.len());
+                if count > 0 {
+                    self.attachment = (self.attachment + 1) % count;
+                }
+            }
+            KeyCode::Char('s') if self.detail => match self.selected_attachment() {
+                Some(attachment) => {
+                    let directory = dirs::download_dir()
+                        .or_else(|| std::env::current_dir().ok())
+                        .unwrap_or_default();
+                    let path = directory.join(&attachment.filename);
+                    self.input = Some(Input::SavePath(path.display().to_string()));
+                }
+                None => self.set_notice("No attachment to save", true),
+            },
+            KeyCode::Char('/') => {
+                self.input = Some(Input::Search(self.search.clone().unwrap_or_default()));
+            }
+            KeyCode::Char('r') => self.reload(),
+            KeyCode::Char('c') => self.input = Some(Input::Compose),
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_compose_key(&mut self, key: KeyEvent) -> Option<OutgoingMessage> {
+        match (key.modifiers, key.code) {
+            (KeyModifiers::CONTROL, KeyCode::Char('a')) => {
+                self.input = Some(Input::AttachPath(String::new()));
+            }
+            (KeyModifiers::CONTROL, KeyCode::Char('d')) => {
+                self.draft_attachments.pop();
+            }
+            (_, KeyCode::Char(c)) => self.draft.push(c),
+            (_, KeyCode::Backspace) => {
+                self.draft.pop();
+            }
+            (_, KeyCode::Enter) => {
+                if self.sending {
+                    return None;
+                }
+                if self.draft.trim().is_empty() && self.draft_attachments.is_empty() {
+                    self.set_notice("Nothing to send", true);
+                    return None;
+                }
+                self.input = None;
+                self.sending = true;
+                self.set_notice("Sending...", false);
+                return Some(OutgoingMessage {
+                    message: self.draft.clone(),
+                    attachments: self.draft_attachments.clone(),
+                });
+            }
+            (_, KeyCode::Esc) => self.input = None,
+            _ => {}
+        }
+        None
+    }
+
+    // 添付するファイルを追加する（~/ はホームディレクトリとして扱う）
+    fn add_draft_attachment(&mut self, path: &str) {
+        self.input = Some(Input::Compose);
+        let path = path.trim();
+        if path.is_empty() {
+            return;
+        }
+        let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(path),
+        };
+        if path.is_file() {
+            self.draft_attachments.push(path);
+            self.notice = None;
+        } else {
+            self.set_notice(format!("{} is not a file", path.display()), true);
+        }
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),    // 一覧と詳細
+                Constraint::Length(2), // 操作説明と入力欄
+            ])
+            .split(area);
+
+        let (list_area, detail_area) = if self.detail {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .split(chunks[0]);
+            (columns[0], Some(columns[1]))
+        } else {
+            (chunks[0], None)
+        };
+
+        self.render_list(frame, list_area);
+        if let Some(detail_area) = detail_area {
+            self.render_detail(frame, detail_area);
+        }
+        self.render_footer(frame, chunks[1]);
+    }
+
+    fn render_list(&mut self, frame: &mut Frame, area: Rect) {
+        let container = Block::new().padding(Padding::horizontal(1));
+
+        if self.messages.is_empty() {
+            let text = match (&self.pending_load, &self.search) {
+                (Some(_), _) => "読み込み中...".to_string(),
+                (None, Some(query)) => format!("「{}」に一致するメッセージはありません", query),
+                (None, None) => "メッセージはありません".to_string(),
+            };
+            frame.render_widget(Paragraph::new(text).block(container), area);
+            return;
+        }
+
+        let rows = self.messages.iter().map(|message| {
+            let from = if message.is_self {
+                format!("{} (self)", message.from_name)
+            } else {
+                message.from_name.clone()
+            };
+            let files = match message.attachments.len() {
+                0 => String::new(),
+                count => count.to_string(),
+            };
+            let mut text = message
+                .message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            if message.pinned {
+                text.insert_str(0, "📌 ");
+            }
+            Row::new(vec![
+                format_time(&message.timestamp),
+                from,
+                message.message_type.clone(),
+                files,
+                text,
+            ])
+        });
+
+        let widths = [
+            Constraint::Length(14),
+            Constraint::Length(16),
+            Constraint::Length(8),
+            Constraint::Length(5),
+            Constraint::Min(10),
+        ];
+
+        let table = Table::new(rows, widths)
+            .column_spacing(1)
+            .header(
+                Row::new(vec!["time", "from", "type", "files", "message"])
+                    .style(Style::new().bold().magenta()),
+            )
+            .block(container)
+            .row_highlight_style(Style::new().reversed());
+
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn render_detail(&self, frame: &mut Frame, area: Rect) {
+        let container = Block::new()
+            .borders(Borders::LEFT)
+            .padding(Padding::horizontal(1));
+
+        let Some(message) = self.selected() else {
+            frame.render_widget(container, area);
+            return;
+        };
+
+        let field = |label: &'static str, value: String| {
+            Line::from(vec![
+                Span::styled(format!("{:<10}", label), Style::new().magenta()),
+                Span::raw(value),
+            ])
+        };
+        let mut lines = vec![
+            field("from", format!("{} ({})", message.from_name, message.from)),
+            field("time", format_time(&message.timestamp)),
+            field("type", message.message_type.clone()),
+            field("id", message.id.clone()),
+        ];
+        if let Some(ref edited_at) = message.edited_at {
+            lines.push(field("edited", format_time(edited_at)));
+        }
+        if message.pinned {
+            lines.push(field("pinned", "yes".to_string()));
+        }
+        lines.push(Line::default());
+        lines.extend(
+            message
+                .message
+                .lines()
+                .map(|line| Line::from(line.to_string())),
+        );
+
+        if !message.attachments.is_empty() {
+            lines.push(Line::default());
+            lines.push(Line::from("attachments").magenta());
+            for (index, attachment) in message.attachments.iter().enumerate() {
+                let text = format!(
+                    "{} {} ({}, {})",
+                    if index == self.attachment { ">" } else { " " },
+                    attachment.filename,
+                    attachment.mime_type,
+                    format_size(attachment.size)
+                );
+                lines.push(if index == self.attachment {
+                    Line::from(text).yellow()
+                } else {
+                    Line::from(text)
+                });
+            }
+        }
+
+        let paragraph = Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(container);
+        frame.render_widget(paragraph, area);
+    }
+
+    fn render_footer(&self, frame: &mut Frame, area: Rect) {
+        let first = match &self.input {
+            Some(Input::Search(buffer)) => Line::from(vec![
+                Span::raw("search: "),
+                Span::styled(buffer.clone(), Style::new().reversed()),
+                Span::raw("  Enter: search (empty to clear)  Esc: cancel").dark_gray(),
+            ]),
+            Some(Input::SavePath(buffer)) => Line::from(vec![
+                Span::raw("save to: "),
+                Span::styled(buffer.clone(), Style::new().reversed()),
+                Span::raw("  Enter: save  Esc: cancel").dark_gray(),
+            ]),
+            Some(Input::Compose) => Line::from(vec![
+                Span::raw("message: "),
+                Span::styled(format!("{} ", self.draft), Style::new().reversed()),
+                Span::raw("  Enter: send  Ctrl+A: attach  Ctrl+D: remove attachment  Esc: close")
+                    .dark_gray(),
+            ]),
+            Some(Input::AttachPath(buffer)) => Line::from(vec![
+                Span::raw("attach file: "),
+                Span::styled(format!("{} ", buffer), Style::new().reversed()),
+                Span::raw("  Enter: add  Esc: back").dark_gray(),
+            ]),
+            None if self.detail => {
+                Line::from("↑/↓: select  Tab: next attachment  s: save attachment  Esc: close")
+                    .dark_gray()
+            }
+            None => {
+                Line::from("↑/↓ PgUp/PgDn Home/End: scroll  Enter: details  /: search  r: reload")
+                    .dark_gray()
+            }
+        };
+
+        let mut footer = vec![first];
+        if !self.draft_attachments.is_empty()
+            && matches!(self.input, Some(Input::Compose | Input::AttachPath(_)))
+        {
+            let names: Vec<String> = self
+                .draft_attachments
+                .iter()
+                .map(|path| {
+                    path.file_name().map_or_else(
+                        || path.display().to_string(),
+                        |name| name.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect();
+            footer.push(Line::from(format!("attachments: {}", names.join(", "))).cyan());
+        } else if let Some((notice, is_error)) = &self.notice {
+            let line = Line::from(notice.as_str());
+            footer.push(if *is_error { line.red() } else { line.green() });
+        } else if let Some(ref query) = self.search {
+            footer.push(
+                Line::from(format!(
+                    "「{}」の検索結果: {}件",
+                    query,
+                    self.messages.len()
+                ))
+                .yellow(),
+            );
+        }
+        let container = Block::new().padding(Padding::horizontal(1));
+        frame.render_widget(Paragraph::new(footer).block(container), area);
+    }
+
+    fn apply_search(&mut self, query: String) {
+        let query = query.trim().to_string();
+        self.search = (!query.is_empty()).then_some(query);
+        self.notice = None;
+        self.detail = false;
+        self.reload();
+    }
+
+    // 選択中の添付ファイルを書き出す（既存のファイルは上書きしない）
+    fn save_attachment(&mut self, path: PathBuf) {
+        let Some(attachment) = self.selected_attachment().cloned() else {
+            return;
+        };
+        if attachment.data.is_empty() {
+            self.set_notice("The attachment data has been removed by retention", true);
+            return;
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending_save = Some(receiver);
+        self.set_notice("Saving...", false);
+        tokio::task::spawn_blocking(move || {
+            let result = decode_attachment(&attachment)
+                .and_then(|bytes| {
+                    use std::io::Write;
+                    let mut file = std::fs::File::create_new(&path).map_err(|e| e.to_string())?;
+                    file.write_all(&bytes).map_err(|e| e.to_string())
+                })
+                .map(|()| path);
+            let _ = sender.send(result);
+        });
+    }
+
+    fn selected(&self) -> Option<&ReceivedMessage> {
+        self.table
+            .selected()
+            .and_then(|index| self.messages.get(index))
+    }
+
+    fn selected_attachment(&self) -> Option<&Attachment> {
+        self.selected()
+            .and_then(|message| message.attachments.get(self.attachment))
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.messages.is_empty() {
+            return;
+        }
+        let last = self.messages.len() - 1;
+        let current = self.table.selected().unwrap_or(last);
+        let next = current.saturating_add_signed(delta).min(last);
+        self.table.select(Some(next));
+        self.follow = next == last;
+        self.attachment = 0;
+    }
+
+    fn select_last(&mut self) {
+        let last = self.messages.len().checked_sub(1);
+        self.table.select(last);
+        self.attachment = 0;
+    }
+
+    // 検索中は一致するメッセージだけを追加する（SQLのLIKEに合わせて大文字小文字を区別しない）
+    fn matches_search(&self, message: &ReceivedMessage) -> bool {
+        match self.search {
+            Some(ref query) => message
+                .message
+                .to_lowercase()
+                .contains(&query.to_lowercase()),
+            None => true,
+        }
+    }
+
+    fn set_notice(&mut self, notice: impl Into<String>, is_error: bool) {
+        self.notice = Some((notice.into(), is_error));
+    }
+}
+
+// 添付ファイルのデータ（Base64、data URLの場合もある）をバイト列に戻す
+fn decode_attachment(attachment: &Attachment) -> Result<Vec<u8>, String> {
+    let data = match attachment.data.split_once(',') {
+        Some((prefix, data)) if prefix.starts_with("data:") => data,
+        _ => attachment.data.as_str(),
+    };
+    base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| format!("invalid attachment data: {}", e))
+}
+
+fn format_time(timestamp: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%m/%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+pub(super) fn format_size(size: u64) -> String {
+    match size {
+        0..1024 => format!("{} B", size),
+        1024..1_048_576 => format!("{:.1} KB", size as f64 / 1024.0),
+        _ => format!("{:.1} MB", size as f64 / 1_048_576.0),
+    }
+}
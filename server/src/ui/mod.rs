@@ -1,18 +1,55 @@
 use color_eyre::eyre::Result;
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::event::{
+    Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+use futures::StreamExt;
 use ratatui::{
     DefaultTerminal, Frame,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Position, Rect},
     style::{Style, Stylize},
     text::Line,
     widgets::{Block, Padding, Paragraph, Row, Table, Tabs},
 };
 use std::collections::VecDeque;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
 
 use crate::server_manager::ServerManager;
-use server::{ServerMessage, ServerState, ServerStatus};
+use server::{
+    RestartRecord, ServerConfig, ServerMessage, ServerState, ServerStatus, SettingsUpdate,
+    message_store::MessageStore, presence::ClientInfo,
+};
+
+mod connect;
+mod logs;
+mod messages;
+mod settings;
+mod stats;
+mod unlock;
+
+use connect::{ConnectCard, ConnectResult};
+use logs::LogsView;
+use messages::{MessagesView, OutgoingMessage};
+use settings::SettingsForm;
+use stats::StatsView;
+pub use unlock::unlock;
+
+// タブの見出し（選択中のタブの番号はこの並び順）
+const TAB_TITLES: [&str; 6] = [
+    "Logs", "Control", "Clients", "Settings", "Messages", "Stats",
+];
+const TAB_DIVIDER: &str = " | ";
+// バックグラウンドで実行中の保存や送信の結果を確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// 経過時間やペアリングコードの残り時間など、時間で変わる表示を更新する間隔
+const CLOCK_INTERVAL: Duration = Duration::from_secs(1);
+// マウスホイール1回でスクロールする行数
+const WHEEL_LINES: usize = 3;
+// Controlタブに表示する、サーバーが落ちた記録の数
+const RESTART_HISTORY: usize = 20;
 
 /// The main application which holds the state and logic of the application.
 #[derive(Debug)]
@@ -20,7 +57,7 @@ pub struct App {
     /// Is the application running?
     running: bool,
     /// Server logs
-    logs: VecDeque<String>,
+    logs: LogsView,
     /// Message receiver channel
     message_receiver: mpsc::UnboundedReceiver<ServerMessage>,
     /// Current server status
@@ -29,6 +66,30 @@ pub struct App {
     selected_tab: usize,
     /// Server manager for controlling server
     server_manager: Arc<ServerManager>,
+    /// Connected clients
+    clients: Vec<ClientInfo>,
+    /// Settings tab form
+    settings: SettingsForm,
+    /// Result of the settings save in progress
+    pending_save: Option<oneshot::Receiver<Result<(), String>>>,
+    /// Config changes waiting for a server restart
+    restart_required: Vec<String>,
+    /// Messages tab
+    messages: MessagesView,
+    /// Result of the message send in progress
+    pending_send: Option<oneshot::Receiver<Result<(), String>>>,
+    /// Connection QR code popup
+    connect: ConnectCard,
+    /// Connect URI being prepared for the popup
+    pending_connect: Option<oneshot::Receiver<ConnectResult>>,
+    /// Completion of the server stop before quitting
+    pending_stop: Option<oneshot::Receiver<()>>,
+    /// Stats tab
+    stats: StatsView,
+    /// Recent times the server went down (oldest first)
+    restarts: VecDeque<RestartRecord>,
+    /// Where the tab titles were last drawn (for mouse clicks)
+    tabs_area: Rect,
 }
 
 impl App {
@@ -36,10 +97,13 @@ impl App {
     pub fn new(
         message_receiver: mpsc::UnboundedReceiver<ServerMessage>,
         server_manager: Arc<ServerManager>,
+        config: &ServerConfig,
+        message_store: Arc<MessageStore>,
     ) -> Self {
+        let metrics = server_manager.metrics();
         Self {
             running: true,
-            logs: VecDeque::new(),
+            logs: LogsView::new(config),
             message_receiver,
             server_status: ServerStatus {
                 state: ServerState::Starting,
@@ -49,6 +113,18 @@ impl App {
             },
             selected_tab: 0, // デフォルトでLogsタブを選択
             server_manager,
+            clients: Vec::new(),
+            settings: SettingsForm::new(config),
+            pending_save: None,
+            restart_required: Vec::new(),
+            messages: MessagesView::new(message_store.clone()),
+            pending_send: None,
+            connect: ConnectCard::default(),
+            pending_connect: None,
+            pending_stop: None,
+            stats: StatsView::new(metrics, message_store, config),
+            restarts: VecDeque::new(),
+            tabs_area: Rect::default(),
         }
     }
 
@@ -60,38 +136,161 @@ impl App {
         let server_manager_clone = self.server_manager.clone();
         tokio::spawn(async move {
             if let Err(e) = server_manager_clone.start_server().await {
-                eprintln!("Failed to start server: {}", e);
+                tracing::error!("Failed to start server: {}", e);
             }
         });
 
+        let mut events = EventStream::new();
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        poll.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut clock = tokio::time::interval(CLOCK_INTERVAL);
+        clock.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        // 表示が変わった場合だけ描画する
+        let mut dirty = true;
         while self.running {
-            // 新しいメッセージを受信
-            while let Ok(message) = self.message_receiver.try_recv() {
-                match message {
-                    ServerMessage::Log(log_message) => {
-                        self.logs.push_back(log_message);
-                        // ログの最大数を制限（例：100行）
-                        if self.logs.len() > 100 {
-                            self.logs.pop_front();
+            if dirty {
+                // Statsタブは描画のたびに最新の集計を取る（時計で毎秒描画される）
+                if self.selected_tab == 5 {
+                    self.stats.refresh();
+                }
+                terminal.draw(|frame| self.render(frame))?;
+            }
+
+            dirty = tokio::select! {
+                Some(message) = self.message_receiver.recv() => {
+                    self.handle_server_message(message);
+                    // まとめて届いたログなどは一度の描画で済ませる
+                    while let Ok(message) = self.message_receiver.try_recv() {
+                        self.handle_server_message(message);
+                    }
+                    true
+                }
+                event = events.next() => match event {
+                    Some(Ok(event)) => self.handle_crossterm_event(event),
+                    Some(Err(e)) => return Err(e.into()),
+                    None => {
+                        // 端末の入力が閉じられた場合は、表示せずにサーバーを止めて終了する
+                        if let Err(e) = self.server_manager.stop_server().await {
+                            tracing::error!("Failed to stop server: {}", e);
                         }
+                        break;
                     }
-                    ServerMessage::StatusUpdate(status) => {
-                        self.server_status = status;
+                },
+                _ = poll.tick() => self.poll_pending(),
+                _ = clock.tick() => true,
+            };
+        }
+        Ok(())
+    }
+
+    // サーバーからの通知を反映する
+    fn handle_server_message(&mut self, message: ServerMessage) {
+        match message {
+            ServerMessage::Log(entry) => self.logs.push(entry),
+            ServerMessage::StatusUpdate(status) => {
+                // 停止したら接続中のクライアントは無くなる
+                if !matches!(status.state, ServerState::Running) {
+                    self.clients.clear();
+                }
+                // 再起動すれば保留中の設定も反映される
+                if matches!(status.state, ServerState::Starting) {
+                    self.restart_required.clear();
+                }
+                // ネットワークが変わって別のアドレスで待ち受け直した場合は、QRコードも作り直す
+                let moved = status.ip.is_some() && status.ip != self.server_status.ip;
+                self.server_status = status;
+                if moved
+                    && matches!(self.server_status.state, ServerState::Running)
+                    && self.connect.is_open()
+                {
+                    let pairing_code = self.connect.reload();
+                    self.prepare_connect_uri(pairing_code);
+                }
+            }
+            ServerMessage::ClientsUpdate(clients) => {
+                self.clients = clients;
+            }
+            ServerMessage::ConfigChanged(config) => {
+                self.server_status.nickname = Some(config.nickname.clone());
+                self.settings.load(&config);
+                self.logs.load(&config);
+                self.stats.load(&config);
+            }
+            ServerMessage::Event(event) => {
+                self.messages.handle_event(&event);
+            }
+            ServerMessage::Restart(record) => {
+                if self.restarts.len() == RESTART_HISTORY {
+                    self.restarts.pop_front();
+                }
+                self.restarts.push_back(record);
+            }
+            ServerMessage::RestartRequired(fields) => {
+                for field in fields {
+                    if !self.restart_required.contains(&field) {
+                        self.restart_required.push(field);
                     }
                 }
             }
+        }
+    }
 
-            terminal.draw(|frame| self.render(frame))?;
+    // バックグラウンドで実行中の処理の結果を受け取る（表示が変わった場合はtrue）
+    fn poll_pending(&mut self) -> bool {
+        let mut changed = self.messages.poll();
+        changed |= self.stats.poll();
+
+        // 設定の保存結果を受け取る
+        if let Some(receiver) = self.pending_save.as_mut() {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    self.settings.finish_save(result);
+                    self.pending_save = None;
+                    changed = true;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => self.pending_save = None,
+            }
+        }
+
+        // メッセージの送信結果を受け取る
+        if let Some(receiver) = self.pending_send.as_mut() {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    self.messages.finish_send(result);
+                    self.pending_send = None;
+                    changed = true;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => self.pending_send = None,
+            }
+        }
 
-            // イベントをノンブロッキングでチェック
-            if event::poll(std::time::Duration::from_millis(50))? {
-                self.handle_crossterm_events()?;
+        // 接続用URIの作成結果を受け取る
+        if let Some(receiver) = self.pending_connect.as_mut() {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    self.connect.finish(result);
+                    self.pending_connect = None;
+                    changed = true;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => self.pending_connect = None,
             }
+        }
 
-            // 少し待機してCPU使用率を下げる
-            tokio::time::sleep(std::time::Duration::from_millis(16)).await;
+        // サーバーが止まったら終了する
+        if let Some(receiver) = self.pending_stop.as_mut()
+            && !matches!(
+                receiver.try_recv(),
+                Err(oneshot::error::TryRecvError::Empty)
+            )
+        {
+            self.pending_stop = None;
+            self.running = false;
         }
-        Ok(())
+        changed
     }
 
     /// Renders the user interface.
@@ -117,12 +316,28 @@ impl App {
                 let port = self.server_status.port.unwrap_or(0);
                 format!("running on {} / {}:{}", name, ip, port)
             }
+            ServerState::Restarting { attempt, retry_at } => format!(
+                "went down, restarting in {}s (attempt {})",
+                retry_at.saturating_duration_since(Instant::now()).as_secs(),
+                attempt
+            ),
+            ServerState::Stopping => "stopping...".to_string(),
             ServerState::Stopped => "stopped".to_string(),
             ServerState::Aborted => "aborted".to_string(),
             ServerState::Error(err) => format!("error - {}", err),
         };
 
-        let title_paragraph = Paragraph::new(status_text)
+        let mut status_line = Line::from(status_text);
+        if self.pending_stop.is_some() {
+            status_line.push_span("  (quitting, press q again to quit now)".yellow());
+        }
+        if !self.restart_required.is_empty() {
+            status_line.push_span(
+                format!("  (restart to apply: {})", self.restart_required.join(", ")).yellow(),
+            );
+        }
+
+        let title_paragraph = Paragraph::new(status_line)
             .block(
                 Block::bordered()
                     .title(title)
@@ -133,9 +348,12 @@ impl App {
 
         // タブとコンテンツを含む統一されたエリア
         self.render_tabbed_content(frame, chunks[1]);
+
+        // 接続用QRコードは全体の上に重ねる
+        self.connect.render(frame, frame.area());
     }
 
-    fn render_tabbed_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+    fn render_tabbed_content(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
         // 全体を一つのボーダーで囲む
         let block = Block::bordered();
         let inner_area = block.inner(area);
@@ -151,34 +369,28 @@ impl App {
             .split(inner_area);
 
         // タブ部分（ボーダーなし）
-        let tab_titles = vec!["Logs", "Control"];
-        let tabs = Tabs::new(tab_titles)
+        let tabs = Tabs::new(TAB_TITLES)
             .style(Style::default().white())
             .highlight_style(Style::default().yellow().bold())
             .select(self.selected_tab)
-            .divider(" | ")
+            .divider(TAB_DIVIDER)
             .padding(" ", " ");
         frame.render_widget(tabs, tab_chunks[0]);
+        self.tabs_area = tab_chunks[0];
 
         // コンテンツ部分（選択されたタブに応じて変更）
         match self.selected_tab {
             0 => self.render_logs_content(frame, tab_chunks[1]),
             1 => self.render_control_content(frame, tab_chunks[1]),
+            2 => self.render_clients_content(frame, tab_chunks[1]),
+            3 => self.render_settings_content(frame, tab_chunks[1]),
+            4 => self.render_messages_content(frame, tab_chunks[1]),
+            5 => self.render_stats_content(frame, tab_chunks[1]),
             _ => self.render_logs_content(frame, tab_chunks[1]),
         }
     }
 
-    fn render_logs_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
-        let log_text = if self.logs.is_empty() {
-            "サーバーを起動中...".to_string()
-        } else {
-            self.logs
-                .iter()
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n")
-        };
-
+    fn render_logs_content(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
         // 上部に水平線を描画
         let content_chunks = Layout::default()
             .direction(Direction::Vertical)
@@ -192,85 +404,7 @@ impl App {
         let separator = Block::default().borders(ratatui::widgets::Borders::TOP);
         frame.render_widget(separator, content_chunks[0]);
 
-        let logs_container = Block::default()
-            .borders(ratatui::widgets::Borders::NONE)
-            .padding(Padding::horizontal(1));
-
-        // 表示可能行数を計算（パディングを考慮）
-        let available_height = content_chunks[1].height.saturating_sub(0) as usize;
-
-        // スクロール位置を計算（最新のログが常に表示されるように）
-        let total_lines = self.logs.len();
-        let scroll_offset = if total_lines > available_height {
-            (total_lines - available_height) as u16
-        } else {
-            0
-        };
-
-        // ログ内容（ボーダーなし）
-        let logs_paragraph = Paragraph::new(log_text)
-            .scroll((scroll_offset, 0))
-            .block(logs_container);
-
-        frame.render_widget(logs_paragraph, content_chunks[1]);
-    }
-
-    fn render_info_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
-        // 上部に水平線を描画
-        let content_chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(1), // 水平線部分
-                Constraint::Min(0),    // 設定内容部分
-            ])
-            .split(area);
-
-        // 水平線を描画
-        let separator = Block::default().borders(ratatui::widgets::Borders::TOP);
-        frame.render_widget(separator, content_chunks[0]);
-
-        let binding = self
-            .server_status
-            .port
-            .map_or_else(|| "N/A".to_string(), |p| p.to_string());
-        let rows = [
-            Row::new(vec![
-                "nickname",
-                self.server_status.nickname.as_deref().unwrap_or("N/A"),
-                "host name that will be shown on clients",
-            ]),
-            Row::new(vec![
-                "ip",
-                self.server_status.ip.as_deref().unwrap_or("N/A"),
-                "IP address of the server",
-            ]),
-            Row::new(vec![
-                "port",
-                &binding,
-                "port number the server is listening on",
-            ]),
-        ];
-
-        // Columns widths are constrained in the same way as Layout...
-        let widths = [
-            Constraint::Length(15),
-            Constraint::Length(25),
-            Constraint::Min(0),
-        ];
-
-        let table_container = Block::new()
-            .borders(ratatui::widgets::Borders::NONE)
-            .padding(Padding::horizontal(1));
-        let table = Table::new(rows, widths)
-            .column_spacing(1)
-            .header(Row::new(vec!["name", "value", "info"]).style(Style::new().bold().magenta()))
-            .block(table_container)
-            .row_highlight_style(Style::new().reversed())
-            .column_highlight_style(Style::new().red())
-            .cell_highlight_style(Style::new())
-            .highlight_symbol(">>");
-
-        frame.render_widget(table, content_chunks[1]);
+        self.logs.render(frame, content_chunks[1]);
     }
 
     fn render_control_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
@@ -294,6 +428,8 @@ impl App {
                 Constraint::Length(3), // サーバー状態表示
                 Constraint::Length(3), // 起動ボタン
                 Constraint::Length(3), // 停止ボタン
+                Constraint::Length(3), // 接続用QRコード
+                Constraint::Min(3),    // サーバーが落ちた記録
             ])
             .margin(2)
             .split(content_chunks[1]);
@@ -302,6 +438,13 @@ impl App {
         let status_text = match &self.server_status.state {
             ServerState::Starting => "Server Status: Starting...".green(),
             ServerState::Running => "Server Status: Running".green().bold(),
+            ServerState::Restarting { attempt, retry_at } => format!(
+                "Server Status: Restarting in {}s (attempt {})",
+                retry_at.saturating_duration_since(Instant::now()).as_secs(),
+                attempt
+            )
+            .yellow(),
+            ServerState::Stopping => "Server Status: Stopping...".yellow(),
             ServerState::Stopped => "Server Status: Stopped".red(),
             ServerState::Aborted => "Server Status: Aborted".red(),
             ServerState::Error(err) => format!("Server Status: Error - {}", err).red(),
@@ -315,7 +458,7 @@ impl App {
         // 起動ボタン
         let start_button_style = if matches!(
             self.server_status.state,
-            ServerState::Stopped | ServerState::Error(_)
+            ServerState::Stopped | ServerState::Aborted | ServerState::Error(_)
         ) {
             Style::default().green().bold()
         } else {
@@ -329,7 +472,10 @@ impl App {
         frame.render_widget(start_button, control_chunks[1]);
 
         // 停止ボタン
-        let stop_button_style = if matches!(self.server_status.state, ServerState::Running) {
+        let stop_button_style = if matches!(
+            self.server_status.state,
+            ServerState::Running | ServerState::Restarting { .. }
+        ) {
             Style::default().red().bold()
         } else {
             Style::default().dark_gray()
@@ -340,75 +486,426 @@ impl App {
             .block(Block::bordered().title("Stop"))
             .centered();
         frame.render_widget(stop_button, control_chunks[2]);
+
+        // 接続用QRコード
+        let connect_button_style = if matches!(self.server_status.state, ServerState::Running) {
+            Style::default().cyan().bold()
+        } else {
+            Style::default().dark_gray()
+        };
+
+        let connect_button = Paragraph::new("Press 'C' to show a QR code for connecting a device")
+            .style(connect_button_style)
+            .block(Block::bordered().title("Connect"))
+            .centered();
+        frame.render_widget(connect_button, control_chunks[3]);
+
+        // サーバーが落ちた記録（新しい順）
+        let history: Vec<Line> = if self.restarts.is_empty() {
+            vec![Line::from("The server has not gone down").dark_gray()]
+        } else {
+            self.restarts
+                .iter()
+                .rev()
+                .map(|record| {
+                    let outcome = match record.retry_in {
+                        Some(delay) => format!("restart in {}s", delay.as_secs()).yellow(),
+                        None => "gave up".red(),
+                    };
+                    Line::from(vec![
+                        format!(
+                            "{}  #{}  {}  ",
+                            record.at.format("%m/%d %H:%M:%S"),
+                            record.attempt,
+                            record.reason
+                        )
+                        .into(),
+                        outcome,
+                    ])
+                })
+                .collect()
+        };
+        let history_paragraph =
+            Paragraph::new(history).block(Block::bordered().title("Restart history"));
+        frame.render_widget(history_paragraph, control_chunks[4]);
+    }
+
+    fn render_clients_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        // 上部に水平線を描画
+        let content_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1), // 水平線部分
+                Constraint::Min(0),    // クライアント一覧部分
+            ])
+            .split(area);
+
+        // 水平線を描画
+        let separator = Block::default().borders(ratatui::widgets::Borders::TOP);
+        frame.render_widget(separator, content_chunks[0]);
+
+        let container = Block::new()
+            .borders(ratatui::widgets::Borders::NONE)
+            .padding(Padding::horizontal(1));
+
+        if self.clients.is_empty() {
+            let empty = Paragraph::new("接続中のクライアントはありません").block(container);
+            frame.render_widget(empty, content_chunks[1]);
+            return;
+        }
+
+        let now = chrono::Utc::now();
+        let rows = self.clients.iter().map(|client| {
+            let connected_since = chrono::DateTime::parse_from_rfc3339(&client.connected_since)
+                .map(|time| {
+                    time.with_timezone(&chrono::Local)
+                        .format("%m/%d %H:%M:%S")
+                        .to_string()
+                })
+                .unwrap_or_else(|_| client.connected_since.clone());
+            let last_activity = chrono::DateTime::parse_from_rfc3339(&client.last_activity)
+                .map(|time| format_elapsed(now.signed_duration_since(time)))
+                .unwrap_or_else(|_| client.last_activity.clone());
+
+            Row::new(vec![
+                client.device_name.clone(),
+                client.ip.clone(),
+                client.transports.join(", "),
+                connected_since,
+                last_activity,
+            ])
+        });
+
+        let widths = [
+            Constraint::Min(20),
+            Constraint::Length(16),
+            Constraint::Length(10),
+            Constraint::Length(16),
+            Constraint::Length(14),
+        ];
+
+        let table = Table::new(rows, widths)
+            .column_spacing(1)
+            .header(
+                Row::new(vec!["device", "ip", "via", "connected", "last activity"])
+                    .style(Style::new().bold().magenta()),
+            )
+            .block(container);
+
+        frame.render_widget(table, content_chunks[1]);
+    }
+
+    fn render_settings_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        // 上部に水平線を描画
+        let content_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1), // 水平線部分
+                Constraint::Min(0),    // 設定フォーム部分
+            ])
+            .split(area);
+
+        // 水平線を描画
+        let separator = Block::default().borders(ratatui::widgets::Borders::TOP);
+        frame.render_widget(separator, content_chunks[0]);
+
+        self.settings.render(frame, content_chunks[1]);
+    }
+
+    fn render_messages_content(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        // 上部に水平線を描画
+        let content_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1), // 水平線部分
+                Constraint::Min(0),    // メッセージ一覧部分
+            ])
+            .split(area);
+
+        // 水平線を描画
+        let separator = Block::default().borders(ratatui::widgets::Borders::TOP);
+        frame.render_widget(separator, content_chunks[0]);
+
+        self.messages.render(frame, content_chunks[1]);
+    }
+
+    fn render_stats_content(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        // 上部に水平線を描画
+        let content_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1), // 水平線部分
+                Constraint::Min(0),    // 集計部分
+            ])
+            .split(area);
+
+        // 水平線を描画
+        let separator = Block::default().borders(ratatui::widgets::Borders::TOP);
+        frame.render_widget(separator, content_chunks[0]);
+
+        self.stats.render(frame, content_chunks[1]);
+    }
+
+    // 設定の変更を保存する（結果は次のループで受け取る）
+    fn save_settings(&mut self, update: SettingsUpdate) {
+        if self.pending_save.is_some() {
+            return;
+        }
+        let (sender, receiver) = oneshot::channel();
+        self.pending_save = Some(receiver);
+
+        let server_manager = self.server_manager.clone();
+        tokio::spawn(async move {
+            let result = server_manager.update_settings(update).await;
+            if let Err(ref e) = result {
+                tracing::error!("Failed to save settings: {}", e);
+            }
+            let _ = sender.send(result.map_err(|e| e.to_string()));
+        });
+    }
+
+    // TUIから入力したメッセージを送信する（結果は次のループで受け取る）
+    fn send_message(&mut self, outgoing: OutgoingMessage) {
+        let (sender, receiver) = oneshot::channel();
+        self.pending_send = Some(receiver);
+
+        let server_manager = self.server_manager.clone();
+        tokio::spawn(async move {
+            let result = server_manager
+                .send_message(outgoing.message, outgoing.attachments)
+                .await;
+            if let Err(ref e) = result {
+                tracing::error!("Failed to send message: {}", e);
+            }
+            let _ = sender.send(result.map_err(|e| e.to_string()));
+        });
     }
 
-    /// Reads the crossterm events and updates the state of [`App`].
-    fn handle_crossterm_events(&mut self) -> Result<()> {
-        match event::read()? {
+    // 接続用URIを作る（結果は次のループで受け取る）
+    fn prepare_connect_uri(&mut self, pairing_code: bool) {
+        let (sender, receiver) = oneshot::channel();
+        self.pending_connect = Some(receiver);
+
+        let server_manager = self.server_manager.clone();
+        let ip = self.server_status.ip.clone().unwrap_or_default();
+        let port = self.server_status.port.unwrap_or_default();
+        tokio::spawn(async move {
+            let result = server_manager.connect_uri(ip, port, pairing_code).await;
+            let _ = sender.send(result.map_err(|e| e.to_string()));
+        });
+    }
+
+    /// Handles a crossterm event and returns whether the screen needs to be redrawn.
+    fn handle_crossterm_event(&mut self, event: Event) -> bool {
+        match event {
             // it's important to check KeyEventKind::Press to avoid handling key release events
-            Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key_event(key),
-            Event::Mouse(_) => {}
-            Event::Resize(_, _) => {}
-            _ => {}
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                self.on_key_event(key);
+                true
+            }
+            Event::Mouse(mouse) => self.on_mouse_event(mouse),
+            Event::Resize(_, _) => true,
+            _ => false,
         }
-        Ok(())
+    }
+
+    /// Handles the mouse events and returns whether anything changed.
+    fn on_mouse_event(&mut self, mouse: MouseEvent) -> bool {
+        // ポップアップや入力欄を開いている間はキー操作に任せる
+        if self.connect.is_open()
+            || (self.selected_tab == 0 && self.logs.is_editing())
+            || (self.selected_tab == 3 && self.settings.is_editing())
+            || (self.selected_tab == 4 && self.messages.is_editing())
+        {
+            return false;
+        }
+
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                match self.tab_at(Position::new(mouse.column, mouse.row)) {
+                    Some(tab) if tab != self.selected_tab => {
+                        self.selected_tab = tab;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            MouseEventKind::ScrollUp => self.scroll(-1),
+            MouseEventKind::ScrollDown => self.scroll(1),
+            _ => false,
+        }
+    }
+
+    // クリックした位置のタブ（Tabsと同じく前後1文字の余白と区切りを挟んで並べる）
+    fn tab_at(&self, position: Position) -> Option<usize> {
+        if !self.tabs_area.contains(position) {
+            return None;
+        }
+        let mut left = self.tabs_area.x;
+        for (index, title) in TAB_TITLES.iter().enumerate() {
+            let right = left + title.len() as u16 + 2;
+            if position.x < right {
+                return (position.x >= left).then_some(index);
+            }
+            left = right + TAB_DIVIDER.len() as u16;
+        }
+        None
+    }
+
+    // マウスホイールで選択中のタブの一覧をスクロールする（負の値で上へ）
+    fn scroll(&mut self, direction: isize) -> bool {
+        match self.selected_tab {
+            0 if direction < 0 => self.logs.scroll_up(WHEEL_LINES),
+            0 => self.logs.scroll_down(WHEEL_LINES),
+            4 => self
+                .messages
+                .move_selection(direction * WHEEL_LINES as isize),
+            _ => return false,
+        }
+        true
     }
 
     /// Handles the key events and updates the state of [`App`].
     fn on_key_event(&mut self, key: KeyEvent) {
+        // 接続用QRコードを表示している間は全てのキーをポップアップに渡す
+        if self.connect.is_open() {
+            if let Some(pairing_code) = self.connect.handle_key(key) {
+                self.prepare_connect_uri(pairing_code);
+            }
+            return;
+        }
+        // 設定の入力中は全てのキーをフォームに渡す
+        if self.selected_tab == 3 && self.settings.is_editing() {
+            if let Some(update) = self.settings.handle_key(key) {
+                self.save_settings(update);
+            }
+            return;
+        }
+        // ログの検索語などの入力中は全てのキーをログ欄に渡す
+        if self.selected_tab == 0 && self.logs.is_editing() {
+            self.logs.handle_key(key);
+            return;
+        }
+        // 検索語などの入力中は全てのキーを、詳細欄を開いている間はEscを渡す
+        if self.selected_tab == 4
+            && (self.messages.is_editing()
+                || (self.messages.is_detail_open() && key.code == KeyCode::Esc))
+        {
+            if let Some(outgoing) = self.messages.handle_key(key) {
+                self.send_message(outgoing);
+            }
+            return;
+        }
+
         match (key.modifiers, key.code) {
             (_, KeyCode::Esc | KeyCode::Char('q'))
             | (KeyModifiers::CONTROL, KeyCode::Char('c') | KeyCode::Char('C')) => self.quit(),
 
             // タブ切り替え
             (_, KeyCode::Left) => {
-                if self.selected_tab > 0 {
-                    self.selected_tab -= 1;
-                }
+                self.selected_tab = self.selected_tab.saturating_sub(1);
             }
             (_, KeyCode::Right) => {
-                if self.selected_tab < 1 {
-                    // 現在は2つのタブ（0, 1）
-                    self.selected_tab += 1;
-                }
+                // 現在は5つのタブ（0, 1, 2, 3, 4）
+                self.selected_tab = (self.selected_tab + 1).min(TAB_TITLES.len() - 1);
             }
 
             // 数字キーでの直接タブ選択
             (_, KeyCode::Char('1')) => self.selected_tab = 0,
             (_, KeyCode::Char('2')) => self.selected_tab = 1,
+            (_, KeyCode::Char('3')) => self.selected_tab = 2,
+            (_, KeyCode::Char('4')) => self.selected_tab = 3,
+            (_, KeyCode::Char('5')) => self.selected_tab = 4,
+            (_, KeyCode::Char('6')) => self.selected_tab = 5,
 
             // Controlタブでのサーバー操作
             (_, KeyCode::Char('s') | KeyCode::Char('S')) if self.selected_tab == 1 => {
                 if matches!(
                     self.server_status.state,
-                    ServerState::Stopped | ServerState::Error(_)
+                    ServerState::Stopped | ServerState::Aborted | ServerState::Error(_)
                 ) {
                     let server_manager = self.server_manager.clone();
                     tokio::spawn(async move {
                         if let Err(e) = server_manager.start_server().await {
-                            eprintln!("Failed to start server: {}", e);
+                            tracing::error!("Failed to start server: {}", e);
                         }
                     });
                 }
             }
             (_, KeyCode::Char('x') | KeyCode::Char('X')) if self.selected_tab == 1 => {
-                if matches!(self.server_status.state, ServerState::Running) {
+                if matches!(
+                    self.server_status.state,
+                    ServerState::Running | ServerState::Restarting { .. }
+                ) {
                     let server_manager = self.server_manager.clone();
                     tokio::spawn(async move {
                         if let Err(e) = server_manager.stop_server().await {
-                            eprintln!("Failed to stop server: {}", e);
+                            tracing::error!("Failed to stop server: {}", e);
                         }
                     });
                 }
             }
 
+            (_, KeyCode::Char('c') | KeyCode::Char('C')) if self.selected_tab == 1 => {
+                if matches!(self.server_status.state, ServerState::Running) {
+                    self.connect.open();
+                    self.prepare_connect_uri(false);
+                }
+            }
+
+            // Settingsタブでのフォーム操作
+            _ if self.selected_tab == 3 => {
+                if let Some(update) = self.settings.handle_key(key) {
+                    self.save_settings(update);
+                }
+            }
+
+            // Logsタブでのスクロールと絞り込み
+            _ if self.selected_tab == 0 => self.logs.handle_key(key),
+
+            // Messagesタブでの一覧操作
+            _ if self.selected_tab == 4 => {
+                if let Some(outgoing) = self.messages.handle_key(key) {
+                    self.send_message(outgoing);
+                }
+            }
+
             // その他のキーは無視
             _ => {}
         }
     }
 
-    /// Set running to false to quit the application.
+    /// Stop the server, then set running to false to quit the application.
+    // サーバーが動いている場合は、クライアントを切断し書き込みを終えてから終了する
+    // 停止を待っている間にもう一度押された場合はすぐに終了する
     fn quit(&mut self) {
-        self.running = false;
+        let stopped = matches!(
+            self.server_status.state,
+            ServerState::Stopped | ServerState::Aborted | ServerState::Error(_)
+        );
+        if stopped || self.pending_stop.is_some() {
+            self.running = false;
+            return;
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending_stop = Some(receiver);
+        let server_manager = self.server_manager.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server_manager.stop_server().await {
+                tracing::error!("Failed to stop server: {}", e);
+            }
+            let _ = sender.send(());
+        });
+    }
+}
+
+// 経過時間を「12s ago」のような短い表記にする
+fn format_elapsed(elapsed: chrono::Duration) -> String {
+    let seconds = elapsed.num_seconds().max(0);
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        _ => format!("{}h ago", seconds / 3600),
     }
 }
@@ -0,0 +1,280 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Padding, Paragraph},
+};
+use server::{ServerConfig, ServerSettings, SettingsUpdate};
+
+// 設定タブの項目（表示順）
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Nickname,
+    Password,
+    RevokeTokens,
+    ShowRequests,
+    ShowResponses,
+    QuietEndpoints,
+    Save,
+}
+
+const FIELDS: [Field; 7] = [
+    Field::Nickname,
+    Field::Password,
+    Field::RevokeTokens,
+    Field::ShowRequests,
+    Field::ShowResponses,
+    Field::QuietEndpoints,
+    Field::Save,
+];
+
+// 設定タブの入力内容
+#[derive(Debug)]
+pub struct SettingsForm {
+    saved: ServerSettings, // 最後に保存された設定（変更の有無の判定用）
+    nickname: String,
+    password: String, // 空の場合は変更しない
+    revoke_tokens: bool,
+    show_requests: bool,
+    show_responses: bool,
+    quiet_endpoints: String, // カンマ区切り
+    selected: usize,
+    editing: Option<String>,        // 編集中のテキスト
+    notice: Option<(String, bool)>, // 保存結果などの表示（trueはエラー）
+}
+
+impl SettingsForm {
+    pub fn new(config: &ServerConfig) -> Self {
+        let mut form = Self {
+            saved: config.settings(),
+            nickname: String::new(),
+            password: String::new(),
+            revoke_tokens: true,
+            show_requests: false,
+            show_responses: false,
+            quiet_endpoints: String::new(),
+            selected: 0,
+            editing: None,
+            notice: None,
+        };
+        form.reset();
+        form
+    }
+
+    // 保存された設定を反映する（未保存の変更がある場合は入力を残す）
+    pub fn load(&mut self, config: &ServerConfig) {
+        let dirty = self.is_dirty();
+        self.saved = config.settings();
+        if !dirty {
+            self.reset();
+        }
+    }
+
+    // 保存の結果を表示する（成功した場合は入力を保存後の設定に戻す）
+    pub fn finish_save(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.reset();
+                self.set_notice("Settings saved", false);
+            }
+            Err(e) => self.set_notice(format!("Failed to save: {}", e), true),
+        }
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editing.is_some()
+    }
+
+    pub fn set_notice(&mut self, notice: impl Into<String>, is_error: bool) {
+        self.notice = Some((notice.into(), is_error));
+    }
+
+    // キー入力を処理し、保存が要求された場合は変更内容を返す
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<SettingsUpdate> {
+        if let Some(buffer) = self.editing.as_mut() {
+            match key.code {
+                KeyCode::Char(c) => buffer.push(c),
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Enter => {
+                    let value = self.editing.take().unwrap_or_default();
+                    match FIELDS[self.selected] {
+                        Field::Nickname => self.nickname = value,
+                        Field::Password => self.password = value,
+                        Field::QuietEndpoints => self.quiet_endpoints = value,
+                        _ => {}
+                    }
+                }
+                KeyCode::Esc => self.editing = None,
+                _ => {}
+            }
+            return None;
+        }
+
+        match (key.modifiers, key.code) {
+            (KeyModifiers::CONTROL, KeyCode::Char('s')) => return self.submit(),
+            (_, KeyCode::Up | KeyCode::Char('k')) => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            (_, KeyCode::Down | KeyCode::Char('j')) => {
+                self.selected = (self.selected + 1).min(FIELDS.len() - 1);
+            }
+            (_, KeyCode::Enter | KeyCode::Char(' ')) => match FIELDS[self.selected] {
+                Field::Nickname => self.editing = Some(self.nickname.clone()),
+                Field::Password => self.editing = Some(String::new()),
+                Field::QuietEndpoints => self.editing = Some(self.quiet_endpoints.clone()),
+                Field::RevokeTokens => self.revoke_tokens = !self.revoke_tokens,
+                Field::ShowRequests => self.show_requests = !self.show_requests,
+                Field::ShowResponses => self.show_responses = !self.show_responses,
+                Field::Save => return self.submit(),
+            },
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),    // 項目
+                Constraint::Length(2), // 操作説明と保存結果
+            ])
+            .split(area);
+
+        let lines: Vec<Line> = FIELDS
+            .iter()
+            .enumerate()
+            .map(|(index, field)| self.render_field(*field, index == self.selected))
+            .collect();
+        let container = Block::new().padding(Padding::horizontal(1));
+        frame.render_widget(Paragraph::new(lines).block(container), chunks[0]);
+
+        let help = if self.is_editing() {
+            "Enter: confirm  Esc: cancel"
+        } else {
+            "↑/↓: select  Enter/Space: edit or toggle  Ctrl+S: save"
+        };
+        let mut footer = vec![Line::from(help).dark_gray()];
+        if let Some((notice, is_error)) = &self.notice {
+            let line = Line::from(notice.as_str());
+            footer.push(if *is_error { line.red() } else { line.green() });
+        }
+        let container = Block::new().padding(Padding::horizontal(1));
+        frame.render_widget(Paragraph::new(footer).block(container), chunks[1]);
+    }
+
+    fn render_field(&self, field: Field, selected: bool) -> Line<'static> {
+        let editing = selected && self.is_editing();
+        let (label, value) = match field {
+            Field::Nickname => ("nickname", self.text_value(&self.nickname, editing, false)),
+            Field::Password => ("password", self.text_value(&self.password, editing, true)),
+            Field::RevokeTokens => ("revoke tokens", checkbox(self.revoke_tokens)),
+            Field::ShowRequests => ("show requests", checkbox(self.show_requests)),
+            Field::ShowResponses => ("show responses", checkbox(self.show_responses)),
+            Field::QuietEndpoints => (
+                "quiet endpoints",
+                self.text_value(&self.quiet_endpoints, editing, false),
+            ),
+            Field::Save => {
+                let label = if self.is_dirty() {
+                    "[ Save * ]"
+                } else {
+                    "[ Save ]"
+                };
+                let style = if selected {
+                    Style::new().reversed().bold()
+                } else {
+                    Style::new().bold()
+                };
+                return Line::from(vec![Span::raw("  "), Span::styled(label, style)]);
+            }
+        };
+
+        let marker = if selected { "> " } else { "  " };
+        let label_style = if selected {
+            Style::new().yellow().bold()
+        } else {
+            Style::new().magenta()
+        };
+        let value_style = if editing {
+            Style::new().reversed()
+        } else {
+            Style::new()
+        };
+        Line::from(vec![
+            Span::raw(marker),
+            Span::styled(format!("{:<16}", label), label_style),
+            Span::styled(value, value_style),
+        ])
+    }
+
+    fn text_value(&self, value: &str, editing: bool, masked: bool) -> String {
+        let value = match (&self.editing, editing) {
+            (Some(buffer), true) => buffer.as_str(),
+            _ => value,
+        };
+        if masked {
+            if value.is_empty() && !editing {
+                return "(unchanged)".to_string();
+            }
+            return "*".repeat(value.chars().count());
+        }
+        value.to_string()
+    }
+
+    fn reset(&mut self) {
+        self.nickname = self.saved.nickname.clone();
+        self.password.clear();
+        self.show_requests = self.saved.show_requests;
+        self.show_responses = self.saved.show_responses;
+        self.quiet_endpoints = self.saved.quiet_endpoints.join(", ");
+    }
+
+    fn quiet_endpoint_list(&self) -> Vec<String> {
+        self.quiet_endpoints
+            .split(',')
+            .map(|endpoint| endpoint.trim().to_string())
+            .filter(|endpoint| !endpoint.is_empty())
+            .collect()
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.nickname != self.saved.nickname
+            || !self.password.is_empty()
+            || self.show_requests != self.saved.show_requests
+            || self.show_responses != self.saved.show_responses
+            || self.quiet_endpoint_list() != self.saved.quiet_endpoints
+    }
+
+    // 変更された項目だけを送る
+    fn submit(&mut self) -> Option<SettingsUpdate> {
+        if !self.is_dirty() {
+            self.set_notice("No changes to save", false);
+            return None;
+        }
+
+        let quiet_endpoints = self.quiet_endpoint_list();
+        let update = SettingsUpdate {
+            nickname: (self.nickname != self.saved.nickname).then(|| self.nickname.clone()),
+            password: (!self.password.is_empty()).then(|| self.password.clone()),
+            current_password: None, // TUIはサーバーを動かしている本人なので確認しない
+            revoke_tokens: self.revoke_tokens,
+            show_requests: (self.show_requests != self.saved.show_requests)
+                .then_some(self.show_requests),
+            show_responses: (self.show_responses != self.saved.show_responses)
+                .then_some(self.show_responses),
+            quiet_endpoints: (quiet_endpoints != self.saved.quiet_endpoints)
+                .then_some(quiet_endpoints),
+        };
+        self.set_notice("Saving...", false);
+        Some(update)
+    }
+}
+
+fn checkbox(checked: bool) -> String {
+    if checked { "[x]" } else { "[ ]" }.to_string()
+}
@@ -0,0 +1,269 @@
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Gauge, Paragraph, Row, Sparkline, Table},
+};
+use server::{
+    ServerConfig,
+    error::ServerResult,
+    message_store::{MessageStore, StorageStats},
+    metrics::{Metrics, MetricsSnapshot},
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use super::messages::format_size;
+
+// ストレージの使用量を数え直す間隔（表示ほど頻繁に変わるものではないため毎秒は行わない）
+const STORAGE_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Statsタブ（サーバーの稼働状況）
+#[derive(Debug)]
+pub struct StatsView {
+    metrics: Arc<Metrics>,
+    store: Arc<MessageStore>,
+    snapshot: MetricsSnapshot,
+    storage: Option<StorageStats>,
+    storage_limit: Option<u64>, // 保持ポリシーの合計サイズ上限（バイト）
+    storage_error: Option<String>,
+    storage_refreshed_at: Option<Instant>,
+    pending_storage: Option<oneshot::Receiver<ServerResult<StorageStats>>>,
+}
+
+impl StatsView {
+    pub fn new(metrics: Arc<Metrics>, store: Arc<MessageStore>, config: &ServerConfig) -> Self {
+        let mut view = Self {
+            metrics,
+            store,
+            snapshot: MetricsSnapshot::default(),
+            storage: None,
+            storage_limit: None,
+            storage_error: None,
+            storage_refreshed_at: None,
+            pending_storage: None,
+        };
+        view.load(config);
+        view
+    }
+
+    pub fn load(&mut self, config: &ServerConfig) {
+        self.storage_limit = config
+            .retention
+            .max_total_size_mb
+            .map(|mb| mb * 1024 * 1024);
+    }
+
+    // 集計を取り直す（表示中だけ呼ばれる）
+    pub fn refresh(&mut self) {
+        self.snapshot = self.metrics.snapshot();
+
+        let stale = self
+            .storage_refreshed_at
+            .is_none_or(|time| time.elapsed() >= STORAGE_REFRESH_INTERVAL);
+        if stale && self.pending_storage.is_none() {
+            let (sender, receiver) = oneshot::channel();
+            self.pending_storage = Some(receiver);
+            self.storage_refreshed_at = Some(Instant::now());
+
+            let store = self.store.clone();
+            tokio::spawn(async move {
+                let _ = sender.send(store.storage_stats().await);
+            });
+        }
+    }
+
+    // ストレージの使用量を受け取る（表示が変わった場合はtrue）
+    pub fn poll(&mut self) -> bool {
+        let Some(receiver) = self.pending_storage.as_mut() else {
+            return false;
+        };
+        match receiver.try_recv() {
+            Ok(result) => {
+                self.pending_storage = None;
+                match result {
+                    Ok(storage) => {
+                        self.storage = Some(storage);
+                        self.storage_error = None;
+                    }
+                    Err(e) => self.storage_error = Some(e.to_string()),
+                }
+                true
+            }
+            Err(oneshot::error::TryRecvError::Empty) => false,
+            Err(oneshot::error::TryRecvError::Closed) => {
+                self.pending_storage = None;
+                false
+            }
+        }
+    }
+
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        let [summary, gauges, requests, messages, endpoints] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Length(3),
+            Constraint::Length(5),
+            Constraint::Length(5),
+            Constraint::Min(3),
+        ])
+        .areas(area);
+
+        self.render_summary(frame, summary);
+        self.render_gauges(frame, gauges);
+        self.render_requests(frame, requests);
+        self.render_messages(frame, messages);
+        self.render_endpoints(frame, endpoints);
+    }
+
+    fn render_summary(&self, frame: &mut Frame, area: Rect) {
+        let snapshot = &self.snapshot;
+        let uptime = snapshot.uptime.as_secs();
+        let storage = match (&self.storage, &self.storage_error) {
+            (_, Some(error)) => format!("storage unavailable: {}", error),
+            (Some(storage), None) => format!(
+                "database {}  attachments {} ({} files)",
+                format_size(storage.database_bytes),
+                format_size(storage.attachment_bytes),
+                storage.attachment_count
+            ),
+            (None, None) => "storage: loading...".to_string(),
+        };
+
+        let lines = vec![
+            Line::from(format!(
+                "Uptime {}:{:02}:{:02}  SSE subscribers {}  Requests (last 60s) {}",
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60,
+                snapshot.sse_subscribers,
+                snapshot.requests_per_second.iter().sum::<u64>()
+            )),
+            Line::from(storage).dark_gray(),
+        ];
+        frame.render_widget(Paragraph::new(lines), area);
+    }
+
+    fn render_gauges(&self, frame: &mut Frame, area: Rect) {
+        let [requests, storage] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(area);
+
+        // 直前の1秒のリクエスト数を、直近60秒の最大値に対する割合で表示する
+        let current = self
+            .snapshot
+            .requests_per_second
+            .last()
+            .copied()
+            .unwrap_or(0);
+        let peak = self
+            .snapshot
+            .requests_per_second
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0);
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(" Requests/s vs peak "))
+            .gauge_style(Style::new().fg(Color::Cyan))
+            .ratio(ratio(current, peak))
+            .label(format!("{} / {}", current, peak));
+        frame.render_widget(gauge, requests);
+
+        // 合計サイズの上限がある場合はそれに対する割合、なければデータベースに占める添付ファイルの割合
+        let storage_stats = self.storage.unwrap_or_default();
+        let (title, used, total) = match self.storage_limit {
+            Some(limit) => (
+                " Database vs retention limit ",
+                storage_stats.database_bytes,
+                limit,
+            ),
+            None => (
+                " Attachments in database ",
+                storage_stats.attachment_bytes,
+                storage_stats.database_bytes,
+            ),
+        };
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(title))
+            .gauge_style(Style::new().fg(Color::Green))
+            .ratio(ratio(used, total))
+            .label(format!("{} / {}", format_size(used), format_size(total)));
+        frame.render_widget(gauge, storage);
+    }
+
+    fn render_requests(&self, frame: &mut Frame, area: Rect) {
+        let sparkline = Sparkline::default()
+            .block(Block::bordered().title(" Requests per second (60s) "))
+            .data(&self.snapshot.requests_per_second)
+            .style(Style::new().fg(Color::Cyan));
+        frame.render_widget(sparkline, area);
+    }
+
+    fn render_messages(&self, frame: &mut Frame, area: Rect) {
+        let [messages, bytes] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(area);
+        let snapshot = &self.snapshot;
+
+        let sparkline = Sparkline::default()
+            .block(Block::bordered().title(format!(
+                " Messages per minute (now {}) ",
+                snapshot.messages_per_minute.last().copied().unwrap_or(0)
+            )))
+            .data(&snapshot.messages_per_minute)
+            .style(Style::new().fg(Color::Yellow));
+        frame.render_widget(sparkline, messages);
+
+        let sparkline = Sparkline::default()
+            .block(Block::bordered().title(format!(
+                " Bytes per minute (now {}) ",
+                format_size(snapshot.bytes_per_minute.last().copied().unwrap_or(0))
+            )))
+            .data(&snapshot.bytes_per_minute)
+            .style(Style::new().fg(Color::Magenta));
+        frame.render_widget(sparkline, bytes);
+    }
+
+    fn render_endpoints(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Endpoints ");
+        if self.snapshot.endpoints.is_empty() {
+            frame.render_widget(
+                Paragraph::new("No requests yet").dark_gray().block(block),
+                area,
+            );
+            return;
+        }
+
+        let header =
+            Row::new(vec!["Endpoint", "Requests", "p50", "p95"]).style(Style::new().bold());
+        let rows = self.snapshot.endpoints.iter().map(|endpoint| {
+            Row::new(vec![
+                endpoint.endpoint.clone(),
+                endpoint.requests.to_string(),
+                format!("{:.1} ms", endpoint.p50_ms),
+                format!("{:.1} ms", endpoint.p95_ms),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(20),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(header)
+        .block(block);
+        frame.render_widget(table, area);
+    }
+}
+
+fn ratio(value: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (value as f64 / total as f64).clamp(0.0, 1.0)
+}
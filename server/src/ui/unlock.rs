@@ -0,0 +1,106 @@
+use color_eyre::eyre::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::{Constraint, Flex, Layout},
+    style::Stylize,
+    text::Line,
+    widgets::{Block, Padding, Paragraph},
+};
+use server::{
+    encryption::{self, EncryptionInfo},
+    error::ServerError,
+};
+
+// 暗号化されたメッセージのパスフレーズを入力させる（解錠できればtrue、Escで諦めればfalse）
+pub async fn unlock(terminal: &mut DefaultTerminal, info: &EncryptionInfo) -> Result<bool> {
+    let mut prompt = UnlockPrompt::default();
+    let mut events = EventStream::new();
+
+    loop {
+        terminal.draw(|frame| prompt.render(frame))?;
+
+        let key = match events.next().await {
+            Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => key,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(false),
+        };
+
+        match (key.modifiers, key.code) {
+            (_, KeyCode::Esc) | (KeyModifiers::CONTROL, KeyCode::Char('c')) => return Ok(false),
+            (_, KeyCode::Enter) if !prompt.passphrase.is_empty() => {
+                prompt.checking = true;
+                terminal.draw(|frame| prompt.render(frame))?;
+
+                // 鍵の導出には時間がかかるため、ランタイムの外で実行する
+                let info = info.clone();
+                let passphrase = std::mem::take(&mut prompt.passphrase);
+                let result = tokio::task::spawn_blocking(move || {
+                    encryption::unlock_with_passphrase(&info, &passphrase)
+                })
+                .await?;
+
+                prompt.checking = false;
+                match result {
+                    Ok(()) => return Ok(true),
+                    Err(ServerError::InvalidKey) => {
+                        prompt.error = Some("Wrong passphrase".to_string());
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            (_, KeyCode::Backspace) => {
+                prompt.passphrase.pop();
+            }
+            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
+                prompt.passphrase.push(c);
+                prompt.error = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct UnlockPrompt {
+    passphrase: String,
+    error: Option<String>,
+    checking: bool,
+}
+
+impl UnlockPrompt {
+    fn render(&self, frame: &mut Frame) {
+        let [area] = Layout::vertical([Constraint::Length(8)])
+            .flex(Flex::Center)
+            .areas(frame.area());
+        let [area] = Layout::horizontal([Constraint::Length(56)])
+            .flex(Flex::Center)
+            .areas(area);
+
+        let block = Block::bordered()
+            .title(Line::from(" Sure-Shot Server ").bold())
+            .padding(Padding::horizontal(1));
+
+        let mut lines = vec![
+            Line::from("Stored messages are encrypted."),
+            Line::from(""),
+            Line::from(vec![
+                "Passphrase: ".magenta(),
+                "*".repeat(self.passphrase.chars().count()).into(),
+                "_".slow_blink(),
+            ]),
+        ];
+        if self.checking {
+            lines.push(Line::from("Unlocking...").yellow());
+        } else if let Some(ref error) = self.error {
+            lines.push(Line::from(error.as_str()).red());
+        } else {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from("Enter: unlock  Esc: quit").dark_gray());
+
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}